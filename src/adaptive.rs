@@ -0,0 +1,73 @@
+/*!
+ * Adaptive polling, which samples faster while selected metrics are changing quickly and backs off while they're flat,
+ * so long soak captures stay small without missing the interesting parts.
+ */
+
+use std::{collections::HashMap, time::Duration};
+
+use serde_json::{Map, Value};
+
+use crate::groups::generic::get_root_elem;
+
+/// The change between two samples, relative to the previous value, above which a metric is changing quickly.
+/// Values smaller than 1 (like fill percentages) are compared by their absolute change instead.
+const CHANGE_THRESHOLD: f64 = 0.05;
+/// How much the interval shrinks after a sample where a metric changed quickly
+const SPEED_UP: f64 = 0.5;
+/// How much the interval grows after a sample where everything was flat
+const SLOW_DOWN: f64 = 1.5;
+
+/// Tracks the selected metrics, and picks the interval before the next sample
+pub struct Adaptive {
+    keys: Vec<String>,
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    prev: HashMap<String, f64>,
+}
+
+impl Adaptive {
+    /// Adapt to how quickly the metrics at `keys` change, starting at the `start` interval and staying between `min` and `max`
+    pub fn new(keys: Vec<String>, start: Duration, min: Duration, max: Duration) -> Adaptive {
+        Adaptive { keys, min, max, current: start.clamp(min, max), prev: HashMap::new() }
+    }
+
+    /// Look at a new sample, and return how long to wait before taking the next one
+    pub fn next(&mut self, doc: &Map<String, Value>) -> Duration {
+        let mut changing = false;
+        for key in &self.keys {
+            let Some(val) = get_root_elem(doc, key).and_then(Value::as_f64) else {
+                continue;
+            };
+            if let Some(prev) = self.prev.insert(key.clone(), val) {
+                changing |= (val - prev).abs() / prev.abs().max(1.0) > CHANGE_THRESHOLD;
+            }
+        }
+
+        let next = self.current.mul_f64(if changing { SPEED_UP } else { SLOW_DOWN });
+        self.current = next.clamp(self.min, self.max);
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::Adaptive;
+
+    #[test]
+    fn test_adaptive() {
+        let doc = |filled: f64| json!({"queue": {"filled": filled}}).as_object().unwrap().clone();
+        let mut adaptive = Adaptive::new(vec!["queue.filled".to_string()], Duration::from_secs(4), Duration::from_secs(1), Duration::from_secs(10));
+
+        // nothing to compare the first sample against
+        assert_eq!(adaptive.next(&doc(100.0)), Duration::from_secs(6));
+        assert_eq!(adaptive.next(&doc(200.0)), Duration::from_secs(3));
+        assert_eq!(adaptive.next(&doc(400.0)), Duration::from_millis(1500));
+        assert_eq!(adaptive.next(&doc(800.0)), Duration::from_secs(1));
+        assert_eq!(adaptive.next(&doc(801.0)), Duration::from_millis(1500));
+    }
+}
@@ -0,0 +1,78 @@
+/*!
+ * Discovers the beats run by an Elastic Agent. Each component the agent runs is its own beat process with its own monitoring endpoint,
+ * and the agent's monitoring server (`agent.monitoring.http`) lists them under `/processes` and proxies their stats
+ * under `/processes/<component id>/stats`.
+ */
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use beatperf::fetch::Client;
+
+/// The response of the agent's `/processes` endpoint
+#[derive(Debug, Deserialize)]
+struct Processes {
+    processes: Vec<Process>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Process {
+    id: String,
+    #[serde(default)]
+    binary: String,
+}
+
+/// A single beat run by the agent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+    /// The component ID, like `filestream-default`
+    pub id: String,
+    /// The stats URL of the component, proxied through the agent
+    pub url: String,
+}
+
+/// List the components of the agent whose monitoring server is at `base`, like `http://localhost:6791`.
+/// Components whose stats can't be fetched, like ones that aren't beats, are skipped.
+pub async fn discover(client: &Client, base: &str) -> anyhow::Result<Vec<Component>> {
+    let base = base.trim_end_matches('/');
+    let url = format!("{}/processes", base);
+    let (body, _) = client.get(&url).await
+    .with_context(|| format!("error listing the agent's components from {}. Is agent.monitoring.http enabled?", url))?;
+    let listing: Processes = serde_json::from_str(&body).with_context(|| format!("unexpected response from {}", url))?;
+
+    let mut components = Vec::new();
+    for process in listing.processes {
+        let component = Component { url: component_url(base, &process.id), id: process.id };
+        match client.get(&component.url).await {
+            Ok(_) => {
+                info!("found component {} ({})", component.id, process.binary);
+                components.push(component);
+            }
+            Err(e) => warn!("skipping component {}, its stats can't be fetched: {:#}", component.id, e),
+        }
+    }
+
+    Ok(components)
+}
+
+/// The stats URL of a component, as proxied by the agent
+fn component_url(base: &str, id: &str) -> String {
+    // some component IDs, like those of the agent's own monitoring, contain a slash
+    format!("{}/processes/{}/stats", base, id.replace('/', "%2F"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{component_url, Processes};
+
+    #[test]
+    fn test_processes() {
+        let listing: Processes = serde_json::from_str(r#"{"processes": [
+            {"id": "filestream-default", "pid": "1234", "binary": "filebeat", "source": {"kind": "configured", "outputs": ["default"]}},
+            {"id": "beat/metrics-monitoring", "binary": "metricbeat"}
+        ]}"#).unwrap();
+        let urls: Vec<String> = listing.processes.iter().map(|p| component_url("http://localhost:6791", &p.id)).collect();
+        assert_eq!(urls, ["http://localhost:6791/processes/filestream-default/stats", "http://localhost:6791/processes/beat%2Fmetrics-monitoring/stats"]);
+    }
+}
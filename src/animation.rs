@@ -0,0 +1,149 @@
+/*!
+ * Assembles a series of rendered chart frames into a single animated SVG,
+ * so the evolution of a capture can be played back in a browser or presentation.
+ * Frames are spooled to disk as they're rendered, so a long run doesn't hold every one of them in memory.
+ */
+
+use std::{fs::{self, File}, io::{self, BufReader, BufWriter, Read, Write}};
+
+use crate::groups::SVG_SIZE;
+
+/// How long each frame is displayed, in seconds
+const FRAME_DURATION_SECS: f64 = 0.5;
+
+/// The frames of an animation, spooled to a file next to where the animation is written until it's finished.
+/// The spool is removed when this is dropped.
+pub struct Frames {
+    spool: String,
+    file: Option<BufWriter<File>>,
+    /// The length of each frame in the spool, in order
+    lengths: Vec<usize>,
+}
+
+impl Frames {
+    /// Spool frames to `spool`, which isn't created until the first frame is added
+    pub fn new(spool: String) -> Frames {
+        Frames { spool, file: None, lengths: Vec::new() }
+    }
+
+    pub fn push(&mut self, frame: &str) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(BufWriter::new(File::create(&self.spool)?)),
+        };
+        file.write_all(frame.as_bytes())?;
+        self.lengths.push(frame.len());
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lengths.is_empty()
+    }
+
+    /// Write every frame so far as an animated SVG to `path`, reading them back from the spool one at a time
+    pub fn write(&mut self, path: &str) -> io::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        let mut spool = BufReader::new(File::open(&self.spool)?);
+        let frames = self.lengths.iter().map(|len| {
+            let mut frame = vec![0; *len];
+            spool.read_exact(&mut frame)?;
+            String::from_utf8(frame).map_err(io::Error::other)
+        });
+        let mut out = BufWriter::new(File::create(path)?);
+        write_svg(&mut out, self.lengths.len(), frames)?;
+        out.flush()
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.spool);
+        }
+    }
+}
+
+/// Stitch together `total` standalone SVG documents into one looping animated SVG.
+/// Each frame is wrapped in a group that is only visible during its slice of the animation.
+pub fn write_svg(out: &mut impl Write, total: usize, frames: impl Iterator<Item = io::Result<String>>) -> io::Result<()> {
+    let duration = FRAME_DURATION_SECS * total as f64;
+
+    writeln!(
+        out,
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        w = SVG_SIZE.0,
+        h = SVG_SIZE.1
+    )?;
+
+    for (idx, frame) in frames.enumerate() {
+        let (values, key_times) = frame_keys(idx, total);
+        writeln!(
+            out,
+            "<g visibility=\"hidden\"><animate attributeName=\"visibility\" values=\"{}\" keyTimes=\"{}\" dur=\"{}s\" calcMode=\"discrete\" repeatCount=\"indefinite\"/>\n{}</g>",
+            values, key_times, duration, frame?
+        )?;
+    }
+    writeln!(out, "</svg>")
+}
+
+/// Build the visibility values and keyTimes for a frame at `idx` out of `total` frames
+fn frame_keys(idx: usize, total: usize) -> (String, String) {
+    let start = idx as f64 / total as f64;
+    let end = (idx + 1) as f64 / total as f64;
+
+    if total == 1 {
+        ("visible".to_string(), "0".to_string())
+    } else if idx == 0 {
+        ("visible;hidden".to_string(), format!("0;{}", end))
+    } else if idx == total - 1 {
+        ("hidden;visible".to_string(), format!("0;{}", start))
+    } else {
+        ("hidden;visible;hidden".to_string(), format!("0;{};{}", start, end))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{frame_keys, write_svg, Frames};
+
+    #[test]
+    fn test_frame_keys() {
+        assert_eq!(frame_keys(0, 4), ("visible;hidden".to_string(), "0;0.25".to_string()));
+        assert_eq!(frame_keys(1, 4), ("hidden;visible;hidden".to_string(), "0;0.25;0.5".to_string()));
+        assert_eq!(frame_keys(3, 4), ("hidden;visible".to_string(), "0;0.75".to_string()));
+        assert_eq!(frame_keys(0, 1), ("visible".to_string(), "0".to_string()));
+    }
+
+    #[test]
+    fn test_assemble() {
+        let mut out = Vec::new();
+        write_svg(&mut out, 2, ["<svg>a</svg>", "<svg>b</svg>"].into_iter().map(|frame| Ok(frame.to_string()))).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("<animate ").count(), 2);
+        assert!(out.contains("dur=\"1s\""));
+        assert!(out.ends_with("</svg>\n"));
+    }
+
+    #[test]
+    fn test_spooled_frames() {
+        let dir = std::env::temp_dir();
+        let spool = dir.join(format!("beatperf-frames-{}", std::process::id())).to_string_lossy().into_owned();
+        let path = dir.join(format!("beatperf-animated-{}.svg", std::process::id()));
+        let mut frames = Frames::new(spool.clone());
+        for frame in ["<svg>a</svg>", "<svg>bb</svg>", "<svg>ccc</svg>"] {
+            frames.push(frame).unwrap();
+        }
+        frames.write(path.to_str().unwrap()).unwrap();
+        let out = std::fs::read_to_string(&path).unwrap();
+        assert!(out.contains("\n<svg>bb</svg></g>") && out.contains("\n<svg>ccc</svg></g>"));
+        drop(frames);
+        assert!(!std::path::Path::new(&spool).exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+/*!
+ * Reads a beat's own YAML config, so a capture can be configured from what the beat is set up to do.
+ * Beat config keys can be written nested or in dot-notation (`queue.mem.events: 4096`), so the config is flattened before any lookups.
+ */
+
+use std::{collections::BTreeMap, fs};
+
+use anyhow::Context;
+use serde_yaml::Value;
+
+use beatperf::groups::annotations::RefLine;
+
+/// The filebeat input types that report their own metrics on the `/inputs/` endpoint, for the per_input group
+const PER_INPUT_TYPES: [&str; 14] = [
+    "aws-cloudwatch", "aws-s3", "azure-eventhub", "cel", "filestream", "gcp-pubsub", "http_endpoint", "httpjson", "journald", "kafka",
+    "netflow", "tcp", "udp", "winlog",
+];
+
+/// The parts of a beat config that matter to beatperf
+#[derive(Debug, Default, PartialEq)]
+pub struct BeatConfig {
+    /// The configured size of the memory queue, in events
+    pub queue_events: Option<u64>,
+    /// The enabled output type, like `elasticsearch` or `logstash`
+    pub output: Option<String>,
+    /// The types of all the enabled inputs
+    pub inputs: Vec<String>,
+    /// The names of all the enabled filebeat modules, which run inputs of their own
+    pub filebeat_modules: Vec<String>,
+    /// The names of all the enabled modules of other beats, like auditbeat's
+    pub modules: Vec<String>,
+    /// The types of all the enabled packetbeat protocols
+    pub protocols: Vec<String>,
+    /// The types of all the enabled heartbeat monitors
+    pub monitors: Vec<String>,
+    /// The names of the global processors
+    pub processors: Vec<String>,
+}
+
+impl BeatConfig {
+    /// Read and parse a beat config file
+    pub fn load(path: &str) -> anyhow::Result<BeatConfig> {
+        let raw = fs::read_to_string(path).with_context(|| format!("error reading beat config {}", path))?;
+        let root: Value = serde_yaml::from_str(&raw).with_context(|| format!("error parsing beat config {}", path))?;
+        Ok(Self::from_value(&root))
+    }
+
+    fn from_value(root: &Value) -> BeatConfig {
+        let mut flat = BTreeMap::new();
+        flatten_yaml(root, "", &mut flat);
+
+        let queue_events = flat.get("queue.mem.events").and_then(Value::as_u64);
+
+        // the output type is the first segment after `output.`, skipping any outputs that are explicitly disabled
+        let output = flat.keys()
+        .filter_map(|key| key.strip_prefix("output.")?.split('.').next())
+        .find(|out| flat.get(&format!("output.{}.enabled", out)).and_then(Value::as_bool) != Some(false))
+        .map(str::to_string);
+
+        let mut inputs = Vec::new();
+        let mut filebeat_modules = Vec::new();
+        let mut modules = Vec::new();
+        for (key, val) in &flat {
+            if key.ends_with(".inputs") {
+                inputs.extend(enabled_names(val, "type"));
+            } else if key == "filebeat.modules" {
+                filebeat_modules.extend(enabled_names(val, "module"));
+            } else if key.ends_with(".modules") {
+                modules.extend(enabled_names(val, "module"));
+            }
+        }
+        let protocols = flat.get("packetbeat.protocols").map(|val| enabled_names(val, "type")).unwrap_or_default();
+        let monitors = flat.get("heartbeat.monitors").map(|val| enabled_names(val, "type")).unwrap_or_default();
+
+        // processors are a list of single-key maps, like `- add_host_metadata: {}`
+        let processors = match flat.get("processors") {
+            Some(Value::Sequence(procs)) => procs.iter()
+            .filter_map(|proc| proc.as_mapping()?.keys().next()?.as_str().map(str::to_string))
+            .collect(),
+            _ => Vec::new(),
+        };
+
+        BeatConfig { queue_events, output, inputs, filebeat_modules, modules, protocols, monitors, processors }
+    }
+
+    /// The metric groups that are relevant to this config
+    pub fn groups(&self) -> Vec<&'static str> {
+        let mut groups = vec!["pipeline"];
+        if self.output.is_some() {
+            groups.push("output");
+        }
+        if self.output.as_deref() == Some("elasticsearch") {
+            groups.push("es_output");
+        }
+        if !self.inputs.is_empty() || !self.filebeat_modules.is_empty() {
+            groups.push("inputs");
+        }
+        if self.inputs.iter().any(|input| PER_INPUT_TYPES.contains(&input.as_str())) {
+            groups.push("per_input");
+        }
+        if self.processors.iter().any(|proc| proc == "add_kubernetes_metadata" || proc == "add_docker_metadata") {
+            groups.push("metadata");
+        }
+        if self.processors.iter().any(|proc| proc == "add_session_metadata") {
+            groups.push("processdb");
+            groups.push("kernel_tracing");
+        }
+        if self.modules.iter().any(|module| module == "auditd") {
+            groups.push("auditbeat");
+        }
+        if !self.protocols.is_empty() {
+            groups.push("packetbeat");
+        }
+        if !self.monitors.is_empty() {
+            groups.push("heartbeat");
+        }
+        groups
+    }
+
+    /// Reference lines for any configured limits
+    pub fn hlines(&self) -> Vec<RefLine> {
+        let mut lines = Vec::new();
+        if let Some(events) = self.queue_events {
+            lines.push(RefLine {
+                group: "pipeline".to_string(),
+                panel: Some("queue".to_string()),
+                value: events as f64,
+                label: Some("queue.mem.events".to_string()),
+            });
+        }
+        lines
+    }
+}
+
+/// Flatten nested mappings into dot-notation keys. Sequences and scalars are leaves.
+fn flatten_yaml(val: &Value, prefix: &str, acc: &mut BTreeMap<String, Value>) {
+    match val {
+        Value::Mapping(map) => {
+            for (key, inner) in map {
+                let Some(key) = key.as_str() else {
+                    continue;
+                };
+                let full = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+                flatten_yaml(inner, &full, acc);
+            }
+        }
+        _ => {
+            acc.insert(prefix.to_string(), val.clone());
+        }
+    }
+}
+
+/// Get the value of `field` from every entry in a list of inputs or modules that isn't disabled
+fn enabled_names(list: &Value, field: &str) -> Vec<String> {
+    let Some(entries) = list.as_sequence() else {
+        return Vec::new();
+    };
+    entries.iter()
+    .filter(|entry| entry.get("enabled").and_then(Value::as_bool) != Some(false))
+    .filter_map(|entry| entry.get(field)?.as_str().map(str::to_string))
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::BeatConfig;
+
+    #[test]
+    fn test_parse_config() -> anyhow::Result<()> {
+        let raw = r#"
+filebeat.inputs:
+  - type: filestream
+    id: logs
+    paths: ["/var/log/*.log"]
+  - type: journald
+    enabled: false
+filebeat.modules:
+  - module: nginx
+queue.mem:
+  events: 4096
+output.logstash:
+  hosts: ["localhost:5044"]
+output.elasticsearch:
+  enabled: false
+processors:
+  - add_host_metadata: ~
+  - add_kubernetes_metadata: ~
+  - add_session_metadata:
+      backend: auto
+"#;
+        let cfg = BeatConfig::from_value(&serde_yaml::from_str(raw)?);
+        assert_eq!(cfg, BeatConfig {
+            queue_events: Some(4096),
+            output: Some("logstash".to_string()),
+            inputs: vec!["filestream".to_string()],
+            filebeat_modules: vec!["nginx".to_string()],
+            modules: Vec::new(),
+            protocols: Vec::new(),
+            monitors: Vec::new(),
+            processors: vec!["add_host_metadata".to_string(), "add_kubernetes_metadata".to_string(), "add_session_metadata".to_string()],
+        });
+        assert_eq!(cfg.groups(), vec!["pipeline", "output", "inputs", "per_input", "metadata", "processdb", "kernel_tracing"]);
+
+        // modules run inputs too, but the old log input doesn't report per-input metrics
+        let cfg = BeatConfig::from_value(&serde_yaml::from_str("filebeat.modules:\n  - module: system\nfilebeat.inputs:\n  - type: log\n")?);
+        assert_eq!(cfg.groups(), vec!["pipeline", "inputs"]);
+
+        let cfg = BeatConfig::from_value(&serde_yaml::from_str("auditbeat.modules:\n  - module: auditd\n  - module: file_integrity\n")?);
+        assert_eq!(cfg.groups(), vec!["pipeline", "auditbeat"]);
+
+        let cfg = BeatConfig::from_value(&serde_yaml::from_str("packetbeat.protocols:\n  - type: http\n    ports: [80]\n  - type: dns\n    enabled: false\n")?);
+        assert_eq!(cfg.protocols, vec!["http"]);
+        assert_eq!(cfg.groups(), vec!["pipeline", "packetbeat"]);
+
+        let cfg = BeatConfig::from_value(&serde_yaml::from_str("heartbeat.monitors:\n  - type: http\n    urls: [\"http://localhost:9200\"]\n")?);
+        assert_eq!(cfg.groups(), vec!["pipeline", "heartbeat"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,574 @@
+/*!
+ * Reading and writing ndjson captures. Captures whose name ends in `.gz` are gzip-compressed, as a multi-day capture of
+ * full stats documents gets huge as plain text. A long capture can also be rotated, rolling over from `out.ndjson` to
+ * `out.1.ndjson`, `out.2.ndjson` and so on, and a capture can be read along with the rest of its set after it. A rotated
+ * capture can prune its oldest files as it goes, so one left running for days stays within a bounded amount of disk.
+ *
+ * Each line is an envelope around one sample: the response exactly as the beat returned it under `stats`, when it was
+ * taken under `@timestamp`, and beatperf's own measurements under `beatperf`, tagged with the envelope's format version.
+ * Lines without an envelope, like plain `/stats` dumps and captures from before it, are read as the stats document they are.
+ */
+
+use std::{fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Read, Write}, path::Path, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant, SystemTime}};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{expvar, fetch::META_KEY};
+
+/// The path that reads a capture from stdin
+pub const STDIN: &str = "-";
+
+/// How often a followed capture is checked for new lines
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+/// How many lines of stdin are read ahead of the replay
+const STDIN_BUFFER: usize = 64;
+
+/// Where a capture is written
+pub type CaptureWriter = Box<dyn Write + Send>;
+
+/// The key holding the version of the envelope a capture line is wrapped in
+pub const VERSION_KEY: &str = "beatperf_capture";
+/// The envelope version this beatperf writes, and the newest it can read
+pub const VERSION: u64 = 1;
+
+/// Write a sample to a capture as a line wrapped in an envelope. `stats` is the beat's response as it was returned, and `meta`
+/// is what [`crate::fetch::add_meta`] and friends tagged the sample with. Both are written as they are, without copying them.
+pub fn write_envelope(out: &mut dyn Write, stats: &Map<String, Value>, meta: &Value) -> anyhow::Result<()> {
+    let timestamp = meta.get("timestamp_ms").and_then(Value::as_i64).and_then(DateTime::<Utc>::from_timestamp_millis)
+    .map(|time| time.to_rfc3339_opts(SecondsFormat::Millis, true));
+    write!(out, r#"{{"{}":{},"@timestamp":"#, VERSION_KEY, VERSION)?;
+    serde_json::to_writer(&mut *out, &timestamp)?;
+    write!(out, r#","{}":"#, META_KEY)?;
+    serde_json::to_writer(&mut *out, meta)?;
+    write!(out, r#","stats":"#)?;
+    serde_json::to_writer(&mut *out, stats)?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Parse a line of a capture into the stats document it holds, with beatperf's measurements under `beatperf` as they were
+/// when it was collected. A line without an envelope is taken as the stats document itself.
+pub fn parse_line(line: &str) -> anyhow::Result<Map<String, Value>> {
+    let mut doc: Map<String, Value> = serde_json::from_str(line)?;
+    let Some(version) = doc.remove(VERSION_KEY) else {
+        return Ok(doc);
+    };
+    match version.as_u64() {
+        Some(version) if version <= VERSION => {}
+        _ => return Err(anyhow!("capture format {} is newer than this beatperf can read, expected {} or older", version, VERSION)),
+    }
+
+    let mut stats = match doc.remove("stats") {
+        Some(Value::Object(stats)) => stats,
+        _ => return Err(anyhow!("capture line has no stats document")),
+    };
+    let mut meta = match doc.remove(META_KEY) {
+        Some(Value::Object(meta)) => meta,
+        _ => Map::new(),
+    };
+    if meta.get("endpoint").and_then(Value::as_str).is_some_and(expvar::is_expvar) {
+        stats = expvar::to_stats(stats);
+    }
+    if !meta.contains_key("timestamp_ms") {
+        let time = doc.get("@timestamp").and_then(Value::as_str).and_then(|raw| DateTime::parse_from_rfc3339(raw).ok());
+        if let Some(time) = time {
+            meta.insert("timestamp_ms".to_string(), json!(time.timestamp_millis()));
+        }
+    }
+    stats.insert(META_KEY.to_string(), Value::Object(meta));
+    Ok(stats)
+}
+
+/// true if the capture at `path` is gzip-compressed
+fn is_gzip(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext == "gz")
+}
+
+/// When a capture rolls over to its next file, and which of its old files it keeps. A capture without either limit
+/// on its files is a single file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rotation {
+    /// Roll over once the current file is this many bytes on disk, after compression for a `.gz` capture
+    pub max_size: Option<u64>,
+    /// Roll over once the current file has been written to for this long
+    pub every: Option<Duration>,
+    /// Remove the oldest files of the set beyond this many, counting the one being written
+    pub keep: Option<usize>,
+    /// Remove files of the set last written to longer ago than this
+    pub max_age: Option<Duration>,
+}
+
+impl Rotation {
+    /// true if the capture rolls over at all
+    fn rolls_over(&self) -> bool {
+        self.max_size.is_some() || self.every.is_some()
+    }
+
+    /// true if old files of the set are removed
+    fn prunes(&self) -> bool {
+        self.keep.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Open a capture for appending. Appending to a compressed capture adds another gzip member to it, which decompresses as one file.
+/// A rotated capture carries on from the last file of its set.
+pub fn create(path: &str, rotation: Rotation) -> anyhow::Result<CaptureWriter> {
+    if !rotation.rolls_over() {
+        return open_writer(path).map(|(writer, _)| writer);
+    }
+    let index = rotated_set(path).last().map_or(0, |(index, _)| *index);
+    let (inner, size) = open_writer(&rotated_path(path, index))?;
+    let writer = RotatingWriter {
+        path: path.to_string(),
+        index,
+        rotation,
+        inner,
+        size,
+        opened: Instant::now(),
+        line_start: true,
+    };
+    writer.prune();
+    Ok(Box::new(writer))
+}
+
+/// Open a file of a capture for appending, along with the number of bytes in it, which goes up as more reach the disk
+fn open_writer(path: &str) -> anyhow::Result<(CaptureWriter, Arc<AtomicU64>)> {
+    let file = OpenOptions::new().append(true).create(true).open(path).with_context(|| format!("error opening {}", path))?;
+    let size = Arc::new(AtomicU64::new(file.metadata().map_or(0, |meta| meta.len())));
+    let file = CountingFile { file, size: size.clone() };
+    if is_gzip(path) {
+        // the encoder writes the gzip trailer when it's dropped, at the end of the run or when the capture rolls over
+        Ok((Box::new(GzEncoder::new(file, Compression::default())), size))
+    } else {
+        Ok((Box::new(file), size))
+    }
+}
+
+/// A file that counts the bytes written to it
+struct CountingFile {
+    file: File,
+    size: Arc<AtomicU64>,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The name of a file in a rotated capture. The first is the capture's own name, and the ones after it are numbered
+/// before the extension, like `out.2.ndjson.gz`.
+pub fn rotated_path(path: &str, index: usize) -> String {
+    let path = Path::new(path);
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let file = match (index, split_name(name)) {
+        (0, _) => return path.to_string_lossy().into_owned(),
+        (_, (stem, Some(ext))) => format!("{}.{}.{}", stem, index, ext),
+        (_, (stem, None)) => format!("{}.{}", stem, index),
+    };
+    path.with_file_name(file).to_string_lossy().into_owned()
+}
+
+/// Which file of the set of the capture at `path` a file called `name` is, if it's one of them
+fn rotated_index(path: &str, name: &str) -> Option<usize> {
+    let own = Path::new(path).file_name()?.to_str()?;
+    if name == own {
+        return Some(0);
+    }
+    let (stem, ext) = split_name(own);
+    let rest = name.strip_prefix(stem)?.strip_prefix('.')?;
+    let index = match ext {
+        Some(ext) => rest.strip_suffix(ext)?.strip_suffix('.')?,
+        None => rest,
+    };
+    index.parse().ok().filter(|parsed: &usize| *parsed > 0 && parsed.to_string() == index)
+}
+
+/// Every file of a capture that exists, with its place in the set, in the order they were written. The oldest files may
+/// have been pruned, so the set doesn't have to start with the capture's own name.
+fn rotated_set(path: &str) -> Vec<(usize, String)> {
+    let dir = Path::new(path).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut indexes: Vec<usize> = fs::read_dir(dir).into_iter().flatten().flatten()
+    .filter_map(|entry| entry.file_name().to_str().and_then(|name| rotated_index(path, name)))
+    .collect();
+    indexes.sort_unstable();
+    indexes.into_iter().map(|index| (index, rotated_path(path, index))).collect()
+}
+
+/// The files of a rotated set to remove, given each one's place in the set and when it was last written to: the oldest
+/// beyond the newest `keep`, and any older than `max_age`. The file being written is always kept.
+fn expired(set: &[(usize, SystemTime)], current: usize, rotation: &Rotation, now: SystemTime) -> Vec<usize> {
+    let excess = rotation.keep.map_or(0, |keep| set.len().saturating_sub(keep));
+    let too_old = |modified: &SystemTime| rotation.max_age.is_some_and(|age| now.duration_since(*modified).is_ok_and(|since| since > age));
+    set.iter().enumerate()
+    .filter(|(position, (index, modified))| *index != current && (*position < excess || too_old(modified)))
+    .map(|(_, (index, _))| *index)
+    .collect()
+}
+
+/// Writes a capture, rolling over to the next file of its set between lines once the current one is big or old enough
+struct RotatingWriter {
+    /// The capture's own name, which the rest of the set is named after
+    path: String,
+    index: usize,
+    rotation: Rotation,
+    inner: CaptureWriter,
+    /// The size of the file being written, as far as it's reached the disk
+    size: Arc<AtomicU64>,
+    opened: Instant,
+    /// true if the next write starts a new line, so the capture can roll over before it
+    line_start: bool,
+}
+
+impl RotatingWriter {
+    fn due(&self) -> bool {
+        // a gzip encoder only writes in blocks, so a compressed file goes a block or so past the limit before it's noticed
+        let size = self.size.load(Ordering::Relaxed);
+        self.rotation.max_size.is_some_and(|max| size >= max) || self.rotation.every.is_some_and(|every| self.opened.elapsed() >= every)
+    }
+
+    fn roll_over(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.index += 1;
+        let next = rotated_path(&self.path, self.index);
+        info!("continuing capture in {}", next);
+        // replacing the writer drops the last one, finishing its file
+        (self.inner, self.size) = open_writer(&next).map_err(io::Error::other)?;
+        self.opened = Instant::now();
+        self.prune();
+        Ok(())
+    }
+
+    /// Remove the files of the set that are past `keep` or `max_age`. Only done as the capture is opened and rolls over,
+    /// so a file can outlive `max_age` by up to one rotation.
+    fn prune(&self) {
+        if !self.rotation.prunes() {
+            return;
+        }
+        let set: Vec<(usize, SystemTime)> = rotated_set(&self.path).into_iter()
+        .filter_map(|(index, file)| Some((index, fs::metadata(file).and_then(|meta| meta.modified()).ok()?)))
+        .collect();
+        for index in expired(&set, self.index, &self.rotation, SystemTime::now()) {
+            let file = rotated_path(&self.path, index);
+            match fs::remove_file(&file) {
+                Ok(()) => info!("removed {}, which is past the capture's retention", file),
+                Err(e) => warn!("error removing {}: {}", file, e),
+            }
+        }
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && !buf.is_empty() && self.due() {
+            self.roll_over()?;
+        }
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Parse a size in bytes, like `500KB`, `100MB` or `2GiB`. A bare number is bytes.
+pub fn parse_size(raw: &str) -> anyhow::Result<u64> {
+    let raw = raw.trim();
+    let split = raw.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(raw.len());
+    let (value, unit) = raw.split_at(split);
+    let value: f64 = value.parse().with_context(|| format!("invalid size '{}'", raw))?;
+    let scale = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "kb" => 1e3,
+        "mb" => 1e6,
+        "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        other => return Err(anyhow!("unknown size unit '{}', expected B, KB, MB, GB, KiB, MiB or GiB", other)),
+    };
+    let size = (value * scale).round() as u64;
+    if size == 0 {
+        return Err(anyhow!("size must be greater than zero"));
+    }
+    Ok(size)
+}
+
+/// Point out a capture that looks like the start of a rotated set, when it's only being read on its own
+fn warn_if_rotated(path: &str) {
+    let next = rotated_path(path, 1);
+    if Path::new(&next).exists() {
+        warn!("{} looks like the rest of a rotated capture, pass --read-rotated to read it after {}", next, path);
+    }
+}
+
+/// The files of a capture to read: with `rotated`, the capture and the rest of its set after it, each one logged as it's added.
+/// Otherwise just the capture.
+fn files_to_read(path: &str, rotated: bool) -> Vec<String> {
+    if !rotated {
+        warn_if_rotated(path);
+        return vec![path.to_string()];
+    }
+    let set: Vec<String> = rotated_set(path).into_iter().map(|(_, file)| file).collect();
+    if set.is_empty() {
+        // left for reading it to report
+        return vec![path.to_string()];
+    }
+    for file in set.iter().filter(|file| *file != path) {
+        info!("reading {} as part of {}", file, path);
+    }
+    set
+}
+
+/// Read a whole capture, decompressing it if needed. With `rotated`, the rest of its set is read after it.
+pub async fn read(path: &str, rotated: bool) -> anyhow::Result<String> {
+    if path == STDIN {
+        let mut lines = stdin_lines();
+        let mut raw = String::new();
+        while let Some(line) = lines.recv().await {
+            raw.push_str(&line.context("error reading stdin")?);
+        }
+        return Ok(raw);
+    }
+    let mut raw = String::new();
+    for file in files_to_read(path, rotated) {
+        raw.push_str(&read_file(&file)?);
+        if !raw.is_empty() && !raw.ends_with('\n') {
+            raw.push('\n');
+        }
+    }
+    Ok(raw)
+}
+
+/// Read a single file of a capture
+fn read_file(path: &str) -> anyhow::Result<String> {
+    if !is_gzip(path) {
+        return fs::read_to_string(path).with_context(|| format!("error reading {}", path));
+    }
+
+    let file = fs::File::open(path).with_context(|| format!("error opening {}", path))?;
+    let mut raw = String::new();
+    MultiGzDecoder::new(file).read_to_string(&mut raw).with_context(|| format!("error decompressing {}", path))?;
+    Ok(raw)
+}
+
+/// Read stdin a line at a time on a thread of its own. A pipe that's quiet for a while blocks that thread instead of the
+/// runtime, so signals are still handled, and the thread doesn't keep the process from exiting once the run is over.
+fn stdin_lines() -> mpsc::Receiver<io::Result<String>> {
+    let (tx, rx) = mpsc::channel(STDIN_BUFFER);
+    std::thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        loop {
+            let mut line = String::new();
+            match stdin.read_line(&mut line) {
+                Ok(0) => return,
+                Ok(_) => {
+                    // stop once the reader has been dropped
+                    if tx.blocking_send(Ok(line)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            }
+        }
+    });
+    rx
+}
+
+/// Where a [`Reader`] gets its lines from
+enum Source {
+    /// A capture file
+    File(Box<dyn BufRead + Send>),
+    /// The lines of stdin, from [`stdin_lines`]
+    Stdin(mpsc::Receiver<io::Result<String>>),
+}
+
+/// Reads a capture a line at a time, optionally following it as it grows, like `tail -f`.
+/// A rotated capture can be read through to the last file of its set, and followed as it rolls over to new ones.
+pub struct Reader {
+    inner: Source,
+    follow: bool,
+    /// The start of a line that hasn't been completely written yet
+    partial: String,
+    /// The capture's own name, if it's read as a rotated set
+    path: Option<String>,
+    /// The file of the capture's set being read
+    index: usize,
+}
+
+impl Reader {
+    /// Open the capture at `path`, or stdin for [`STDIN`]. Stdin is always read until it's closed, so there's nothing to follow.
+    /// With `rotated`, the rest of the capture's set is read after it.
+    pub fn open(path: &str, follow: bool, rotated: bool) -> anyhow::Result<Reader> {
+        if path == STDIN {
+            return Ok(Reader { inner: Source::Stdin(stdin_lines()), follow: false, partial: String::new(), path: None, index: 0 });
+        }
+
+        if !rotated {
+            warn_if_rotated(path);
+            return Ok(Reader { inner: Source::File(open_reader(path, follow)?), follow, partial: String::new(), path: None, index: 0 });
+        }
+        // the start of the set may have been pruned
+        let (index, first) = rotated_set(path).into_iter().next().unwrap_or((0, path.to_string()));
+        if first != path {
+            info!("reading {} as part of {}", first, path);
+        }
+        Ok(Reader { inner: Source::File(open_reader(&first, follow)?), follow, partial: String::new(), path: Some(path.to_string()), index })
+    }
+
+    /// Move on to the next file of a rotated capture, if it's there
+    fn next_file(&mut self) -> anyhow::Result<bool> {
+        let Some(next) = self.path.as_deref().map(|path| rotated_path(path, self.index + 1)).filter(|next| Path::new(next).exists()) else {
+            return Ok(false);
+        };
+        info!("reading {} as part of {}", next, self.path.as_deref().unwrap_or_default());
+        self.inner = Source::File(open_reader(&next, self.follow)?);
+        self.index += 1;
+        Ok(true)
+    }
+
+    /// The next line of the capture, or `None` at its end. When following, waits for more to be written instead of ending.
+    pub async fn next_line(&mut self) -> anyhow::Result<Option<String>> {
+        loop {
+            let read = match &mut self.inner {
+                Source::File(file) => file.read_line(&mut self.partial).context("error reading capture")?,
+                Source::Stdin(lines) => match lines.recv().await {
+                    Some(line) => {
+                        let line = line.context("error reading stdin")?;
+                        self.partial.push_str(&line);
+                        line.len()
+                    }
+                    None => 0,
+                },
+            };
+            if self.partial.ends_with('\n') {
+                let line = std::mem::take(&mut self.partial);
+                return Ok(Some(line.trim_end().to_string()));
+            }
+            if read > 0 {
+                continue;
+            }
+            // captures only roll over between lines
+            if self.partial.is_empty() && self.next_file()? {
+                continue;
+            }
+            if !self.follow {
+                // the last line may not have a newline
+                return Ok((!self.partial.is_empty()).then(|| std::mem::take(&mut self.partial).trim_end().to_string()));
+            }
+            tokio::time::sleep(FOLLOW_POLL).await;
+        }
+    }
+}
+
+fn open_reader(path: &str, follow: bool) -> anyhow::Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path).with_context(|| format!("error opening {}", path))?;
+    match (is_gzip(path), follow) {
+        (false, _) => Ok(Box::new(BufReader::new(file))),
+        (true, false) => Ok(Box::new(BufReader::new(MultiGzDecoder::new(file)))),
+        (true, true) => Err(anyhow!("can't follow {}, compressed captures can only be read once they're complete", path)),
+    }
+}
+
+/// Split a capture's file name into its name and extension, keeping `.gz` with the extension before it, like `out` and `ndjson.gz`
+pub fn split_name(name: &str) -> (&str, Option<&str>) {
+    let plain = name.strip_suffix(".gz").unwrap_or(name);
+    match plain.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => (stem, Some(&name[stem.len() + 1..])),
+        _ if plain.len() < name.len() => (plain, Some("gz")),
+        _ => (name, None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use serde_json::json;
+
+    use super::{expired, parse_line, parse_size, rotated_index, rotated_path, split_name, write_envelope, Rotation};
+
+    #[test]
+    fn test_envelope() {
+        let stats = json!({"beat": {"memstats": {"rss": 1000}}}).as_object().unwrap().clone();
+        let meta = json!({"endpoint": "http://localhost:5066/stats", "timestamp_ms": 1_700_000_000_000_i64});
+        let mut line = Vec::new();
+        write_envelope(&mut line, &stats, &meta).unwrap();
+        let line = String::from_utf8(line).unwrap();
+        assert!(line.starts_with(r#"{"beatperf_capture":1,"#) && line.ends_with("}\n"));
+        assert!(line.contains(r#""@timestamp":"2023-11-14T22:13:20.000Z""#));
+        let doc = parse_line(&line).unwrap();
+        assert_eq!(doc["beat"]["memstats"]["rss"], 1000);
+        assert_eq!(doc["beatperf"]["timestamp_ms"], 1_700_000_000_000_i64);
+
+        // the time comes from @timestamp if the measurements don't have it
+        let doc = parse_line(r#"{"beatperf_capture": 1, "@timestamp": "2023-11-14T22:13:20Z", "stats": {"beat": {}}}"#).unwrap();
+        assert_eq!(doc["beatperf"]["timestamp_ms"], 1_700_000_000_000_i64);
+        // expvar responses are converted as they're read
+        let doc = parse_line(r#"{"beatperf_capture": 1, "beatperf": {"endpoint": "http://localhost:5066/debug/vars"}, "stats": {"memstats": {"Alloc": 1}}}"#).unwrap();
+        assert_eq!(doc["beat"]["memstats"]["memory_alloc"], 1);
+
+        // lines without an envelope are stats documents already
+        assert_eq!(parse_line(r#"{"beat": {"runtime": {"goroutines": 3}}}"#).unwrap()["beat"]["runtime"]["goroutines"], 3);
+        assert!(parse_line(r#"{"beatperf_capture": 2, "stats": {}}"#).is_err());
+    }
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(split_name("out.ndjson"), ("out", Some("ndjson")));
+        assert_eq!(split_name("out.ndjson.gz"), ("out", Some("ndjson.gz")));
+        assert_eq!(split_name("out.gz"), ("out", Some("gz")));
+        assert_eq!(split_name("out"), ("out", None));
+    }
+
+    #[test]
+    fn test_rotation() {
+        assert_eq!(rotated_path("out.ndjson", 0), "out.ndjson");
+        assert_eq!(rotated_path("runs/soak.ndjson.gz", 2), "runs/soak.2.ndjson.gz");
+        assert_eq!(rotated_path("../capture", 1), "../capture.1");
+
+        assert_eq!(parse_size("100MB").unwrap(), 100_000_000);
+        assert_eq!(parse_size("1.5 KiB").unwrap(), 1536);
+        assert_eq!(parse_size("2048").unwrap(), 2048);
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("0MB").is_err());
+    }
+
+    #[test]
+    fn test_retention() {
+        assert_eq!(rotated_index("runs/soak.ndjson.gz", "soak.ndjson.gz"), Some(0));
+        assert_eq!(rotated_index("runs/soak.ndjson.gz", "soak.12.ndjson.gz"), Some(12));
+        assert_eq!(rotated_index("capture", "capture.3"), Some(3));
+        assert_eq!(rotated_index("soak.ndjson.gz", "soak.03.ndjson.gz"), None);
+        assert_eq!(rotated_index("soak.ndjson.gz", "soak.ndjson"), None);
+        assert_eq!(rotated_index("soak.ndjson", "other.1.ndjson"), None);
+
+        let now = SystemTime::now();
+        let hours = |h: u64| now - Duration::from_secs(h * 3600);
+        let set = [(2, hours(30)), (3, hours(20)), (4, hours(10)), (5, hours(0))];
+        let keep = Rotation { keep: Some(2), ..Default::default() };
+        assert_eq!(expired(&set, 5, &keep, now), vec![2, 3]);
+        let max_age = Rotation { max_age: Some(Duration::from_secs(24 * 3600)), ..Default::default() };
+        assert_eq!(expired(&set, 5, &max_age, now), vec![2]);
+        // the file being written is never removed, however old it is
+        assert_eq!(expired(&set[..1], 2, &Rotation { keep: Some(1), max_age: Some(Duration::from_secs(1)), ..Default::default() }, now), Vec::<usize>::new());
+    }
+}
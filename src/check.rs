@@ -0,0 +1,164 @@
+/*!
+ * `beatperf check`, which looks over a beat's monitoring endpoint before a run. Each of the beat's documents is fetched and
+ * parsed, what the beat is and how long it's been up is reported, and every built-in group is run over a sample of its stats
+ * to show which of them would find anything to chart.
+ */
+
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+use tracing::subscriber::NoSubscriber;
+
+use beatperf::{expvar, fetch::{self, Client}, groups::generic::{flatten_map, get_root_elem}, recorder::Recorder, registry::Registry};
+
+use crate::pprof;
+
+/// The top-level sections every beat's stats have
+const STATS_SECTIONS: [&str; 2] = ["beat", "libbeat"];
+
+/// Fetch and report on every document the beat behind `stats_url` serves. Only failing to fetch the stats is an error,
+/// since not every beat serves the rest.
+pub async fn run(client: &Client, stats_url: &str) -> anyhow::Result<()> {
+    println!("checking {}", stats_url);
+    let base = pprof::base_url(stats_url);
+
+    if let Some(base) = base {
+        match get_json(client, &format!("{}/", base)).await {
+            Ok((Value::Object(info), took)) => report("/", took, &describe_info(&info)),
+            Ok(_) => report_failure("/", &anyhow!("expected a JSON object")),
+            Err(e) => report_failure("/", &e),
+        }
+    }
+
+    let path = base.map_or(stats_url, |base| &stats_url[base.len()..]);
+    let (body, timings) = match client.get(stats_url).await {
+        Ok(res) => res,
+        Err(e) => {
+            report_failure(path, &e);
+            return Err(anyhow!("error fetching {}. Is it correct, and is the beat running?", stats_url));
+        }
+    };
+    let mut doc: Map<String, Value> = serde_json::from_str(&body).with_context(|| format!("{} didn't return a JSON object", stats_url))?;
+    if expvar::is_expvar(stats_url) {
+        doc = expvar::to_stats(doc);
+    }
+    report(path, timings.total, &describe_stats(&doc));
+    fetch::add_meta(&mut doc, stats_url, timings);
+
+    if let Some(url) = fetch::state_url(stats_url) {
+        match get_json(client, &url).await {
+            Ok((Value::Object(state), took)) => report("/state", took, &describe_state(&state)),
+            Ok(_) => report_failure("/state", &anyhow!("expected a JSON object")),
+            Err(e) => report_failure("/state", &e),
+        }
+    }
+    // only filebeat serves its inputs, but --per-input can't find anything without them
+    if let Some(url) = fetch::inputs_url(stats_url) {
+        match get_json(client, &url).await {
+            Ok((Value::Array(inputs), took)) => {
+                report("/inputs/", took, &format!("{} inputs", inputs.len()));
+                fetch::add_inputs(&mut doc, &inputs);
+            }
+            Ok(_) => report_failure("/inputs/", &anyhow!("expected a JSON array")),
+            Err(e) => report_failure("/inputs/", &e),
+        }
+    }
+
+    let (found, missing): (Vec<_>, Vec<_>) = groups_found(&Registry::builtin(), &doc).into_iter().partition(|(_, found)| *found);
+    let names = |groups: Vec<(&str, bool)>| groups.into_iter().map(|(name, _)| name).collect::<Vec<_>>().join(", ");
+    println!();
+    println!("groups with metrics to chart:    {}", names(found));
+    println!("groups with nothing to chart:    {}", names(missing));
+
+    Ok(())
+}
+
+/// Fetch a JSON document, along with how long the request took
+async fn get_json(client: &Client, url: &str) -> anyhow::Result<(Value, Duration)> {
+    let (body, timings) = client.get(url).await?;
+    let doc = serde_json::from_str(&body).context("response is not JSON")?;
+    Ok((doc, timings.total))
+}
+
+fn report(path: &str, took: Duration, summary: &str) {
+    println!("{:<12} ok      {:>6}ms  {}", path, took.as_millis(), summary);
+}
+
+fn report_failure(path: &str, e: &anyhow::Error) {
+    println!("{:<12} failed            {:#}", path, e);
+}
+
+/// The beat's type, version and host, from the document at `/`
+fn describe_info(info: &Map<String, Value>) -> String {
+    let field = |key: &str| info.get(key).and_then(Value::as_str).unwrap_or("unknown");
+    format!("{} {} on {}", field("beat"), field("version"), field("hostname"))
+}
+
+/// The beat's uptime and how many metrics it reports, along with any section every beat's stats should have but these don't
+fn describe_stats(doc: &Map<String, Value>) -> String {
+    let mut summary = match get_root_elem(doc, "beat.info.uptime.ms").and_then(Value::as_f64) {
+        Some(ms) => format!("up {}, ", format_uptime(Duration::from_millis(ms as u64))),
+        None => "no uptime, ".to_string(),
+    };
+    let count = flatten_map(doc).len();
+    summary.push_str(&format!("{} metric{}", count, if count == 1 { "" } else { "s" }));
+    let missing: Vec<&str> = STATS_SECTIONS.into_iter().filter(|section| !doc.get(*section).is_some_and(Value::is_object)).collect();
+    if !missing.is_empty() {
+        summary.push_str(&format!(", missing {}", missing.join(" and ")));
+    }
+    summary
+}
+
+/// The beat's output and queue, from the document at `/state`
+fn describe_state(state: &Map<String, Value>) -> String {
+    let name = |path: &str| get_root_elem(state, path).and_then(Value::as_str).unwrap_or("unknown").to_string();
+    format!("output {}, queue {}", name("output.name"), name("queue.name"))
+}
+
+/// An uptime, to the second, like `2h5m3s`
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, m, s) => format!("{}h{}m{}s", h, m, s),
+    }
+}
+
+/// Whether each group in the registry charts anything from a stats document
+fn groups_found(registry: &Registry, doc: &Map<String, Value>) -> Vec<(&'static str, bool)> {
+    // groups log every key they can't find, which is the answer here rather than a problem
+    tracing::subscriber::with_default(NoSubscriber::default(), || registry.iter().map(|spec| {
+        let mut recorder = spec.record(Recorder::new());
+        recorder.record(doc);
+        let found = recorder.summary().groups.iter().any(|group| group.series.values().any(|series| !series.is_empty()));
+        (spec.name, found)
+    }).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use beatperf::registry::Registry;
+    use serde_json::json;
+
+    use super::{describe_stats, format_uptime, groups_found};
+
+    #[test]
+    fn test_groups_found() {
+        let doc = json!({
+            "beat": {"info": {"uptime": {"ms": 7_503_000}}, "memstats": {"rss": 1000, "gc_next": 1, "memory_alloc": 1, "memory_sys": 1, "memory_total": 1}},
+            "libbeat": {"output": {"type": "elasticsearch"}},
+        }).as_object().unwrap().clone();
+        let found = groups_found(&Registry::builtin(), &doc);
+        assert!(found.contains(&("memory", true)));
+        assert!(found.contains(&("auditbeat", false)));
+        assert_eq!(describe_stats(&doc), "up 2h5m3s, 6 metrics");
+
+        let expvar = json!({"memstats": {"HeapInuse": 1}}).as_object().unwrap().clone();
+        assert_eq!(describe_stats(&expvar), "no uptime, 1 metric, missing beat and libbeat");
+        assert_eq!(format_uptime(Duration::from_secs(59)), "59s");
+    }
+}
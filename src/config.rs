@@ -0,0 +1,114 @@
+/*!
+ * Reads beatperf's own config file, so long lists of metrics and options don't all have to go on the command line.
+ * The file is a YAML or TOML map of option names to values, and is turned into command-line arguments that are parsed
+ * ahead of the real ones. That way every option is validated the same way, and anything on the command line takes precedence:
+ *
+ * ```yaml
+ * endpoint: localhost:5066
+ * interval: 1s
+ * pipeline: true
+ * metrics:
+ *   - beat.runtime.goroutines
+ *   - libbeat.output.write.bytes
+ * ```
+ */
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context};
+use serde_json::Value;
+
+/// The key of the positional endpoint argument
+const ENDPOINT_KEY: &str = "endpoint";
+
+/// A config file, as command-line arguments
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigArgs {
+    /// Every option, as flags and their values
+    pub flags: Vec<String>,
+    /// The endpoints to watch, if the config file sets any
+    pub endpoints: Vec<String>,
+}
+
+/// Read a config file. `known` is the long name of every command-line option, used to catch typos in the file.
+pub fn load(path: &str, known: &[&str]) -> anyhow::Result<ConfigArgs> {
+    let raw = fs::read_to_string(path).with_context(|| format!("error reading config file {}", path))?;
+    let root: Value = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&raw).with_context(|| format!("error parsing config file {}", path))?,
+        _ => serde_yaml::from_str(&raw).with_context(|| format!("error parsing config file {}", path))?,
+    };
+
+    to_args(&root, known).with_context(|| format!("invalid config file {}", path))
+}
+
+fn to_args(root: &Value, known: &[&str]) -> anyhow::Result<ConfigArgs> {
+    let map = root.as_object().ok_or_else(|| anyhow!("the config must be a map of option names to values"))?;
+    let mut args = ConfigArgs::default();
+    for (key, value) in map {
+        // options can be written like they are on the command line, or with underscores
+        let name = key.replace('_', "-");
+        if name == ENDPOINT_KEY || name == "endpoints" {
+            args.endpoints.extend(values(key, value)?);
+            continue;
+        }
+        if !known.contains(&name.as_str()) {
+            return Err(anyhow!("unknown option '{}'", key));
+        }
+
+        match value {
+            Value::Bool(true) => args.flags.push(format!("--{}", name)),
+            Value::Bool(false) | Value::Null => {}
+            _ => {
+                for val in values(key, value)? {
+                    args.flags.push(format!("--{}", name));
+                    args.flags.push(val);
+                }
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+/// The values of a single option, which is either a single value or a list of them
+fn values(key: &str, value: &Value) -> anyhow::Result<Vec<String>> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(anyhow!("unsupported value for option '{}', expected a string, number, or list of them", key)),
+    };
+    match value {
+        Value::Array(list) => list.iter().map(scalar).collect(),
+        _ => Ok(vec![scalar(value)?]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::{to_args, ConfigArgs};
+
+    #[test]
+    fn test_to_args() {
+        let known = ["interval", "pipeline", "memory", "metrics"];
+        let root: Value = serde_yaml::from_str("
+endpoint: localhost:5067
+interval: 1s
+pipeline: true
+memory: false
+metrics: [beat.runtime.goroutines, libbeat.output.write.bytes]
+").unwrap();
+        assert_eq!(to_args(&root, &known).unwrap(), ConfigArgs {
+            flags: ["--interval", "1s", "--metrics", "beat.runtime.goroutines", "--metrics", "libbeat.output.write.bytes", "--pipeline"]
+            .map(String::from).to_vec(),
+            endpoints: vec!["localhost:5067".to_string()],
+        });
+
+        let root: Value = toml::from_str("kernel_tracing = true").unwrap();
+        assert!(to_args(&root, &known).unwrap_err().to_string().contains("kernel_tracing"));
+        let root: Value = serde_yaml::from_str("interval: {secs: 1}").unwrap();
+        assert!(to_args(&root, &known).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+/*!
+ * A small HTTP control API, bound to localhost, that lets automation drive a running beatperf instance.
+ *
+ * - `GET /status`: report run status as JSON
+ * - `POST /render`: force every watcher to render its plot
+ * - `POST /add-metric`: add the dot-notation metric in the request body to the custom metrics group
+ * - `POST /mark`: draw a vertical line on every chart at the latest sample, labelled with the request body
+ * - `POST /stop`: render final plots and shut down cleanly
+ */
+
+use std::{convert::Infallible, net::{Ipv4Addr, SocketAddr}, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Instant};
+
+use anyhow::Context;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::{Bytes, Incoming}, server::conn::http1, service::service_fn, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::json;
+use tokio::{net::TcpListener, sync::broadcast::Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use beatperf::{fetch::Client, poller::RunStats, watchers::Control};
+
+/// Everything the control API needs to report on and drive a run
+pub struct ControlState {
+    /// Every endpoint being watched, and its fetch counters
+    pub endpoints: Vec<(String, Arc<RunStats>)>,
+    pub groups: Vec<&'static str>,
+    /// true if a group that accepts new metrics is running
+    pub accepts_metrics: bool,
+    pub started: Instant,
+    pub control: Sender<Control>,
+    pub shutdown: CancellationToken,
+}
+
+/// Start the control API on the given localhost port
+pub async fn serve(port: u16, state: ControlState) -> anyhow::Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let listener = TcpListener::bind(addr).await?;
+    info!("control API listening on http://{}", addr);
+
+    let state = Arc::new(state);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("error accepting control connection: {}", e);
+                    continue;
+                }
+            };
+            let conn_state = state.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| handle(req, conn_state.clone()));
+                if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                    debug!("error serving control connection: {}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle(req: Request<Incoming>, state: Arc<ControlState>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let resp = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/status") => {
+            let endpoints: Vec<_> = state.endpoints.iter().map(|(endpoint, stats)| json!({
+                "endpoint": endpoint,
+                "samples": stats.samples.load(Ordering::Relaxed),
+                "fetch_errors": stats.fetch_errors.load(Ordering::Relaxed),
+                "fetch_timeouts": stats.timeouts.load(Ordering::Relaxed),
+                "fetch_retried": stats.retried.load(Ordering::Relaxed),
+                "outages": stats.outages.load(Ordering::Relaxed),
+                "longest_outage": stats.longest_outage.load(Ordering::Relaxed),
+                "threshold_breaches": stats.breaches.load(Ordering::Relaxed),
+            })).collect();
+            let total = |counter: fn(&RunStats) -> &AtomicU64| -> u64 {
+                state.endpoints.iter().map(|(_, stats)| counter(stats).load(Ordering::Relaxed)).sum()
+            };
+            let status = json!({
+                "endpoints": endpoints,
+                "groups": state.groups,
+                "uptime_secs": state.started.elapsed().as_secs(),
+                "samples": total(|stats| &stats.samples),
+                "fetch_errors": total(|stats| &stats.fetch_errors),
+                "fetch_timeouts": total(|stats| &stats.timeouts),
+                "fetch_retried": total(|stats| &stats.retried),
+                "outages": total(|stats| &stats.outages),
+                "longest_outage": state.endpoints.iter().map(|(_, stats)| stats.longest_outage.load(Ordering::Relaxed)).max().unwrap_or_default(),
+                "threshold_breaches": total(|stats| &stats.breaches),
+            });
+            response(StatusCode::OK, status.to_string())
+        }
+        (&Method::POST, "/render") => {
+            send(&state, Control::Render)
+        }
+        (&Method::POST, "/add-metric") => {
+            if !state.accepts_metrics {
+                return Ok(response(StatusCode::CONFLICT, "no custom metrics group is running, start beatperf with --metrics".to_string()));
+            }
+            let key = match req.into_body().collect().await {
+                Ok(body) => String::from_utf8_lossy(&body.to_bytes()).trim().to_string(),
+                Err(e) => return Ok(response(StatusCode::BAD_REQUEST, format!("error reading body: {}", e))),
+            };
+            if key.is_empty() {
+                return Ok(response(StatusCode::BAD_REQUEST, "request body must contain a metric key".to_string()));
+            }
+            send(&state, Control::AddMetric(key))
+        }
+        (&Method::POST, "/mark") => {
+            let label = match req.into_body().collect().await {
+                Ok(body) => String::from_utf8_lossy(&body.to_bytes()).trim().to_string(),
+                Err(e) => return Ok(response(StatusCode::BAD_REQUEST, format!("error reading body: {}", e))),
+            };
+            if label.is_empty() {
+                return Ok(response(StatusCode::BAD_REQUEST, "request body must contain a label".to_string()));
+            }
+            info!("marking the charts with '{}'", label);
+            send(&state, Control::Annotate(label))
+        }
+        (&Method::POST, "/stop") => {
+            info!("stop requested via control API");
+            state.shutdown.cancel();
+            response(StatusCode::OK, "stopping".to_string())
+        }
+        (_, "/status" | "/render" | "/add-metric" | "/mark" | "/stop") => {
+            response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed".to_string())
+        }
+        _ => response(StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+
+    Ok(resp)
+}
+
+/// Ask the beatperf instance serving the control API on a localhost port to mark its charts with a label
+pub async fn mark(port: u16, label: &str) -> anyhow::Result<()> {
+    let url = format!("http://{}/mark", SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
+    Client::default().post(&url, "text/plain", label.as_bytes().to_vec()).await
+    .with_context(|| format!("error marking the charts through {}. Is beatperf running with --control-port {}?", url, port))?;
+    Ok(())
+}
+
+/// Forward a command to the watchers
+fn send(state: &ControlState, cmd: Control) -> Response<Full<Bytes>> {
+    match state.control.send(cmd) {
+        Ok(_) => response(StatusCode::ACCEPTED, "ok".to_string()),
+        Err(e) => response(StatusCode::SERVICE_UNAVAILABLE, format!("no watchers running: {}", e)),
+    }
+}
+
+fn response(code: StatusCode, body: String) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::new(Bytes::from(body)));
+    *resp.status_mut() = code;
+    resp
+}
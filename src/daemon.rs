@@ -0,0 +1,60 @@
+/*!
+ * Runs beatperf in the background, for watching a production beat for days. Forking a process that's already running an async
+ * runtime isn't safe, so `--daemon` starts a second, detached copy of beatperf with the same arguments instead, and exits.
+ * The copy writes a pid file, and logs to a file, until it's stopped with a signal.
+ */
+
+use std::{env, fs::{self, File}, process::{self, Command, Stdio}, time::Duration};
+
+use anyhow::{anyhow, Context};
+
+/// Set in the environment of the detached copy, so it knows not to detach again
+const CHILD_ENV: &str = "BEATPERF_DAEMON_CHILD";
+
+/// How long to watch the detached copy for, so mistakes like an unreachable endpoint are reported straight away
+const STARTUP_CHECK: Duration = Duration::from_millis(500);
+
+/// true if this is the detached copy of a `--daemon` run
+pub fn is_child() -> bool {
+    env::var_os(CHILD_ENV).is_some()
+}
+
+/// Start a detached copy of beatperf with the same arguments, writing anything it prints to the log file, and return its pid
+pub async fn detach(log_file: &str) -> anyhow::Result<u32> {
+    let log = File::options().append(true).create(true).open(log_file).with_context(|| format!("error opening log file {}", log_file))?;
+    let mut command = Command::new(env::current_exe().context("error finding the beatperf executable")?);
+    command.args(env::args_os().skip(1))
+    .env(CHILD_ENV, "1")
+    .stdin(Stdio::null())
+    .stdout(log.try_clone()?)
+    .stderr(log);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // a process group of its own keeps it out of reach of the terminal's signals
+        command.process_group(0);
+    }
+    let mut child = command.spawn().context("error starting beatperf in the background")?;
+
+    tokio::time::sleep(STARTUP_CHECK).await;
+    if let Some(status) = child.try_wait()? {
+        return Err(anyhow!("beatperf exited straight away with {}, see {}", status, log_file));
+    }
+    Ok(child.id())
+}
+
+/// The pid of the running process, written to a file that's removed again when this is dropped
+pub struct PidFile(String);
+
+impl PidFile {
+    pub fn create(path: &str) -> anyhow::Result<PidFile> {
+        fs::write(path, format!("{}\n", process::id())).with_context(|| format!("error writing pid file {}", path))?;
+        Ok(PidFile(path.to_string()))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
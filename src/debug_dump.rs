@@ -0,0 +1,75 @@
+/*!
+ * Writes payloads that couldn't be parsed, or that had a value of an unexpected type, to a directory so they can be looked at later.
+ * Schema surprises from a specific beat build tend to be intermittent, and the log line alone usually isn't enough to diagnose them.
+ * Nothing is written unless `--debug-dump` is set.
+ */
+
+use std::{collections::HashSet, fs, path::PathBuf, sync::{Mutex, OnceLock}};
+
+use anyhow::Context;
+use chrono::Utc;
+use tracing::{error, warn};
+
+static DUMPER: OnceLock<Dumper> = OnceLock::new();
+
+struct Dumper {
+    dir: PathBuf,
+    // metric keys that have already had a type mismatch dumped
+    seen: Mutex<HashSet<String>>,
+}
+
+/// Start dumping payloads to the given directory, creating it if needed
+pub fn init(dir: &str) -> anyhow::Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("error creating debug dump directory {}", dir))?;
+    let _ = DUMPER.set(Dumper { dir: PathBuf::from(dir), seen: Mutex::new(HashSet::new()) });
+    Ok(())
+}
+
+/// Dump a raw payload that couldn't be parsed
+pub fn payload(reason: &str, raw: &str) {
+    if let Some(dumper) = DUMPER.get() {
+        dumper.write(reason, raw);
+    }
+}
+
+/// Dump a stats document with a value that couldn't be read as the expected type.
+/// A mismatch usually repeats on every sample, so only the first document is dumped for each key.
+pub fn mismatch(key: &str, root: &serde_json::Map<String, serde_json::Value>) {
+    let Some(dumper) = DUMPER.get() else {
+        return;
+    };
+    if !dumper.seen.lock().map(|mut seen| seen.insert(key.to_string())).unwrap_or(false) {
+        return;
+    }
+    match serde_json::to_string_pretty(root) {
+        Ok(raw) => dumper.write(&format!("type-{}", key), &raw),
+        Err(e) => error!("error serializing payload for debug dump: {}", e),
+    }
+}
+
+impl Dumper {
+    fn write(&self, reason: &str, raw: &str) {
+        let name = format!("{}_{}.json", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"), slug(reason));
+        let path = self.dir.join(name);
+        match fs::write(&path, raw) {
+            Ok(_) => warn!("wrote offending payload to {}", path.display()),
+            Err(e) => error!("error writing debug dump {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// Make a reason safe to use in a file name
+fn slug(reason: &str) -> String {
+    reason.chars().map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') { c } else { '_' }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::slug;
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("type-libbeat.pipeline.queue"), "type-libbeat.pipeline.queue");
+        assert_eq!(slug("parse error/1"), "parse_error_1");
+    }
+}
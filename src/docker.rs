@@ -0,0 +1,136 @@
+/*!
+ * Discovers beats running in Docker containers on this host. Running containers are listed through the Docker API, and the ones
+ * whose image is a beat, or that carry a given label, are watched through the host port their monitoring endpoint is published on.
+ */
+
+use std::{collections::HashMap, env};
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use beatperf::fetch::Client;
+
+/// Where the Docker API listens when `DOCKER_HOST` isn't set
+const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+/// The port beats serve their monitoring endpoint on, unless `http.port` says otherwise
+const MONITORING_PORT: u16 = 5066;
+
+/// The images of beats, by the last part of their name
+const BEATS: [&str; 6] = ["filebeat", "metricbeat", "packetbeat", "auditbeat", "heartbeat", "elastic-agent"];
+
+/// A container, as listed by the `/containers/json` endpoint
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    names: Vec<String>,
+    image: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    ports: Vec<Port>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Port {
+    #[serde(rename = "IP")]
+    ip: Option<String>,
+    #[serde(rename = "PrivatePort")]
+    private_port: u16,
+    #[serde(rename = "PublicPort")]
+    public_port: Option<u16>,
+}
+
+/// A beat running in a container
+#[derive(Clone, Debug, PartialEq)]
+pub struct Discovered {
+    /// The container's name, like `filebeat-1`
+    pub name: String,
+    /// The host and port its monitoring endpoint is published on, like `localhost:32768`
+    pub endpoint: String,
+}
+
+/// List the running containers that look like beats, or that have `label`, as `<key>` or `<key>=<value>`.
+/// Containers that don't publish their monitoring port are skipped.
+pub async fn discover(label: Option<&str>) -> anyhow::Result<Vec<Discovered>> {
+    let (client, base) = docker_api(env::var("DOCKER_HOST").ok().as_deref())?;
+    let url = format!("{}/containers/json", base);
+    let (body, _) = client.get(&url).await.context("error listing containers. Is Docker running, and can this user reach its socket?")?;
+    let containers: Vec<Container> = serde_json::from_str(&body).context("unexpected response listing containers")?;
+
+    let mut found = Vec::new();
+    for container in containers.into_iter().filter(|container| selected(container, label)) {
+        let name = container.names.first().map_or_else(|| container.image.clone(), |name| name.trim_start_matches('/').to_string());
+        match published(&container) {
+            Some(endpoint) => {
+                info!("found container {} ({}), monitoring on {}", name, container.image, endpoint);
+                found.push(Discovered { name, endpoint });
+            }
+            None => warn!("skipping container {}, it doesn't publish port {} for the beat's monitoring endpoint", name, MONITORING_PORT),
+        }
+    }
+
+    Ok(found)
+}
+
+/// A client for the Docker API at `DOCKER_HOST`, or at its default socket, and the base URL of the API
+fn docker_api(docker_host: Option<&str>) -> anyhow::Result<(Client, String)> {
+    match docker_host {
+        None => Ok((Client::default().with_unix_socket(DEFAULT_SOCKET), "http://docker".to_string())),
+        Some(host) => match host.split_once("://") {
+            Some(("unix", path)) => Ok((Client::default().with_unix_socket(path), "http://docker".to_string())),
+            Some(("tcp", addr)) => Ok((Client::default(), format!("http://{}", addr))),
+            _ => Err(anyhow!("unsupported DOCKER_HOST {}, expected unix:// or tcp://", host)),
+        },
+    }
+}
+
+/// true if a container should be watched: it has the label, if there is one, and otherwise its image is a beat
+fn selected(container: &Container, label: Option<&str>) -> bool {
+    match label.map(|label| label.split_once('=')) {
+        Some(Some((key, value))) => container.labels.get(key).is_some_and(|found| found == value),
+        Some(None) => label.is_some_and(|key| container.labels.contains_key(key)),
+        None => {
+            let image = container.image.rsplit('/').next().unwrap_or_default();
+            let image = image.split([':', '@']).next().unwrap_or_default();
+            BEATS.contains(&image)
+        }
+    }
+}
+
+/// The host address the container's monitoring port is published on
+fn published(container: &Container) -> Option<String> {
+    let port = container.ports.iter().find(|port| port.private_port == MONITORING_PORT && port.public_port.is_some())?;
+    let host = match port.ip.as_deref() {
+        None | Some("") | Some("0.0.0.0") | Some("::") => "localhost",
+        Some(ip) if ip.contains(':') => return Some(format!("[{}]:{}", ip, port.public_port?)),
+        Some(ip) => ip,
+    };
+    Some(format!("{}:{}", host, port.public_port?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{published, selected, Container};
+
+    #[test]
+    fn test_containers() {
+        let containers: Vec<Container> = serde_json::from_str(r#"[
+            {"Names": ["/filebeat-1"], "Image": "docker.elastic.co/beats/filebeat:8.17.0", "Labels": {},
+             "Ports": [{"IP": "0.0.0.0", "PrivatePort": 5066, "PublicPort": 32768, "Type": "tcp"}, {"IP": "::", "PrivatePort": 5066, "PublicPort": 32768, "Type": "tcp"}]},
+            {"Names": ["/shipper"], "Image": "custom/shipper:latest", "Labels": {"team": "obs"},
+             "Ports": [{"IP": "127.0.0.1", "PrivatePort": 5066, "PublicPort": 5067, "Type": "tcp"}]},
+            {"Names": ["/metricbeat"], "Image": "metricbeat", "Labels": {}, "Ports": [{"PrivatePort": 5066, "Type": "tcp"}]}
+        ]"#).unwrap();
+
+        let beats: Vec<&str> = containers.iter().filter(|c| selected(c, None)).map(|c| c.names[0].as_str()).collect();
+        assert_eq!(beats, ["/filebeat-1", "/metricbeat"]);
+        assert!(selected(&containers[1], Some("team=obs")) && selected(&containers[1], Some("team")));
+        assert!(!selected(&containers[1], Some("team=apm")) && !selected(&containers[0], Some("team")));
+
+        assert_eq!(published(&containers[0]).as_deref(), Some("localhost:32768"));
+        assert_eq!(published(&containers[1]).as_deref(), Some("127.0.0.1:5067"));
+        assert_eq!(published(&containers[2]), None);
+    }
+}
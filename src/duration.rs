@@ -0,0 +1,81 @@
+/*!
+ * Parsing for durations given on the command line, like `250ms`, `5s`, `1m` or `1m30s`.
+ */
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context};
+
+/// Parse a duration with a unit suffix (`ms`, `s`, `m` or `h`), or several, like `1m30s`. A bare number is taken as seconds.
+pub fn parse_duration(raw: &str) -> anyhow::Result<Duration> {
+    let raw = raw.trim();
+    let mut rest = raw;
+    let mut secs = 0.0;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let (value, tail) = rest.split_at(split);
+        let value: f64 = value.parse().with_context(|| format!("invalid duration '{}'", raw))?;
+        let tail = tail.trim_start();
+        let unit_len = tail.find(|c: char| c.is_ascii_digit() || c.is_whitespace()).unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+        secs += match unit {
+            "ms" => value / 1000.0,
+            // only a lone number can leave out its unit
+            "" if secs == 0.0 && tail.trim().is_empty() => value,
+            "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            "" => return Err(anyhow!("missing a unit in duration '{}'", raw)),
+            other => return Err(anyhow!("unknown duration unit '{}', expected ms, s, m or h", other)),
+        };
+        rest = tail.trim_start();
+    }
+
+    let duration = Duration::try_from_secs_f64(secs).with_context(|| format!("invalid duration '{}'", raw))?;
+    if duration.is_zero() {
+        return Err(anyhow!("duration must be greater than zero"));
+    }
+    Ok(duration)
+}
+
+/// How often a single group charts a sample, parsed from `<group>=<duration>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct GroupInterval {
+    pub group: String,
+    pub interval: Duration,
+}
+
+impl FromStr for GroupInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, interval) = s.split_once('=').ok_or_else(|| anyhow!("group interval must be formatted as <group>=<duration>"))?;
+        Ok(GroupInterval { group: group.trim().to_string(), interval: parse_duration(interval)? })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{parse_duration, GroupInterval};
+
+    #[test]
+    fn test_parse_duration() -> anyhow::Result<()> {
+        assert_eq!(parse_duration("250ms")?, Duration::from_millis(250));
+        assert_eq!(parse_duration("5")?, Duration::from_secs(5));
+        assert_eq!(parse_duration("1.5s")?, Duration::from_millis(1500));
+        assert_eq!(parse_duration("2m")?, Duration::from_secs(120));
+        assert_eq!(parse_duration("1m30s")?, Duration::from_secs(90));
+        assert_eq!(parse_duration("1h 5m")?, Duration::from_secs(3900));
+        assert_eq!(parse_duration("1s500ms")?, Duration::from_millis(1500));
+        assert!(parse_duration("1m30").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("5 fortnights").is_err());
+        assert!(parse_duration("ms").is_err());
+
+        assert_eq!("memory=30s".parse::<GroupInterval>()?, GroupInterval { group: "memory".to_string(), interval: Duration::from_secs(30) });
+        assert!("memory".parse::<GroupInterval>().is_err());
+        Ok(())
+    }
+}
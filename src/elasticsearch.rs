@@ -0,0 +1,164 @@
+/*!
+ * Indexes samples into Elasticsearch as they're collected, so a long session can be charted in Kibana alongside the beat's own data.
+ * Every sample becomes a document with each of its values under its dot-notation key, and the time it was taken as `@timestamp`.
+ * Documents are sent with the `_bulk` API in batches, and kept for the next batch if Elasticsearch can't be reached.
+ */
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+use tokio::{sync::broadcast::{error::RecvError, Receiver}, time::{self, MissedTickBehavior}};
+use tracing::{debug, warn};
+
+use crate::{fetch::{Client, TIMESTAMP_PATH}, groups::generic::get_root_elem, watchers::Sample};
+
+/// Documents are sent once this many are waiting
+const BATCH: usize = 50;
+/// Waiting documents are sent at least this often, however few there are
+const FLUSH_EVERY: Duration = Duration::from_secs(10);
+/// The most documents kept while Elasticsearch can't be reached. The oldest are dropped past this
+const MAX_PENDING: usize = 5000;
+
+/// Bulk-indexes samples into an index or data stream
+pub struct Indexer {
+    client: Client,
+    bulk_url: String,
+    index: String,
+    /// The action and source lines of every document that hasn't been indexed yet
+    pending: Vec<String>,
+}
+
+impl Indexer {
+    /// An indexer for the Elasticsearch cluster at `url`, like `https://localhost:9200`
+    pub fn new(client: Client, url: &str, index: &str) -> Indexer {
+        Indexer { client, bulk_url: format!("{}/_bulk", url.trim_end_matches('/')), index: index.to_string(), pending: Vec::new() }
+    }
+
+    /// Index every sample sent on the channel, until it closes
+    pub async fn run(mut self, mut samples: Receiver<Sample>) {
+        let mut flush = time::interval_at(time::Instant::now() + FLUSH_EVERY, FLUSH_EVERY);
+        flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                res = samples.recv() => match res {
+                    Ok(Sample::Missed) => {}
+                    Ok(Sample::Doc(sample)) => {
+                        self.add(&sample);
+                        if self.pending.len() >= BATCH {
+                            self.flush().await;
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => warn!("{} samples were not indexed, Elasticsearch is falling behind", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = flush.tick() => self.flush().await,
+            }
+        }
+        self.flush().await;
+    }
+
+    /// Queue a sample to be indexed with the next batch
+    pub fn add(&mut self, sample: &Map<String, Value>) {
+        // create works for data streams as well as regular indices
+        let action = json!({"create": {"_index": self.index}});
+        self.pending.push(format!("{}\n{}\n", action, Value::Object(to_document(sample))));
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            warn!("dropped {} samples that could not be indexed", dropped);
+        }
+    }
+
+    /// Send every waiting document. Documents are kept for the next try if the request fails,
+    /// but not if Elasticsearch rejects them, as they'd only be rejected again.
+    pub async fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        match self.send().await {
+            Ok(None) => debug!("indexed {} samples into {}", self.pending.len(), self.index),
+            Ok(Some(reason)) => warn!("some samples were rejected by {}: {}", self.index, reason),
+            Err(e) => {
+                warn!("error indexing samples into {}: {:#}", self.index, e);
+                return;
+            }
+        }
+        self.pending.clear();
+    }
+
+    /// Make the bulk request, returning the reason the first rejected document was rejected for, if any were
+    async fn send(&self) -> anyhow::Result<Option<String>> {
+        let body = self.client.post(&self.bulk_url, "application/x-ndjson", self.pending.concat().into_bytes()).await?;
+        let res: Map<String, Value> = serde_json::from_slice(&body).context("error parsing the bulk response")?;
+        if res.get("errors").and_then(Value::as_bool) != Some(true) {
+            return Ok(None);
+        }
+        let reason = res.get("items").and_then(Value::as_array).into_iter().flatten()
+        .find_map(|item| item.get("create")?.get("error")?.get("reason")?.as_str())
+        .unwrap_or("unknown error");
+        Ok(Some(reason.to_string()))
+    }
+}
+
+/// The document a sample is indexed as, with every value under its dot-notation key
+pub fn to_document(sample: &Map<String, Value>) -> Map<String, Value> {
+    let mut doc = Map::new();
+    let timestamp = get_root_elem(sample, TIMESTAMP_PATH).and_then(Value::as_i64)
+    .and_then(DateTime::from_timestamp_millis)
+    .unwrap_or_else(Utc::now);
+    doc.insert("@timestamp".to_string(), Value::String(timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)));
+    for (key, val) in sample {
+        flatten(key, val, &mut doc);
+    }
+    doc
+}
+
+fn flatten(key: &str, val: &Value, doc: &mut Map<String, Value>) {
+    match val {
+        Value::Object(inner) => {
+            for (inner_key, inner_val) in inner {
+                flatten(&format!("{}.{}", key, inner_key), inner_val, doc);
+            }
+        }
+        val => {
+            doc.insert(key.to_string(), val.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::fetch::Client;
+
+    use super::{to_document, Indexer};
+
+    #[test]
+    fn test_to_document() {
+        let sample = json!({
+            "beat": {"info": {"name": "filebeat", "version": "8.17.0"}, "memstats": {"rss": 1000}},
+            "beatperf": {"endpoint": "localhost:5066", "timestamp_ms": 1700000000000_i64},
+            "system": {"load": {"1": 0.5}, "cpu": {"cores": 4}},
+        }).as_object().unwrap().clone();
+
+        let doc = to_document(&sample);
+        assert_eq!(doc["@timestamp"], "2023-11-14T22:13:20.000Z");
+        assert_eq!(doc["beat.memstats.rss"], 1000);
+        assert_eq!(doc["beat.info.version"], "8.17.0");
+        assert_eq!(doc["system.load.1"], 0.5);
+        assert!(!doc.contains_key("beat"));
+    }
+
+    #[test]
+    fn test_bulk_body() {
+        let mut indexer = Indexer::new(Client::default(), "http://localhost:9200/", "beatperf");
+        assert_eq!(indexer.bulk_url, "http://localhost:9200/_bulk");
+        indexer.add(json!({"a": {"b": 1}}).as_object().unwrap());
+        let (action, doc) = indexer.pending[0].trim_end().split_once('\n').unwrap();
+        assert_eq!(action, r#"{"create":{"_index":"beatperf"}}"#);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(doc).unwrap()["a.b"], 1);
+    }
+}
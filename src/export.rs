@@ -0,0 +1,464 @@
+/*!
+ * Exporters that write the collected series of every group to files other than charts.
+ */
+
+use std::{fmt::Write as _, fs, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+#[cfg(feature = "xlsx")]
+use rust_xlsxwriter::{Format, Workbook};
+
+use serde_json::{json, Map, Value};
+
+use crate::groups::{generic::{get_root_elem, Series}, GroupData};
+
+/// The longest name Excel allows for a sheet
+#[cfg(feature = "xlsx")]
+const MAX_SHEET_NAME: usize = 31;
+
+/// The formats a group's output can be written in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The chart, as `<group>_plot.svg`
+    Svg,
+    /// The chart as a raster image, as `<group>_plot.png`
+    Png,
+    /// A standalone page with the chart and a summary of every series, as `<group>_plot.html`
+    Html,
+    /// The raw series as CSV, laid out as given
+    Csv(CsvLayout),
+}
+
+/// How a CSV export lays out a group's series. Both start every row with the datapoint's timestamp and index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvLayout {
+    /// One row per datapoint, with a column for each series, as `<group>.csv`
+    Wide,
+    /// One row per datapoint and series, as `timestamp,datapoint,key,value`, written as `<group>_series.csv`
+    Long,
+}
+
+impl CsvLayout {
+    /// The file a group's export is written to, for a group whose files start with `stem`
+    pub fn file_name(&self, stem: &str) -> String {
+        match self {
+            CsvLayout::Wide => format!("{}.csv", stem),
+            CsvLayout::Long => format!("{}_series.csv", stem),
+        }
+    }
+}
+
+impl FromStr for CsvLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "wide" => Ok(CsvLayout::Wide),
+            "long" => Ok(CsvLayout::Long),
+            other => Err(anyhow!("unknown CSV layout '{}', expected wide or long", other)),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "svg" => Ok(OutputFormat::Svg),
+            "png" => Ok(OutputFormat::Png),
+            "html" => Ok(OutputFormat::Html),
+            "csv" => Ok(OutputFormat::Csv(CsvLayout::Wide)),
+            "csv-long" => Ok(OutputFormat::Csv(CsvLayout::Long)),
+            other => Err(anyhow!("unknown output format '{}', expected svg, png, html, csv or csv-long", other)),
+        }
+    }
+}
+
+/// The output formats for every group, or a single group, parsed from `[<group>=]<format>[,<format>...]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatSpec {
+    /// The group the formats apply to, or `None` for every group without its own
+    pub group: Option<String>,
+    pub formats: Vec<OutputFormat>,
+}
+
+impl FromStr for FormatSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, list) = match s.split_once('=') {
+            Some((group, list)) => (Some(group.trim().to_string()), list),
+            None => (None, s),
+        };
+        let formats = list.split(',').map(OutputFormat::from_str).collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(FormatSpec { group, formats })
+    }
+}
+
+/// The name a group's files are written under, instead of the group's own, parsed from `<group>=<name>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileName {
+    pub group: String,
+    pub name: String,
+}
+
+impl FromStr for FileName {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, name) = s.split_once('=').ok_or_else(|| anyhow!("file name must be formatted as <group>=<name>"))?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(anyhow!("invalid file name '{}', it can't be empty or contain a path separator", name));
+        }
+
+        Ok(FileName { group: group.trim().to_string(), name: name.to_string() })
+    }
+}
+
+/// Summary statistics for a single series
+#[derive(Clone, Debug, PartialEq)]
+pub struct SeriesSummary {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub last: f64,
+}
+
+impl SeriesSummary {
+    /// Summarize a series, returns `None` if the series is empty
+    pub fn from_series(series: &Series<f64>) -> Option<SeriesSummary> {
+        let (_, last) = *series.last()?;
+        let values = series.iter().map(|(_, v)| *v);
+        let min = values.clone().fold(f64::INFINITY, f64::min);
+        let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+        let mean = values.sum::<f64>() / series.len() as f64;
+
+        Some(SeriesSummary { count: series.len(), min, max, mean, last })
+    }
+}
+
+/// Write every group to its own sheet of an Excel workbook, with one column per series,
+/// and a summary sheet with statistics for every series.
+#[cfg(feature = "xlsx")]
+pub fn write_xlsx(path: &str, groups: &[GroupData]) -> anyhow::Result<()> {
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+
+    let summary = workbook.add_worksheet();
+    summary.set_name("Summary")?;
+    for (col, header) in ["group", "metric", "count", "min", "max", "mean", "last"].iter().enumerate() {
+        summary.write_string_with_format(0, col as u16, *header, &bold)?;
+    }
+    let mut row = 1;
+    for group in groups {
+        for (key, series) in &group.series {
+            let Some(stats) = SeriesSummary::from_series(series) else {
+                continue;
+            };
+            summary.write_string(row, 0, &group.name)?;
+            summary.write_string(row, 1, key)?;
+            summary.write_number(row, 2, stats.count as f64)?;
+            summary.write_number(row, 3, stats.min)?;
+            summary.write_number(row, 4, stats.max)?;
+            summary.write_number(row, 5, stats.mean)?;
+            summary.write_number(row, 6, stats.last)?;
+            row += 1;
+        }
+    }
+    summary.set_column_width(1, 50)?;
+
+    for group in groups {
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(sheet_name(&group.name))?;
+        sheet.write_string_with_format(0, 0, "datapoint", &bold)?;
+        for idx in 0..group.datapoints {
+            sheet.write_number(idx as u32 + 1, 0, idx as f64)?;
+        }
+
+        for (col, (key, series)) in group.series.iter().enumerate() {
+            let col = col as u16 + 1;
+            sheet.write_string_with_format(0, col, key, &bold)?;
+            // datapoints a metric is missing from are left blank
+            for (idx, val) in series {
+                sheet.write_number(*idx as u32 + 1, col, *val)?;
+            }
+        }
+        sheet.set_freeze_panes(1, 1)?;
+    }
+
+    workbook.save(path).with_context(|| format!("error writing workbook {}", path))?;
+
+    Ok(())
+}
+
+/// Write a single group to a CSV file, laid out as given. Datapoints a metric is missing from are left blank in the wide layout
+/// and left out of the long one, and the timestamp is left blank for datapoints without a recorded sample time.
+pub fn write_csv(path: &str, group: &GroupData, layout: CsvLayout) -> anyhow::Result<()> {
+    fs::write(path, csv(group, layout)?).with_context(|| format!("error writing {}", path))
+}
+
+fn csv(group: &GroupData, layout: CsvLayout) -> anyhow::Result<String> {
+    let mut out = String::from("timestamp,datapoint");
+    match layout {
+        CsvLayout::Wide => {
+            for key in group.series.keys() {
+                write!(out, ",{}", csv_field(key))?;
+            }
+            out.push('\n');
+
+            let mut rows = vec![vec![None; group.series.len()]; group.datapoints];
+            for (col, series) in group.series.values().enumerate() {
+                for (idx, val) in series {
+                    if let Some(row) = rows.get_mut(*idx) {
+                        row[col] = Some(*val);
+                    }
+                }
+            }
+            for (idx, row) in rows.iter().enumerate() {
+                write!(out, "{},{}", timestamp(group, idx).unwrap_or_default(), idx)?;
+                for val in row {
+                    match val {
+                        Some(val) => write!(out, ",{}", val)?,
+                        None => out.push(','),
+                    }
+                }
+                out.push('\n');
+            }
+        }
+        CsvLayout::Long => {
+            out.push_str(",key,value\n");
+            let mut rows: Vec<(usize, &str, f64)> = group.series.iter()
+            .flat_map(|(key, series)| series.iter().map(move |(idx, val)| (*idx, key.as_str(), *val)))
+            .collect();
+            // the sort is stable, so the metrics of each datapoint stay in name order
+            rows.sort_by_key(|(idx, _, _)| *idx);
+            for (idx, key, val) in rows {
+                writeln!(out, "{},{},{},{}", timestamp(group, idx).unwrap_or_default(), idx, csv_field(key), val)?;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// When a datapoint of a group was collected, as an RFC 3339 timestamp
+fn timestamp(group: &GroupData, datapoint: usize) -> Option<String> {
+    group.times.get(datapoint).copied().flatten()
+    .and_then(|ms| DateTime::from_timestamp_millis(ms as i64))
+    .map(|ts| ts.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// Write every group's series to a single JSON document, with the timestamp and value of every datapoint of every series,
+/// and the summary statistics of each series, for scripts to check a run's results with.
+pub fn write_json(path: &str, groups: &[GroupData]) -> anyhow::Result<()> {
+    let doc = serde_json::to_string_pretty(&json_doc(groups)).context("error encoding JSON")?;
+    fs::write(path, doc).with_context(|| format!("error writing {}", path))
+}
+
+fn json_doc(groups: &[GroupData]) -> Value {
+    let groups: Map<String, Value> = groups.iter().map(|group| {
+        let series: Map<String, Value> = group.series.iter().map(|(key, series)| {
+            let values: Vec<Value> = series.iter()
+            .map(|(idx, val)| json!({"datapoint": idx, "timestamp": timestamp(group, *idx), "value": val}))
+            .collect();
+            let summary = SeriesSummary::from_series(series).map(|stats| json!({
+                "count": stats.count, "min": stats.min, "max": stats.max, "mean": stats.mean, "last": stats.last,
+            }));
+            (key.clone(), json!({"values": values, "summary": summary}))
+        }).collect();
+        (group.name.clone(), json!({"datapoints": group.datapoints, "series": series}))
+    }).collect();
+
+    json!({"generated": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true), "groups": groups})
+}
+
+/// Write a standalone HTML page with a group's chart, and a table summarizing each of its series
+pub fn write_html(path: &str, group: &GroupData, svg: &str) -> anyhow::Result<()> {
+    let body = format!("<h1>{}</h1>\n{}\n{}", html_escape(&group.name), svg, summary_table(group)?);
+    fs::write(path, html_page(&group.name, &body)).with_context(|| format!("error writing {}", path))
+}
+
+/// Write a single self-contained HTML page with the chart and summary table of every group, linked from a table of contents.
+/// `states` is the `/state` document of each endpoint that served one, summarized at the top of the page.
+pub fn write_report(path: &str, groups: &[(&GroupData, Option<&str>)], states: &[(String, Map<String, Value>)]) -> anyhow::Result<()> {
+    let mut body = format!("<h1>beatperf report</h1>\n<p>Generated {}</p>\n", Utc::now().format("%Y-%m-%d %H:%M:%S UTC"));
+    if !states.is_empty() {
+        body.push_str(&state_table(states)?);
+    }
+    body.push_str("<ul>\n");
+    for (idx, (group, _)) in groups.iter().enumerate() {
+        writeln!(body, "<li><a href=\"#group-{}\">{}</a> ({} datapoints)</li>", idx, html_escape(&group.name), group.datapoints)?;
+    }
+    body.push_str("</ul>\n");
+
+    for (idx, (group, svg)) in groups.iter().enumerate() {
+        writeln!(body, "<h2 id=\"group-{}\">{}</h2>", idx, html_escape(&group.name))?;
+        match svg {
+            Some(svg) => body.push_str(svg),
+            None => body.push_str("<p>The chart could not be rendered.</p>"),
+        }
+        body.push('\n');
+        body.push_str(&summary_table(group)?);
+    }
+
+    fs::write(path, html_page("beatperf report", &body)).with_context(|| format!("error writing {}", path))
+}
+
+/// A table with the beat, host, output and queue of each endpoint, from their `/state`
+fn state_table(states: &[(String, Map<String, Value>)]) -> anyhow::Result<String> {
+    let field = |state: &Map<String, Value>, path: &str| match get_root_elem(state, path) {
+        Some(Value::String(val)) => html_escape(val),
+        Some(val) => val.to_string(),
+        None => String::new(),
+    };
+    let mut rows = String::new();
+    for (endpoint, state) in states {
+        let mut queue = field(state, "queue.name");
+        if let Some(max) = get_root_elem(state, "queue.max_events") {
+            write!(queue, " ({} events)", max)?;
+        }
+        writeln!(rows, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            html_escape(endpoint), field(state, "beat.name"), field(state, "beat.version"), field(state, "host.hostname"), field(state, "output.name"), queue)?;
+    }
+
+    Ok(format!("<table>
+<tr><th>endpoint</th><th>beat</th><th>version</th><th>host</th><th>output</th><th>queue</th></tr>
+{}</table>
+", rows))
+}
+
+/// A table with the summary statistics of each of a group's series
+fn summary_table(group: &GroupData) -> anyhow::Result<String> {
+    let mut rows = String::new();
+    for (key, series) in &group.series {
+        let Some(stats) = SeriesSummary::from_series(series) else {
+            continue;
+        };
+        writeln!(rows, "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td><td>{:.2}</td></tr>",
+            html_escape(key), stats.count, stats.min, stats.max, stats.mean, stats.last)?;
+    }
+
+    Ok(format!("<table>
+<tr><th>metric</th><th>count</th><th>min</th><th>max</th><th>mean</th><th>last</th></tr>
+{}</table>
+", rows))
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.8em; text-align: right; }}
+th:first-child, td:first-child {{ text-align: left; }}
+</style>
+</head>
+<body>
+{body}</body>
+</html>
+"#, title = html_escape(title))
+}
+
+/// Quote a CSV field if it contains anything that would break the row
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Excel limits sheet names to 31 characters, and doesn't allow some characters
+#[cfg(feature = "xlsx")]
+fn sheet_name(name: &str) -> String {
+    name.chars()
+    .map(|c| if matches!(c, '[' | ']' | ':' | '*' | '?' | '/' | '\\') { '_' } else { c })
+    .take(MAX_SHEET_NAME)
+    .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::groups::GroupData;
+
+    use serde_json::json;
+
+    use super::{csv, csv_field, json_doc, CsvLayout, FileName, FormatSpec, OutputFormat, SeriesSummary};
+
+    #[test]
+    fn test_summary() {
+        let stats = SeriesSummary::from_series(&vec![(0, 2.0), (1, 6.0), (3, 1.0)]);
+        assert_eq!(stats, Some(SeriesSummary { count: 3, min: 1.0, max: 6.0, mean: 3.0, last: 1.0 }));
+        assert_eq!(SeriesSummary::from_series(&vec![]), None);
+    }
+
+    #[test]
+    fn test_format_spec() {
+        assert_eq!("pipeline=html,csv".parse::<FormatSpec>().unwrap(),
+            FormatSpec { group: Some("pipeline".to_string()), formats: vec![OutputFormat::Html, OutputFormat::Csv(CsvLayout::Wide)] });
+        assert_eq!("csv-long".parse::<FormatSpec>().unwrap(), FormatSpec { group: None, formats: vec![OutputFormat::Csv(CsvLayout::Long)] });
+        assert!("memory=pdf".parse::<FormatSpec>().is_err());
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!("output=output_events".parse::<FileName>().unwrap(), FileName { group: "output".to_string(), name: "output_events".to_string() });
+        assert!("output".parse::<FileName>().is_err());
+        assert!("output=".parse::<FileName>().is_err());
+        assert!("output=../events".parse::<FileName>().is_err());
+    }
+
+    #[test]
+    fn test_csv() -> anyhow::Result<()> {
+        let group = GroupData {
+            name: "custom".to_string(),
+            datapoints: 2,
+            series: BTreeMap::from([("b".to_string(), vec![(0, 1.5), (1, 2.0)]), ("a".to_string(), vec![(1, 7.0)])]),
+            times: vec![Some(1700000000000.0), None],
+        };
+        assert_eq!(csv(&group, CsvLayout::Long)?, "timestamp,datapoint,key,value\n2023-11-14T22:13:20.000Z,0,b,1.5\n,1,a,7\n,1,b,2\n");
+        assert_eq!(csv(&group, CsvLayout::Wide)?, "timestamp,datapoint,a,b\n2023-11-14T22:13:20.000Z,0,,1.5\n,1,7,2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_doc() {
+        let group = GroupData {
+            name: "custom".to_string(),
+            datapoints: 2,
+            series: BTreeMap::from([("b".to_string(), vec![(0, 1.5), (1, 2.5)])]),
+            times: vec![Some(1700000000000.0), None],
+        };
+        let doc = json_doc(&[group]);
+        assert_eq!(doc["groups"]["custom"]["datapoints"], 2);
+        assert_eq!(doc["groups"]["custom"]["series"]["b"], json!({
+            "values": [{"datapoint": 0, "timestamp": "2023-11-14T22:13:20.000Z", "value": 1.5}, {"datapoint": 1, "timestamp": null, "value": 2.5}],
+            "summary": {"count": 2, "min": 1.5, "max": 2.5, "mean": 2.0, "last": 2.5},
+        }));
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn test_sheet_name() {
+        use super::sheet_name;
+
+        assert_eq!(sheet_name("a/b"), "a_b");
+        assert_eq!(sheet_name(&"x".repeat(40)).len(), 31);
+    }
+}
@@ -0,0 +1,91 @@
+/*!
+ * Reads stats from Go's expvar endpoint, `/debug/vars`, for beats whose `/stats` endpoint is restricted.
+ * The expvar document is reshaped to look like a stats document, so every group works on it unchanged:
+ * dot-separated variable names are nested, and the runtime's memstats are copied to where a beat reports them.
+ */
+
+use serde_json::{Map, Value};
+
+/// The path the expvar endpoint is served at
+pub const PATH: &str = "/debug/vars";
+
+/// Go runtime memstats, and the `beat.memstats` metrics they correspond to
+const MEMSTATS: [(&str, &str); 4] = [
+    ("Alloc", "memory_alloc"),
+    ("TotalAlloc", "memory_total"),
+    ("Sys", "memory_sys"),
+    ("NextGC", "gc_next"),
+];
+
+/// true if a URL points at an expvar endpoint, instead of a beat's stats
+pub fn is_expvar(url: &str) -> bool {
+    url.ends_with(PATH)
+}
+
+/// Reshape an expvar document into a stats document.
+/// The runtime's memstats are kept under `memstats`, without the per-GC and per-size-class arrays, which are large and can't be charted.
+pub fn to_stats(vars: Map<String, Value>) -> Map<String, Value> {
+    let mut doc = Map::new();
+    for (key, val) in vars {
+        let val = match (key.as_str(), val) {
+            ("memstats", Value::Object(memstats)) => Value::Object(memstats.into_iter().filter(|(_, v)| !v.is_array()).collect()),
+            (_, val) => val,
+        };
+        insert_nested(&mut doc, &key, val);
+    }
+
+    let memstats: Map<String, Value> = MEMSTATS.iter()
+    .filter_map(|(go, beat)| Some((beat.to_string(), doc.get("memstats")?.get(go)?.clone())))
+    .collect();
+    if !memstats.is_empty() {
+        insert_nested(&mut doc, "beat.memstats", Value::Object(memstats));
+    }
+    doc
+}
+
+/// Insert a value at a dot-notation key, merging it with any maps already on the way
+fn insert_nested(doc: &mut Map<String, Value>, key: &str, val: Value) {
+    let (head, rest) = match key.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (key, None),
+    };
+    match (rest, doc.get_mut(head), val) {
+        (Some(rest), Some(Value::Object(inner)), val) => insert_nested(inner, rest, val),
+        (Some(rest), _, val) => {
+            let mut inner = Map::new();
+            insert_nested(&mut inner, rest, val);
+            doc.insert(head.to_string(), Value::Object(inner));
+        }
+        (None, Some(Value::Object(existing)), Value::Object(new)) => {
+            for (inner_key, inner_val) in new {
+                insert_nested(existing, &inner_key, inner_val);
+            }
+        }
+        (None, _, val) => {
+            doc.insert(head.to_string(), val);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::to_stats;
+
+    #[test]
+    fn test_to_stats() {
+        let vars = json!({
+            "cmdline": ["filebeat", "-e"],
+            "memstats": {"Alloc": 100, "TotalAlloc": 500, "Sys": 1000, "NextGC": 200, "NumGC": 3, "PauseNs": [1, 2, 3]},
+            "libbeat.pipeline.events.total": 42,
+            "libbeat.pipeline.events.active": 2,
+        }).as_object().unwrap().clone();
+
+        let doc = to_stats(vars);
+        assert_eq!(doc["libbeat"]["pipeline"]["events"], json!({"total": 42, "active": 2}));
+        assert_eq!(doc["beat"]["memstats"], json!({"memory_alloc": 100, "memory_total": 500, "memory_sys": 1000, "gc_next": 200}));
+        assert_eq!(doc["memstats"], json!({"Alloc": 100, "TotalAlloc": 500, "Sys": 1000, "NextGC": 200, "NumGC": 3}));
+        assert_eq!(doc["cmdline"], json!(["filebeat", "-e"]));
+    }
+}
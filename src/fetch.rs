@@ -0,0 +1,395 @@
+/*!
+ * Fetches stats from a beat's HTTP endpoint, timing each phase of the request.
+ * A "slow beat" sometimes turns out to be a slow network path to its monitoring endpoint, so the timings are added to every
+ * stats document under `beatperf.http`, where they can be charted like any other metric. The time the sample was taken is added
+ * as `beatperf.timestamp_ms`, so rates stay correct when the time between samples varies.
+ *
+ * Endpoints can be plain HTTP, or HTTPS when built with the `tls` feature.
+ */
+
+use std::{fmt, str::FromStr, time::{Duration, Instant}};
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST}, Method, Request, StatusCode, Uri};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Map, Value};
+use tokio::{io::{AsyncRead, AsyncWrite}, net::{lookup_host, TcpStream}, time};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(feature = "tls")]
+use tokio_native_tls::{native_tls::{self, Certificate}, TlsConnector};
+use tracing::debug;
+
+/// The key beatperf's own measurements are stored under in a stats document
+pub const META_KEY: &str = "beatperf";
+/// The path of the time a sample was taken, in milliseconds since the epoch
+pub const TIMESTAMP_PATH: &str = "beatperf.timestamp_ms";
+/// The path of the beat's last `/state` document, on the samples it was fetched alongside
+pub const STATE_PATH: &str = "beatperf.state";
+/// The path of filebeat's per-input metrics, keyed by input ID
+pub const INPUTS_PATH: &str = "beatperf.inputs";
+
+/// How long each phase of a request took. Every phase is measured from the start of the request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    /// Resolving the endpoint's hostname
+    pub dns: Duration,
+    /// Opening a TCP connection
+    pub connect: Duration,
+    /// Finishing the TLS handshake, for HTTPS endpoints
+    pub tls: Option<Duration>,
+    /// Receiving the response headers
+    pub ttfb: Duration,
+    /// Receiving the whole response body
+    pub total: Duration,
+}
+
+impl Timings {
+    /// The timings in milliseconds, in the same shape as a beat's own metrics
+    pub fn to_value(self) -> Value {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let mut timings = json!({
+            "dns_ms": ms(self.dns),
+            "connect_ms": ms(self.connect),
+            "ttfb_ms": ms(self.ttfb),
+            "total_ms": ms(self.total),
+        });
+        if let Some(tls) = self.tls {
+            timings["tls_ms"] = json!(ms(tls));
+        }
+        timings
+    }
+}
+
+/// Tag a stats document with the endpoint it came from, the request timings, and the current time
+pub fn add_meta(doc: &mut Map<String, Value>, endpoint: &str, timings: Timings) {
+    doc.insert(META_KEY.to_string(), json!({
+        "endpoint": endpoint,
+        "http": timings.to_value(),
+        "timestamp_ms": Utc::now().timestamp_millis(),
+    }));
+}
+
+/// Attach a beat's `/state` document to a stats document that's already been tagged with [`add_meta`]
+pub fn add_state(doc: &mut Map<String, Value>, state: Map<String, Value>) {
+    if let Some(Value::Object(meta)) = doc.get_mut(META_KEY) {
+        meta.insert("state".to_string(), Value::Object(state));
+    }
+}
+
+/// Attach filebeat's per-input metrics, the list served by `/inputs/`, to a stats document that's already been tagged with [`add_meta`].
+/// Each input is keyed by its ID, with any dots replaced, so the ID stays a single segment of a dot-notation key.
+pub fn add_inputs(doc: &mut Map<String, Value>, inputs: &[Value]) {
+    let by_id: Map<String, Value> = inputs.iter()
+    .filter_map(|input| Some((input.get("id")?.as_str()?.replace('.', "_"), input.clone())))
+    .collect();
+    if let Some(Value::Object(meta)) = doc.get_mut(META_KEY) {
+        meta.insert("inputs".to_string(), Value::Object(by_id));
+    }
+}
+
+/// The `/state` URL of the beat behind a stats URL, if it's a beat's own `/stats` endpoint
+pub fn state_url(stats_url: &str) -> Option<String> {
+    stats_url.strip_suffix("/stats").map(|base| format!("{}/state", base))
+}
+
+/// The `/inputs/` URL of the filebeat behind a stats URL, if it's a beat's own `/stats` endpoint
+pub fn inputs_url(stats_url: &str) -> Option<String> {
+    stats_url.strip_suffix("/stats").map(|base| format!("{}/inputs/", base))
+}
+
+/// GET a URL with the default settings, returning the response body and the timings of the request
+pub async fn get(url: &str) -> anyhow::Result<(String, Timings)> {
+    Client::default().get(url).await
+}
+
+/// Fetches stats documents. Clones share the same TLS settings.
+#[derive(Clone, Default)]
+pub struct Client {
+    /// How HTTPS connections are made. If unset, the system's defaults are used.
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConnector>,
+    /// Extra headers sent with every request, like credentials
+    headers: Vec<Header>,
+    /// If set, every request is sent over this unix socket instead of to the URL's host
+    unix_socket: Option<String>,
+    /// If set, requests that haven't finished after this long are abandoned with a [`TimedOut`] error
+    timeout: Option<Duration>,
+}
+
+/// The error of a request that was abandoned because it took longer than the client's timeout
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no response within {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A request header, parsed from `<name>: <value>`
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+}
+
+impl Header {
+    /// An `Authorization` header for HTTP basic auth
+    pub fn basic_auth(username: &str, password: &str) -> anyhow::Result<Header> {
+        Header::authorization(&format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password))))
+    }
+
+    /// An `Authorization` header with the given credentials, like `Bearer <token>`
+    pub fn authorization(credentials: &str) -> anyhow::Result<Header> {
+        let mut value = HeaderValue::from_str(credentials).context("invalid credentials")?;
+        value.set_sensitive(true);
+        Ok(Header { name: AUTHORIZATION, value })
+    }
+}
+
+impl FromStr for Header {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s.split_once(':').ok_or_else(|| anyhow!("headers must be formatted as <name>: <value>"))?;
+        let name = HeaderName::from_str(name.trim()).with_context(|| format!("invalid header name '{}'", name.trim()))?;
+        let value = HeaderValue::from_str(value.trim()).with_context(|| format!("invalid value for header {}", name))?;
+        Ok(Header { name, value })
+    }
+}
+
+impl Client {
+    /// A client that trusts the CA certificate in the given PEM file as well as the system's, or if `insecure` is set,
+    /// doesn't verify certificates at all
+    #[cfg(feature = "tls")]
+    pub fn with_tls(ca_cert: Option<&str>, insecure: bool) -> anyhow::Result<Client> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(path) = ca_cert {
+            let pem = std::fs::read(path).with_context(|| format!("error reading CA certificate {}", path))?;
+            builder.add_root_certificate(Certificate::from_pem(&pem).with_context(|| format!("invalid CA certificate {}", path))?);
+        }
+        if insecure {
+            builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+
+        Ok(Client { tls: Some(builder.build().context("error setting up TLS")?.into()), ..Client::default() })
+    }
+
+    /// Send every request over a unix socket, like beats listening on `unix:///var/run/filebeat.sock`.
+    /// The URL's host is still sent as the `Host` header.
+    pub fn with_unix_socket(mut self, path: &str) -> Client {
+        self.unix_socket = Some(path.to_string());
+        self
+    }
+
+    /// Send a header with every request
+    pub fn with_header(mut self, header: Header) -> Client {
+        self.headers.push(header);
+        self
+    }
+
+    /// Give up on any request that hasn't finished after this long, from connecting to reading the whole response
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// GET a URL over HTTP or HTTPS, returning the response body and the timings of the request
+    pub async fn get(&self, url: &str) -> anyhow::Result<(String, Timings)> {
+        let (body, timings) = self.get_bytes(url).await?;
+        Ok((String::from_utf8(body.to_vec()).context("response is not valid UTF-8")?, timings))
+    }
+
+    /// GET a URL like [`Client::get`], for responses that aren't text, like profiles
+    pub async fn get_bytes(&self, url: &str) -> anyhow::Result<(Bytes, Timings)> {
+        self.call(url, None).await
+    }
+
+    /// POST a body of the given content type to a URL, returning the response body
+    pub async fn post(&self, url: &str, content_type: &'static str, body: Vec<u8>) -> anyhow::Result<Bytes> {
+        let (body, _) = self.call(url, Some(Payload { content_type, body: body.into() })).await?;
+        Ok(body)
+    }
+
+    /// Make the request, giving up on it if it takes longer than the timeout
+    async fn call(&self, url: &str, payload: Option<Payload>) -> anyhow::Result<(Bytes, Timings)> {
+        match self.timeout {
+            Some(timeout) => time::timeout(timeout, self.connect(url, payload)).await.map_err(|_| TimedOut(timeout))?,
+            None => self.connect(url, payload).await,
+        }
+    }
+
+    /// Connect to the URL's host, or the unix socket, and make the request, a POST if there's a payload and a GET otherwise
+    async fn connect(&self, url: &str, payload: Option<Payload>) -> anyhow::Result<(Bytes, Timings)> {
+        let target = Target::parse(url)?;
+        let mut timings = Timings::default();
+        let start = Instant::now();
+
+        if let Some(path) = &self.unix_socket {
+            #[cfg(unix)]
+            {
+                let stream = UnixStream::connect(path).await.with_context(|| format!("could not connect to unix socket {}", path))?;
+                timings.connect = start.elapsed();
+                return self.send(stream, &target, payload, start, timings).await;
+            }
+            #[cfg(not(unix))]
+            return Err(anyhow!("can't connect to {}, unix sockets aren't supported on this platform", path));
+        }
+
+        let addrs: Vec<_> = lookup_host((target.bare_host(), target.port)).await.with_context(|| format!("error resolving {}", target.host))?.collect();
+        timings.dns = start.elapsed();
+
+        let mut stream = None;
+        for addr in &addrs {
+            match TcpStream::connect(addr).await {
+                Ok(conn) => {
+                    stream = Some(conn);
+                    break;
+                }
+                Err(e) => debug!("error connecting to {}: {}", addr, e),
+            }
+        }
+        let stream = stream.ok_or_else(|| anyhow!("could not connect to {}:{}", target.host, target.port))?;
+        timings.connect = start.elapsed();
+
+        self.send(stream, &target, payload, start, timings).await
+    }
+
+    /// Make the request over an open connection, starting a TLS session on it first for HTTPS
+    async fn send<S>(&self, stream: S, target: &Target, payload: Option<Payload>, start: Instant, timings: Timings) -> anyhow::Result<(Bytes, Timings)>
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+        if !target.https {
+            return request(stream, target, &self.headers, payload, start, timings).await;
+        }
+
+        #[cfg(feature = "tls")]
+        {
+            let connector = match &self.tls {
+                Some(connector) => connector.clone(),
+                None => native_tls::TlsConnector::new().context("error setting up TLS")?.into(),
+            };
+            let stream = connector.connect(target.bare_host(), stream).await.with_context(|| format!("TLS handshake with {} failed", target.host))?;
+            let timings = Timings { tls: Some(start.elapsed()), ..timings };
+            request(stream, target, &self.headers, payload, start, timings).await
+        }
+        #[cfg(not(feature = "tls"))]
+        Err(anyhow!("can't fetch {}, beatperf was built without the tls feature", target.uri))
+    }
+}
+
+/// The body of a POST request
+struct Payload {
+    content_type: &'static str,
+    body: Bytes,
+}
+
+/// Where a request is sent
+struct Target {
+    uri: Uri,
+    /// The host as it's written in the URL, with an IPv6 address in brackets, for the `Host` header
+    host: String,
+    port: u16,
+    https: bool,
+}
+
+impl Target {
+    fn parse(url: &str) -> anyhow::Result<Target> {
+        let uri: Uri = url.parse().with_context(|| format!("invalid URL {}", url))?;
+        let host = uri.host().ok_or_else(|| anyhow!("URL {} has no host", url))?.to_string();
+        let https = match uri.scheme_str() {
+            None | Some("http") => false,
+            Some("https") => true,
+            Some(other) => return Err(anyhow!("unsupported URL scheme {} in {}", other, url)),
+        };
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        Ok(Target { uri, host, port, https })
+    }
+
+    /// The host without the brackets around an IPv6 address, for resolving it and for TLS
+    fn bare_host(&self) -> &str {
+        self.host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(&self.host)
+    }
+}
+
+/// Send the request over an open connection, and read the response
+async fn request<S>(stream: S, target: &Target, headers: &[Header], payload: Option<Payload>, start: Instant, mut timings: Timings) -> anyhow::Result<(Bytes, Timings)>
+where S: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            debug!("connection closed with error: {}", e);
+        }
+    });
+
+    let path = target.uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let method = if payload.is_some() { Method::POST } else { Method::GET };
+    let mut req = Request::builder().method(method).uri(path).header(HOST, format!("{}:{}", target.host, target.port));
+    for header in headers {
+        req = req.header(&header.name, &header.value);
+    }
+    let req = match payload {
+        Some(payload) => req.header(CONTENT_TYPE, payload.content_type).body(Full::new(payload.body))?,
+        None => req.body(Full::new(Bytes::new()))?,
+    };
+    let res = sender.send_request(req).await?;
+    timings.ttfb = start.elapsed();
+
+    if res.status() == StatusCode::UNAUTHORIZED || res.status() == StatusCode::FORBIDDEN {
+        return Err(anyhow!("HTTP status {} from {}, check the credentials", res.status(), target.uri));
+    }
+    if !res.status().is_success() {
+        return Err(anyhow!("HTTP status {} from {}", res.status(), target.uri));
+    }
+    let body = res.into_body().collect().await?.to_bytes();
+    timings.total = start.elapsed();
+
+    Ok((body, timings))
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{add_inputs, inputs_url, state_url, Header, Target};
+
+    #[test]
+    fn test_header() {
+        let header: Header = "X-Opaque-Id:  beatperf ".parse().unwrap();
+        assert_eq!((header.name.as_str(), header.value.to_str().unwrap()), ("x-opaque-id", "beatperf"));
+        assert!("no-colon".parse::<Header>().is_err());
+        assert!("bad name: x".parse::<Header>().is_err());
+
+        let auth = Header::basic_auth("elastic", "changeme").unwrap();
+        assert_eq!(auth.value.to_str().unwrap(), "Basic ZWxhc3RpYzpjaGFuZ2VtZQ==");
+        assert!(auth.value.is_sensitive());
+    }
+
+    #[test]
+    fn test_target() {
+        let target = Target::parse("http://[::1]:5066/stats").unwrap();
+        assert_eq!((target.host.as_str(), target.bare_host(), target.port), ("[::1]", "::1", 5066));
+        let target = Target::parse("https://filebeat.internal/stats").unwrap();
+        assert_eq!((target.bare_host(), target.port, target.https), ("filebeat.internal", 443, true));
+    }
+
+    #[test]
+    fn test_state_url() {
+        assert_eq!(state_url("http://localhost:5066/stats").as_deref(), Some("http://localhost:5066/state"));
+        assert_eq!(state_url("http://localhost:6791/processes/filebeat-default"), None);
+        assert_eq!(inputs_url("http://localhost:5066/stats").as_deref(), Some("http://localhost:5066/inputs/"));
+    }
+
+    #[test]
+    fn test_add_inputs() {
+        let mut doc = json!({"beatperf": {"endpoint": "http://localhost:5066/stats"}}).as_object().unwrap().clone();
+        let inputs = [json!({"id": "filestream.logs", "events_processed_total": 10}), json!({"input": "no-id"})];
+        add_inputs(&mut doc, &inputs);
+        assert_eq!(doc["beatperf"]["inputs"], json!({"filestream_logs": {"id": "filestream.logs", "events_processed_total": 10}}));
+    }
+}
@@ -0,0 +1,241 @@
+/*!
+ * Annotations that are drawn on top of a group's charts, like user-supplied reference lines for a memory limit or a target EPS,
+ * spans of the run where the beat couldn't be reached, or labelled moments like the start of a load test.
+ */
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context};
+use num_traits::NumCast;
+use plotters::{coord::ranged1d::Ranged, prelude::*};
+
+use super::{theme::Theme, time_axis::TimeAxis};
+
+/// A horizontal reference line, parsed from `<group>[.<panel>]=<value>[:label]`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefLine {
+    /// The metric group the line is drawn on
+    pub group: String,
+    /// For groups with multiple panels, the panel the line is drawn on
+    pub panel: Option<String>,
+    /// The y-axis value, in the units shown on the chart
+    pub value: f64,
+    pub label: Option<String>,
+}
+
+impl FromStr for RefLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (target, rest) = s.split_once('=').ok_or_else(|| anyhow!("reference line must be formatted as <group>=<value>[:label]"))?;
+        let (value, label) = match rest.split_once(':') {
+            Some((value, label)) => (value, Some(label.to_string())),
+            None => (rest, None),
+        };
+        let value: f64 = value.trim().parse().with_context(|| format!("invalid reference line value '{}'", value))?;
+        let (group, panel) = match target.split_once('.') {
+            Some((group, panel)) => (group.to_string(), Some(panel.to_string())),
+            None => (target.to_string(), None),
+        };
+
+        Ok(RefLine { group, panel, value, label })
+    }
+}
+
+/// A span of datapoints highlighted across a chart, like an outage of the beat.
+/// A marker that ends where it starts is a single moment, see [`mark_event`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+    /// The first datapoint of the span
+    pub start: usize,
+    /// The datapoint after the end of the span
+    pub end: usize,
+    pub label: String,
+}
+
+/// The label used for datapoints that couldn't be collected
+pub const OUTAGE_LABEL: &str = "outage";
+
+/// The label of the datapoint a beat restarted at, which is drawn as a line instead of a shaded span
+pub const RESTART_LABEL: &str = "restart";
+
+/// The font size of an event's label
+const EVENT_FONT_SIZE: i32 = 12;
+/// How far the label of each event is below the last one's, in pixels
+const EVENT_LABEL_STEP: i32 = 14;
+/// How many events are labelled one under the other before starting again at the top
+const EVENT_LABEL_ROWS: i32 = 4;
+
+/// Record a missed datapoint as an outage, extending the previous outage if it ended right before this datapoint
+pub fn mark_outage(markers: &mut Vec<Marker>, datapoint: usize) {
+    mark_span(markers, datapoint, OUTAGE_LABEL);
+}
+
+/// Highlight a datapoint, extending the previous span with the same label if it ended right before this datapoint
+pub fn mark_span(markers: &mut Vec<Marker>, datapoint: usize, label: &str) {
+    if let Some(last) = markers.iter_mut().rfind(|marker| marker.label == label) {
+        if last.end == datapoint {
+            last.end += 1;
+            return;
+        }
+    }
+    markers.push(Marker { start: datapoint, end: datapoint + 1, label: label.to_string() });
+}
+
+/// Mark a labelled moment at a datapoint, like the start of a load generator, which is drawn as a labelled vertical line
+pub fn mark_event(markers: &mut Vec<Marker>, datapoint: usize, label: &str) {
+    markers.push(Marker { start: datapoint, end: datapoint, label: label.to_string() });
+}
+
+/// Move every span back by `dropped` datapoints, after that many were dropped from the front of a retention window.
+/// Spans that fall out of the window are removed, and spans that only partly do are cut at its start.
+pub fn slide_markers(markers: &mut Vec<Marker>, dropped: usize) {
+    if dropped == 0 {
+        return;
+    }
+    markers.retain(|marker| marker.end > dropped || marker.start >= dropped);
+    for marker in markers {
+        marker.start = marker.start.saturating_sub(dropped);
+        marker.end -= dropped;
+    }
+}
+
+/// Widen a y-axis range so all the reference lines are visible
+pub fn extend_range<T: NumCast + PartialOrd + Copy>(lines: &[RefLine], min: T, max: T) -> (T, T) {
+    lines.iter().filter_map(|line| T::from(line.value)).fold((min, max), |(min, max), val| {
+        (if val < min { val } else { min }, if val > max { val } else { max })
+    })
+}
+
+/// Draw reference lines as dashed lines across the whole chart, in the theme's text color
+pub fn draw_hlines<DB, Y>(chart: &mut ChartContext<'_, DB, Cartesian2d<TimeAxis, Y>>, lines: &[RefLine], datapoints: usize, color: RGBColor) -> anyhow::Result<()>
+where
+    DB: DrawingBackend<ErrorType: 'static>,
+    Y: Ranged,
+    Y::ValueType: NumCast + Clone + 'static,
+{
+    for line in lines {
+        let Some(y) = <Y::ValueType as NumCast>::from(line.value) else {
+            continue;
+        };
+        let series = chart.draw_series(DashedLineSeries::new(vec![(0, y.clone()), (datapoints, y)], 10, 5, color.stroke_width(2)))?;
+        if let Some(label) = &line.label {
+            series.label(label.clone())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], color.stroke_width(2)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Shade every marked span across the full height of the chart. The shading reaches back to the last datapoint before
+/// the span, so it covers the break in the chart's lines. Restarts are drawn as a dashed vertical line at the first sample after them,
+/// and events as a dotted line with their label at the top. Outages are shaded with a faint foreground color, so they show up on
+/// light and dark backgrounds alike.
+pub fn draw_markers<DB, Y>(chart: &mut ChartContext<'_, DB, Cartesian2d<TimeAxis, Y>>, markers: &[Marker], theme: &Theme) -> anyhow::Result<()>
+where
+    DB: DrawingBackend<ErrorType: 'static>,
+    Y: Ranged,
+    Y::ValueType: Clone + 'static,
+{
+    let y_range = chart.y_range();
+    let mut labelled: Vec<&str> = Vec::new();
+    let mut events = 0;
+    for marker in markers {
+        if marker.start == marker.end {
+            let line = vec![(marker.start, y_range.start.clone()), (marker.start, y_range.end.clone())];
+            chart.draw_series(DashedLineSeries::new(line, 3, 3, theme.foreground.stroke_width(1)))?;
+            // labels of nearby events are stepped down, so they don't overlap
+            let offset = (4, 4 + EVENT_LABEL_STEP * (events % EVENT_LABEL_ROWS));
+            let text = EmptyElement::at((marker.start, y_range.end.clone())) + Text::new(marker.label.clone(), offset, ("sans-serif", EVENT_FONT_SIZE).into_font().color(&theme.foreground));
+            chart.draw_series(std::iter::once(text))?;
+            events += 1;
+            continue;
+        }
+        if marker.label == RESTART_LABEL {
+            let line = vec![(marker.start, y_range.start.clone()), (marker.start, y_range.end.clone())];
+            let series = chart.draw_series(DashedLineSeries::new(line, 6, 4, BLUE.stroke_width(2)))?;
+            if !labelled.contains(&RESTART_LABEL) {
+                labelled.push(RESTART_LABEL);
+                series.label(RESTART_LABEL).legend(move |(x, y)| PathElement::new(vec![(x + 5, y - 5), (x + 5, y + 5)], BLUE.stroke_width(2)));
+            }
+            continue;
+        }
+        let area = [(marker.start.saturating_sub(1), y_range.start.clone()), (marker.end, y_range.end.clone())];
+        // outages are grey, anything else is something that went wrong while the beat was up, like a breached threshold
+        let style = if marker.label == OUTAGE_LABEL { theme.foreground.mix(0.1) } else { RED.mix(0.15) }.filled();
+        let series = chart.draw_series(std::iter::once(Rectangle::new(area, style)))?;
+        if !labelled.contains(&marker.label.as_str()) {
+            labelled.push(&marker.label);
+            series.label(marker.label.clone())
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], style));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extend_range, mark_event, mark_outage, mark_span, slide_markers, Marker, RefLine, OUTAGE_LABEL};
+
+    #[test]
+    fn test_parse_refline() -> anyhow::Result<()> {
+        let line: RefLine = "memory=500000:limit".parse()?;
+        assert_eq!(line, RefLine { group: "memory".to_string(), panel: None, value: 500000.0, label: Some("limit".to_string()) });
+
+        let line: RefLine = "pipeline.events=1000".parse()?;
+        assert_eq!(line, RefLine { group: "pipeline".to_string(), panel: Some("events".to_string()), value: 1000.0, label: None });
+
+        assert!("memory".parse::<RefLine>().is_err());
+        assert!("memory=lots".parse::<RefLine>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend_range() -> anyhow::Result<()> {
+        let lines = vec!["memory=50".parse()?, "memory=-5".parse()?];
+        assert_eq!(extend_range(&lines, 0.0, 10.0), (-5.0, 50.0));
+        // negative values can't be represented on an unsigned axis
+        assert_eq!(extend_range(&lines, 1u64, 10), (1, 50));
+        Ok(())
+    }
+
+    #[test]
+    fn test_mark_outage() {
+        let mut markers = Vec::new();
+        for datapoint in [3, 4, 5, 9] {
+            mark_outage(&mut markers, datapoint);
+        }
+        let outage = |start, end| Marker { start, end, label: OUTAGE_LABEL.to_string() };
+        assert_eq!(markers, vec![outage(3, 6), outage(9, 10)]);
+
+        // spans with different labels can overlap
+        mark_span(&mut markers, 10, "rss > 500MB");
+        mark_outage(&mut markers, 10);
+        mark_span(&mut markers, 11, "rss > 500MB");
+        let breach = Marker { start: 10, end: 12, label: "rss > 500MB".to_string() };
+        assert_eq!(markers, vec![outage(3, 6), outage(9, 11), breach]);
+
+        slide_markers(&mut markers, 4);
+        let breach = Marker { start: 6, end: 8, label: "rss > 500MB".to_string() };
+        assert_eq!(markers, vec![outage(0, 2), outage(5, 7), breach]);
+        slide_markers(&mut markers, 2);
+        assert_eq!(markers[0], outage(3, 5));
+    }
+
+    #[test]
+    fn test_mark_event() {
+        let mut markers = Vec::new();
+        mark_event(&mut markers, 4, "load started");
+        mark_event(&mut markers, 5, "load started");
+        let event = |at| Marker { start: at, end: at, label: "load started".to_string() };
+        assert_eq!(markers, vec![event(4), event(5)]);
+
+        // an event at the first datapoint left in the window is kept
+        slide_markers(&mut markers, 4);
+        assert_eq!(markers, vec![event(0), event(1)]);
+        slide_markers(&mut markers, 1);
+        assert_eq!(markers, vec![event(0)]);
+    }
+}
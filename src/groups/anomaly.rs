@@ -0,0 +1,102 @@
+/*!
+ * Flags samples that stand out from the datapoints before them, like a single GC spike in an hour-long capture.
+ * Each sample is scored against the median and median absolute deviation (MAD) of a trailing window,
+ * which, unlike a mean and standard deviation, the spikes themselves can't drag along.
+ */
+
+use std::collections::HashMap;
+
+use num_traits::ToPrimitive;
+
+use super::{transform::percentile, ChartOpts, GroupData};
+
+/// Scales the MAD to be comparable to a standard deviation, for normally distributed data
+const MAD_SCALE: f64 = 0.6745;
+
+/// The smallest spread a window is scored against, as a fraction of its median, so a metric that's been flat
+/// doesn't make the slightest wobble an anomaly
+const MIN_SPREAD: f64 = 0.01;
+
+/// A rolling modified z-score detector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Detector {
+    /// How many of the preceding datapoints each sample is compared to
+    pub window: usize,
+    /// The modified z-score above which a sample is an outlier
+    pub threshold: f64,
+}
+
+/// A sample flagged by a [`Detector`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outlier {
+    pub datapoint: usize,
+    pub value: f64,
+    /// How far the sample is from the median of its window, in scaled MADs. Negative for dips
+    pub score: f64,
+}
+
+/// An outlier in one of a group's series
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anomaly {
+    pub group: String,
+    pub key: String,
+    /// When the sample was collected, in milliseconds since the epoch, if the document says
+    pub time: Option<f64>,
+    pub outlier: Outlier,
+}
+
+impl Detector {
+    /// Score every sample with a full window of datapoints before it, returning the outliers.
+    /// Gaps in the series are skipped over, so the window is always the `window` samples before.
+    pub fn outliers<T: Copy + ToPrimitive>(&self, series: &[(usize, T)]) -> Vec<Outlier> {
+        let values: Vec<(usize, f64)> = series.iter().filter_map(|(idx, v)| Some((*idx, v.to_f64()?))).collect();
+        values.iter().enumerate().skip(self.window).filter_map(|(pos, (datapoint, value))| {
+            let window: Vec<f64> = values[pos - self.window..pos].iter().map(|(_, v)| *v).collect();
+            let median = percentile(&window, 50.0)?;
+            let deviations: Vec<f64> = window.iter().map(|v| (v - median).abs()).collect();
+            let spread = percentile(&deviations, 50.0)?.max(median.abs() * MIN_SPREAD);
+            if spread == 0.0 {
+                return None;
+            }
+            let score = MAD_SCALE * (value - median) / spread;
+            (score.abs() > self.threshold).then_some(Outlier { datapoint: *datapoint, value: *value, score })
+        }).collect()
+    }
+
+    /// Find the outliers in every series of a group, as the series are charted with `opts`
+    pub fn find(&self, group: &GroupData, opts: &ChartOpts) -> Vec<Anomaly> {
+        let series: HashMap<String, Vec<(usize, f64)>> = group.series.clone().into_iter().collect();
+        let mut found: Vec<Anomaly> = opts.prepare(series, &group.times).into_iter().flat_map(|(key, series)| {
+            self.outliers(&series).into_iter().map(move |outlier| Anomaly {
+                group: group.name.clone(),
+                key: key.clone(),
+                time: group.times.get(outlier.datapoint).copied().flatten(),
+                outlier,
+            })
+        }).collect();
+        found.sort_by(|a, b| a.outlier.datapoint.cmp(&b.outlier.datapoint).then_with(|| a.key.cmp(&b.key)));
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Detector;
+
+    #[test]
+    fn test_outliers() {
+        let detector = Detector { window: 5, threshold: 3.5 };
+        let mut series: Vec<(usize, f64)> = (0..20).map(|idx| (idx, 100.0 + (idx % 3) as f64)).collect();
+        series[12].1 = 400.0;
+        series[15].1 = 20.0;
+
+        let found = detector.outliers(&series);
+        assert_eq!(found.iter().map(|outlier| outlier.datapoint).collect::<Vec<_>>(), vec![12, 15]);
+        assert!(found[0].score > 0.0 && found[1].score < 0.0);
+
+        // a flat series only flags real changes
+        let flat: Vec<(usize, u64)> = (0..10).map(|idx| (idx, 50)).collect();
+        assert!(detector.outliers(&flat).is_empty());
+        assert!(detector.outliers(&series[..5]).is_empty());
+    }
+}
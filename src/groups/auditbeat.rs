@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess, Series}, Watcher};
+
+/// The auditd module's metrics: the audit messages received from the kernel, the ones lost on the way, and the kernel's backlog
+const AUDITD_KEY: &str = "auditd";
+
+/// The panels the auditd metrics are split into, as (panel name, chart title, name fragments).
+/// A metric goes in the first panel with a fragment in its name, or the first panel if none match.
+const FACETS: &[(&str, &str, &[&str])] = &[
+    ("received", "Received Messages", &[]),
+    ("lost", "Lost Events", &["lost", "gaps"]),
+    ("backlog", "Kernel Backlog", &["backlog"]),
+];
+
+/// Find the index of the panel a metric belongs in
+fn facet(key: &str) -> usize {
+    let name = key.trim_start_matches(AUDITD_KEY);
+    FACETS.iter().position(|(_, _, frags)| frags.iter().any(|frag| name.contains(frag))).unwrap_or(0)
+}
+
+pub struct Auditbeat {
+    group: Generic<u64, NoOpProcess<u64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+
+impl Watcher for Auditbeat {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::from(vec![AUDITD_KEY]).with_retain(opts.retain);
+        Auditbeat { group, fname: "auditbeat".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let map_data = self.opts.prepare(self.group.plot(), self.group.times());
+
+        let mut facets: Vec<HashMap<String, Series<f64>>> = vec![HashMap::new(); FACETS.len()];
+        for (key, series) in map_data {
+            facets[facet(&key)].insert(key, series);
+        }
+        let facets: Vec<_> = FACETS.iter().zip(facets).filter(|(_, map)| !map.is_empty()).collect();
+        if facets.len() <= 1 {
+            let map_data = facets.into_iter().flat_map(|(_, map)| map).collect();
+            return gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), self.group.times(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, AUDITD_KEY, &self.opts);
+        }
+
+        // the first panel with any data gets any reference lines that don't name a panel
+        let areas = root.split_evenly((facets.len(), 1));
+        for (idx, ((panel, title, _), map)) in facets.into_iter().enumerate() {
+            gen_events_graph(title.to_string(), map, self.group.datapoints(), self.group.times(), &areas[idx], DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, AUDITD_KEY, &self.opts.panel(panel, idx == 0))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{facet, AUDITD_KEY};
+
+    #[test]
+    fn test_facet() {
+        let panel = |name: &str| facet(&format!("{}.{}", AUDITD_KEY, name));
+        assert_eq!(panel("received_msgs"), 0);
+        assert_eq!(panel("kernel_lost"), 1);
+        assert_eq!(panel("userspace_lost"), 1);
+        assert_eq!(panel("reassembler_seq_gaps"), 1);
+        assert_eq!(panel("backlog"), 2);
+    }
+}
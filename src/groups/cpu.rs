@@ -0,0 +1,128 @@
+use plotters::{coord::Shift, prelude::*};
+
+use serde_json::{Map, Value};
+
+use crate::groups::*;
+use super::{generic::{DeltaProcessor, Generic}, Watcher};
+
+const CPU_KEY: &str = "beat.cpu";
+/// The milliseconds of CPU time the beat has used since it started, in user space, in the kernel, and both
+const TIMES: [&str; 3] = ["user.time.ms", "system.time.ms", "total.time.ms"];
+/// The cgroup's CPU quota, as microseconds of CPU time per period. A quota of -1, or none at all, means there's no limit
+const CFS_QUOTA_KEY: &str = "beat.cgroup.cpu.cfs.quota.us";
+const CFS_PERIOD_KEY: &str = "beat.cgroup.cpu.cfs.period.us";
+/// The number of cores on the host
+const HOST_CORES_KEY: &str = "system.cpu.cores";
+
+/// The beat's CPU usage, charted as a percentage of the cores available to it
+pub struct CpuMetrics {
+    group: Generic<f64, DeltaProcessor<f64>>,
+    /// The cores available to the beat in the latest sample that said, if any has
+    cores: Option<f64>,
+    fname: String,
+    opts: ChartOpts
+}
+
+/// The cores available to the beat: its cgroup's CPU quota if it has one, or else every core on the host
+fn available_cores(doc: &Map<String, Value>) -> Option<f64> {
+    let num = |key: &str| get_root_elem(doc, key).and_then(Value::as_f64);
+    match (num(CFS_QUOTA_KEY), num(CFS_PERIOD_KEY)) {
+        (Some(quota), Some(period)) if quota > 0.0 && period > 0.0 => Some(quota / period),
+        _ => num(HOST_CORES_KEY).filter(|cores| *cores > 0.0),
+    }
+}
+
+/// Turn milliseconds of CPU time per second into a percentage of the given number of cores
+fn to_pct(map: HashMap<String, Series<f64>>, cores: f64) -> HashMap<String, Series<f64>> {
+    map.into_iter().map(|(key, series)| (key, series.into_iter().map(|(idx, ms)| (idx, ms / 10.0 / cores)).collect())).collect()
+}
+
+impl Watcher for CpuMetrics {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let keys: Vec<String> = TIMES.iter().map(|time| format!("{}.{}", CPU_KEY, time)).collect();
+        let group = Generic::new(keys, DeltaProcessor::enabled(opts.deltas)).with_retain(opts.retain);
+        CpuMetrics { group, cores: None, fname: "cpu".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.group.update(new);
+        self.cores = available_cores(new).or(self.cores);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        if self.opts.deltas {
+            let opts = self.opts.clone().for_deltas();
+            let map_data = opts.prepare(self.group.plot(), self.group.times());
+            return gen_float_graph("CPU time (ms per sample)".to_string(), map_data, self.group.datapoints(), self.group.times(), root, "beat.cpu.", &|ms| format!("{:.0}", ms), &opts);
+        }
+        // CPU time only means something as a rate, whatever the other groups are charted as
+        let opts = ChartOpts { counters: CounterMode::Rate, ..self.opts.clone() };
+        // without a core count, usage is charted against a single core, so a beat using two full cores shows up at 200%
+        let title = match self.cores {
+            Some(cores) => format!("CPU (% of {} available cores)", (cores * 100.0).round() / 100.0),
+            None => "CPU (% of one core)".to_string(),
+        };
+        let map_data = to_pct(opts.prepare(self.group.plot(), self.group.times()), self.cores.unwrap_or(1.0));
+        gen_float_graph(title, map_data, self.group.datapoints(), self.group.times(), root, "beat.cpu.", &|pct| pct_formatter(*pct), &opts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::{available_cores, to_pct};
+
+    #[test]
+    fn test_to_pct() {
+        let map = HashMap::from([("beat.cpu.total.time.ms".to_string(), vec![(1, 250.0), (2, 1500.0)])]);
+        assert_eq!(to_pct(map.clone(), 1.0)["beat.cpu.total.time.ms"], vec![(1, 25.0), (2, 150.0)]);
+        assert_eq!(to_pct(map, 4.0)["beat.cpu.total.time.ms"], vec![(1, 6.25), (2, 37.5)]);
+    }
+
+    #[test]
+    fn test_available_cores() {
+        let doc = |value: serde_json::Value| value.as_object().unwrap().clone();
+        let host = json!({"system": {"cpu": {"cores": 8}}});
+        assert_eq!(available_cores(&doc(host.clone())), Some(8.0));
+
+        let mut limited = host.clone();
+        limited["beat"] = json!({"cgroup": {"cpu": {"cfs": {"period": {"us": 100000}, "quota": {"us": 150000}}}}});
+        assert_eq!(available_cores(&doc(limited)), Some(1.5));
+
+        // a quota of -1 means the cgroup isn't limited
+        let mut unlimited = host;
+        unlimited["beat"] = json!({"cgroup": {"cpu": {"cfs": {"period": {"us": 100000}, "quota": {"us": -1}}}}});
+        assert_eq!(available_cores(&doc(unlimited)), Some(8.0));
+        assert_eq!(available_cores(&doc(json!({}))), None);
+    }
+}
@@ -1,60 +1,197 @@
-use anyhow::Context;
-use plotters::prelude::*;
-use tracing::debug;
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
 
 use crate::groups::*;
-use super::{generic::{Generic, NoOpProcess}, Watcher};
+use super::{generic::{Generic, NoOpProcess, Series, Shape}, Watcher};
 
 
 pub struct CustomMetrics {
     group: Generic<f64, NoOpProcess<f64>>,
+    /// Friendly names for metrics, from fields given as `<alias>=<key>`
+    aliases: Vec<Alias>,
     fname: String,
+    opts: ChartOpts,
+}
+
+/// A friendly name for a metric, or for every metric under a subtree or glob
+#[derive(Clone, Debug, PartialEq)]
+struct Alias {
+    name: String,
+    /// The key the name replaces: the metric's key, or for a glob, the part before the first wildcard, up to a dot
+    stem: String,
 }
 
+impl Alias {
+    /// Split a field given as `<alias>=<key>` into its alias and key. Keys never contain `=`, so a field without one is just a key
+    fn parse(field: &str) -> (Option<Alias>, String) {
+        let Some((name, key)) = field.split_once('=') else {
+            return (None, field.to_string());
+        };
+        let (name, key) = (name.trim(), key.trim());
+        if name.is_empty() {
+            return (None, key.to_string());
+        }
+        let stem = match key.find(['*', '?']) {
+            Some(wildcard) => key[..wildcard].rsplit_once('.').map_or("", |(stem, _)| stem),
+            None => key,
+        };
+        (Some(Alias { name: name.to_string(), stem: stem.trim_start_matches('.').to_string() }), key.to_string())
+    }
+
+    /// The aliased name of a metric, if the alias covers it
+    fn label(&self, key: &str) -> Option<String> {
+        if key == self.stem {
+            return Some(self.name.clone());
+        }
+        key.strip_prefix(&self.stem).and_then(|rest| rest.strip_prefix('.')).filter(|_| !self.stem.is_empty())
+        .map(|rest| format!("{}.{}", self.name, rest))
+    }
+}
 
 impl Watcher for CustomMetrics {
-    fn new(fields: Option<Vec<String>>) -> Self {
+    fn new(fields: Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let (aliases, keys): (Vec<Option<Alias>>, Vec<String>) = fields.unwrap_or_else(|| vec![".beat.runtime.goroutines".to_string()])
+        .iter().map(|field| Alias::parse(field)).unzip();
+        let group = Generic::from(keys).with_filter(opts.filter.clone()).with_retain(opts.retain);
 
-        let group = if let Some(mf) = fields {
-            Generic::from(mf)
-        } else {
-            Generic::from(vec![".beat.runtime.goroutines"])
-        };
-        
-        CustomMetrics { fname: "custom".to_string(), group }
+        CustomMetrics { fname: "custom".to_string(), group, aliases: aliases.into_iter().flatten().collect(), opts }
     }
 
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
         self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn add_metric(&mut self, key: &str) -> bool {
+        let (alias, key) = Alias::parse(key);
+        self.aliases.extend(alias);
+        self.group.add_metric(key);
+        true
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
     }
 
-    fn plot(&self) -> anyhow::Result<()> {
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
         let map_data = self.group.plot();
+        let shapes = self.group.shapes();
+        if shapes.is_empty() {
+            return self.draw_lines(self.opts.prepare(map_data, self.group.times()), root);
+        }
 
-        let name = format!("./{}_plot.svg", &self.fname);
-        debug!("writing {}...", name);
-    
-        let (min, max) = get_min_max_float(&map_data)?;
+        // histogram-like metrics get their own panels, everything else is drawn as lines
+        let lines: HashMap<String, Series<f64>> = map_data.iter()
+        .filter(|(k, _)| !shapes.iter().any(|shape| k.starts_with(&format!("{}.", shape.key()))))
+        .map(|(k, v)| (k.clone(), v.clone())).collect();
+        let lines = self.opts.prepare(lines, self.group.times());
+
+        let panels = shapes.len() + usize::from(!lines.is_empty());
+        let areas = root.split_evenly((panels, 1));
+        let mut areas = areas.iter();
+        if !lines.is_empty() {
+            if let Some(area) = areas.next() {
+                self.draw_lines(lines, area)?;
+            }
+        }
+
+        for (shape, area) in shapes.iter().zip(areas) {
+            match shape {
+                Shape::Summary { key } => gen_band_graph(&map_data, key, self.group.datapoints(), self.group.times(), area, &self.opts)?,
+                Shape::Buckets { key, bounds } => gen_heatmap_graph(&map_data, key, bounds, self.group.datapoints(), self.group.times(), area, &self.opts)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CustomMetrics {
+    /// The name a metric is charted under: its alias, if it has one, or its key
+    fn label(&self, key: String) -> String {
+        self.aliases.iter().find_map(|alias| alias.label(&key)).unwrap_or(key)
+    }
 
-        let root = SVGBackend::new(&name, SVG_SIZE).into_drawing_area();
-        root.fill(&WHITE)?;
+    /// Draw plain metrics as individual lines
+    fn draw_lines<DB: DrawingBackend<ErrorType: 'static>>(&self, map_data: HashMap<String, Series<f64>>, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let map_data: HashMap<String, Series<f64>> = map_data.into_iter().map(|(key, series)| (self.label(key), series)).collect();
+        if self.opts.facet {
+            return gen_small_multiples(self.fname.clone(), &map_data, self.group.datapoints(), self.group.times(), root, "", &self.opts);
+        }
+        if self.opts.stack {
+            return gen_stacked_graph(self.fname.clone(), &map_data, self.group.datapoints(), self.group.times(), root, "", &|v| format!("{}", v), &self.opts);
+        }
+
+        let (min, max) = get_min_max_float(&map_data)?;
+        let max = self.opts.clamp_max(&map_data, max);
+        let (min, max) = extend_range(&self.opts.hlines, min, max);
     
-        let mut chart = setup_graph(self.fname.clone(), &root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT);
-        let mut chart_con = chart.build_cartesian_2d(0usize..self.group.datapoints(), min..max)?;
+        let mut chart = setup_graph(self.fname.clone(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, &self.opts.theme);
+        let x_axis = TimeAxis::new(self.group.times(), self.group.datapoints());
+        let x_desc = x_axis.desc();
+        let mut chart_con = chart.build_cartesian_2d(x_axis, min..max)?;
     
-        chart_con.configure_mesh().x_desc("Datapoints").y_desc("Values").draw()?;
+        self.opts.theme.mesh(&mut chart_con.configure_mesh()).x_desc(x_desc).y_desc("Values").draw()?;
+        draw_markers(&mut chart_con, &self.opts.markers, &self.opts.theme)?;
     
-        for (idx, (name, group)) in map_data.iter().enumerate() {
-            let color = Palette99::pick(idx).mix(0.9);
-            chart_con.draw_series(LineSeries::new(group.iter().enumerate().map(|(p_idx, d)| (p_idx, *d)), color.stroke_width(2)))?
+        for (name, color) in self.opts.colors(map_data.keys()) {
+            let color = color.mix(0.9);
+            draw_line(&mut chart_con, &map_data[name], color.stroke_width(2), &self.opts)?
             .label(name)
             .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
         }
+        draw_hlines(&mut chart_con, &self.opts.hlines, self.group.datapoints(), self.opts.theme.foreground)?;
     
-        chart_con.configure_series_labels().border_style(BLACK).background_style(WHITE.mix(0.8)).position(SeriesLabelPosition::UpperLeft).draw()?;
-    
-        root.present().context("could not write file")?;
+        self.opts.theme.legend(&mut chart_con.configure_series_labels()).draw()?;
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::Alias;
+
+    #[test]
+    fn test_alias() {
+        let (alias, key) = Alias::parse("goroutines=beat.runtime.goroutines");
+        let alias = alias.unwrap();
+        assert_eq!(key, "beat.runtime.goroutines");
+        assert_eq!(alias.label("beat.runtime.goroutines").as_deref(), Some("goroutines"));
+        assert_eq!(alias.label("beat.runtime.goroutines_total"), None);
+
+        let (alias, _) = Alias::parse("out = libbeat.output.events");
+        assert_eq!(alias.unwrap().label("libbeat.output.events.acked").as_deref(), Some("out.acked"));
+
+        let (alias, key) = Alias::parse("heap=beat.memstats.*alloc*");
+        assert_eq!(key, "beat.memstats.*alloc*");
+        assert_eq!(alias.unwrap().label("beat.memstats.memory_alloc").as_deref(), Some("heap.memory_alloc"));
+
+        assert_eq!(Alias::parse("beat.runtime.goroutines"), (None, "beat.runtime.goroutines".to_string()));
+        // a glob with no literal part before its wildcard has nothing to replace
+        assert_eq!(Alias::parse("all=*.bytes").0.unwrap().label("libbeat.output.write.bytes"), None);
+    }
+}
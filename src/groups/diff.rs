@@ -0,0 +1,168 @@
+/*!
+ * Compares two captures of the same group, like before and after a beat upgrade.
+ * Both runs are charted on top of each other, one panel per metric, with the runs lined up at their first sample.
+ */
+
+use std::{collections::{BTreeSet, HashMap}, fmt::Write as _, fs};
+
+use anyhow::Context;
+use plotters::{coord::Shift, prelude::*};
+use tracing::debug;
+
+use crate::export::csv_field;
+
+use super::{draw_line, generic::Series, get_min_max_float, time_axis::TimeAxis, ChartOpts, GroupData, FACET_NAME_FONT_SIZE, LABEL_SIZE_LEFT, SVG_SIZE};
+
+/// The height of a single metric's panel
+const PANEL_HEIGHT: u32 = 160;
+
+/// How the mean of a single metric changed between two runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricDelta {
+    pub key: String,
+    /// The mean in the first run, if the metric was seen in it
+    pub before: Option<f64>,
+    /// The mean in the second run, if the metric was seen in it
+    pub after: Option<f64>,
+}
+
+impl MetricDelta {
+    /// The change from the first run to the second, as a percentage of the first
+    pub fn change_pct(&self) -> Option<f64> {
+        match (self.before, self.after) {
+            (Some(before), Some(after)) if before != 0.0 => Some((after - before) / before.abs() * 100.0),
+            _ => None,
+        }
+    }
+}
+
+/// Two runs of the same group, prepared for charting
+pub struct GroupDiff {
+    pub name: String,
+    before: HashMap<String, Series<f64>>,
+    after: HashMap<String, Series<f64>>,
+    /// Every metric seen in either run, in order
+    keys: Vec<String>,
+    opts: ChartOpts,
+}
+
+impl GroupDiff {
+    /// Line up two runs of a group, charting counters as the chart options say
+    pub fn new(before: &GroupData, after: &GroupData, opts: ChartOpts) -> GroupDiff {
+        let prepare = |group: &GroupData| opts.prepare(group.series.clone().into_iter().collect(), &group.times);
+        let (name, before, after) = (before.name.clone(), prepare(before), prepare(after));
+        let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+        let keys = keys.into_iter().cloned().collect();
+        GroupDiff { name, before, after, keys, opts }
+    }
+
+    /// How the mean of every metric changed
+    pub fn deltas(&self) -> Vec<MetricDelta> {
+        self.keys.iter().map(|key| MetricDelta {
+            key: key.clone(),
+            before: self.before.get(key).and_then(mean),
+            after: self.after.get(key).and_then(mean),
+        }).collect()
+    }
+
+    /// Write the overlaid chart as an SVG
+    pub fn plot(&self, path: &str) -> anyhow::Result<()> {
+        debug!("writing {}...", path);
+        let height = SVG_SIZE.1.max(PANEL_HEIGHT * self.keys.len() as u32);
+        let root = SVGBackend::new(path, (SVG_SIZE.0, height)).into_drawing_area();
+        root.fill(&self.opts.theme.background)?;
+        self.draw(&root).context("could not draw diff")?;
+        root.present().context("could not write file")?;
+        Ok(())
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let root = root.titled(&format!("{}: before vs after", self.name), self.opts.theme.font(24).into_text_style(root))?;
+        let panels = root.split_evenly((self.keys.len().max(1), 1));
+        let (before_color, after_color) = (self.opts.color(0), self.opts.color(1));
+        let empty = Vec::new();
+
+        for (idx, (key, panel)) in self.keys.iter().zip(panels.iter()).enumerate() {
+            let before = self.before.get(key).unwrap_or(&empty);
+            let after = self.after.get(key).unwrap_or(&empty);
+            let both = HashMap::from([("before".to_string(), before.clone()), ("after".to_string(), after.clone())]);
+            let (min, max) = get_min_max_float(&both).unwrap_or((0.0, 0.0));
+            // a flat series still needs some height to be drawn in
+            let max = if max > min { max } else { min + 1.0 };
+            let datapoints = both.values().flatten().map(|(x, _)| x + 1).max().unwrap_or(1);
+            let last = idx == self.keys.len() - 1;
+
+            let mut chart = ChartBuilder::on(panel);
+            chart.caption(key, self.opts.theme.font(FACET_NAME_FONT_SIZE))
+            .set_label_area_size(LabelAreaPosition::Left, (LABEL_SIZE_LEFT).percent())
+            .set_label_area_size(LabelAreaPosition::Bottom, if last { 20 } else { 0 })
+            .margin(5);
+            let mut chart_con = chart.build_cartesian_2d(TimeAxis::new(&[], datapoints), min..max)?;
+            // the runs were taken at different times, so the only thing they share is the number of samples since they started
+            self.opts.theme.mesh(&mut chart_con.configure_mesh()).y_labels(3).x_label_formatter(&|x| if last { x.to_string() } else { String::new() }).draw()?;
+
+            if !before.is_empty() {
+                draw_line(&mut chart_con, before, before_color.mix(0.9).stroke_width(2), &self.opts)?
+                .label("before")
+                .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], before_color.filled()));
+            }
+            if !after.is_empty() {
+                draw_line(&mut chart_con, after, after_color.mix(0.9).stroke_width(2), &self.opts)?
+                .label("after")
+                .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], after_color.filled()));
+            }
+            if idx == 0 {
+                self.opts.theme.legend(&mut chart_con.configure_series_labels()).draw()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the change of every metric in every group as CSV, one row per metric
+pub fn write_summary(path: &str, diffs: &[GroupDiff]) -> anyhow::Result<()> {
+    let num = |val: Option<f64>| val.map(|v| format!("{:.4}", v)).unwrap_or_default();
+    let mut out = String::from("group,metric,before_mean,after_mean,change_pct\n");
+    for diff in diffs {
+        for delta in diff.deltas() {
+            writeln!(out, "{},{},{},{},{}", csv_field(&diff.name), csv_field(&delta.key), num(delta.before), num(delta.after), num(delta.change_pct()))?;
+        }
+    }
+
+    fs::write(path, out).with_context(|| format!("error writing {}", path))
+}
+
+fn mean(series: &Series<f64>) -> Option<f64> {
+    (!series.is_empty()).then(|| series.iter().map(|(_, v)| v).sum::<f64>() / series.len() as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::groups::{transform::CounterMode, ChartOpts, GroupData};
+
+    use super::{GroupDiff, MetricDelta};
+
+    #[test]
+    fn test_deltas() {
+        let group = |series: Vec<(&str, Vec<(usize, f64)>)>| GroupData {
+            name: "custom".to_string(),
+            datapoints: 2,
+            series: series.into_iter().map(|(k, v)| (k.to_string(), v)).collect::<BTreeMap<_, _>>(),
+            times: Vec::new(),
+        };
+        let before = group(vec![("rss", vec![(0, 100.0), (1, 300.0)]), ("gone", vec![(0, 1.0)])]);
+        let after = group(vec![("rss", vec![(0, 250.0), (1, 250.0)])]);
+        let diff = GroupDiff::new(&before, &after, ChartOpts { counters: CounterMode::Raw, ..Default::default() });
+
+        let deltas = diff.deltas();
+        assert_eq!(deltas, vec![
+            MetricDelta { key: "gone".to_string(), before: Some(1.0), after: None },
+            MetricDelta { key: "rss".to_string(), before: Some(200.0), after: Some(250.0) },
+        ]);
+        assert_eq!(deltas[1].change_pct(), Some(25.0));
+        assert_eq!(deltas[0].change_pct(), None);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess}, memory::MemoryProcessor, Watcher};
+use plotters::coord::Shift;
+
+const EVENTS_KEY: &str = "libbeat.output.events";
+const EVENTS: [&str; 5] = ["acked", "active", "batches", "failed", "duplicates"];
+const BYTES: [&str; 2] = ["libbeat.output.read.bytes", "libbeat.output.write.bytes"];
+const ERRORS: [&str; 2] = ["libbeat.output.read.errors", "libbeat.output.write.errors"];
+
+/// The output's own view of how it's doing, beyond the event counts charted by [`super::output::Output`]:
+/// the events it's acked, retried and failed, and the bytes and errors on its connection to Elasticsearch
+pub struct EsOutput {
+    events: Generic<u64, NoOpProcess<u64>>,
+    bytes: Generic<f64, MemoryProcessor>,
+    errors: Generic<u64, NoOpProcess<u64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for EsOutput {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let events = Generic::from(EVENTS.iter().map(|name| format!("{}.{}", EVENTS_KEY, name)).collect::<Vec<_>>()).with_retain(opts.retain);
+        let bytes = Generic::from(BYTES.to_vec()).with_retain(opts.retain);
+        let errors = Generic::from(ERRORS.to_vec()).with_retain(opts.retain);
+        EsOutput { events, bytes, errors, fname: "es_output".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.events.update(new);
+        self.bytes.update(new);
+        self.errors.update(new);
+        self.opts.slide(self.events.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.events.export();
+        series.extend(self.bytes.export());
+        series.extend(self.errors.export());
+        GroupData { name: self.fname.clone(), datapoints: self.events.datapoints(), series, times: self.events.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.events.datapoints());
+        self.events.skip();
+        self.bytes.skip();
+        self.errors.skip();
+        self.opts.slide(self.events.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.events.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.events.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper, lower) = root.split_vertically(height / 2);
+        let (bytes_area, errors_area) = lower.split_vertically(height / 4);
+
+        let map_events = self.opts.prepare(self.events.plot(), self.events.times());
+        gen_events_graph("Output Events".to_string(), map_events, self.events.datapoints(), self.events.times(), &upper, 5, 18, EVENTS_KEY, &self.opts.panel("events", true))?;
+
+        let map_bytes = self.opts.prepare(self.bytes.plot(), self.bytes.times());
+        gen_bytes_graph("Bytes".to_string(), map_bytes, self.bytes.datapoints(), self.bytes.times(), &bytes_area, "libbeat.output.", &self.opts.panel("bytes", false))?;
+
+        let map_errors = self.opts.prepare(self.errors.plot(), self.errors.times());
+        gen_events_graph("Errors".to_string(), map_errors, self.errors.datapoints(), self.errors.times(), &errors_area, 5, 18, "libbeat.output", &self.opts.panel("errors", false))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess}, palette::ChartPalette, transform::CounterMode, Watcher};
+
+const OUTPUT_KEY: &str = "libbeat.output.events";
+const OUTPUT: [&str; 3] = ["failed", "dropped", "toomany"];
+const PIPELINE_KEY: &str = "libbeat.pipeline.events";
+const PIPELINE: [&str; 3] = ["retry", "failed", "dropped"];
+
+/// Only the events that didn't make it, from the output and the pipeline, so a retry storm can't hide behind a healthy-looking events chart.
+/// Everything is charted as a per-second rate.
+pub struct Failures {
+    output: Generic<f64, NoOpProcess<f64>>,
+    pipeline: Generic<f64, NoOpProcess<f64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Failures {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let keys = |root: &str, names: &[&str]| names.iter().map(|name| format!("{}.{}", root, name)).collect::<Vec<_>>();
+        // keep a colorblind palette if one was asked for
+        let palette = if opts.palette == ChartPalette::Default { ChartPalette::Alert } else { opts.palette };
+        let opts = ChartOpts { palette, counters: CounterMode::Rate, ..opts };
+        Failures { output: Generic::from(keys(OUTPUT_KEY, &OUTPUT)).with_retain(opts.retain), pipeline: Generic::from(keys(PIPELINE_KEY, &PIPELINE)).with_retain(opts.retain), fname: "failures".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.output.update(new);
+        self.pipeline.update(new);
+        self.opts.slide(self.output.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.output.export();
+        series.extend(self.pipeline.export());
+        GroupData { name: self.fname.clone(), datapoints: self.output.datapoints(), series, times: self.output.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.output.datapoints());
+        self.output.skip();
+        self.pipeline.skip();
+        self.opts.slide(self.output.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.output.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.output.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper, lower) = root.split_vertically(height / 2);
+        let per_sec = |i: &f64| format!("{:.1}", i);
+
+        let map_output = self.opts.prepare(self.output.plot(), self.output.times());
+        gen_float_graph("Output Failures (per second)".to_string(), map_output, self.output.datapoints(), self.output.times(), &upper, "libbeat.output.events.", &per_sec, &self.opts.panel("output", true))?;
+
+        let map_pipeline = self.opts.prepare(self.pipeline.plot(), self.pipeline.times());
+        gen_float_graph("Pipeline Retries and Failures (per second)".to_string(), map_pipeline, self.pipeline.datapoints(), self.pipeline.times(), &lower, "libbeat.pipeline.events.", &per_sec, &self.opts.panel("pipeline", false))?;
+
+        Ok(())
+    }
+}
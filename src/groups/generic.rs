@@ -4,23 +4,31 @@
  * 
  */
 
-use std::{collections::{HashMap, VecDeque}, marker::PhantomData};
+use std::{collections::{BTreeMap, HashMap, VecDeque}, marker::PhantomData, ops::Sub};
+
+use num_traits::ToPrimitive;
+use regex::Regex;
 
 use serde::de::DeserializeOwned;
 use serde_json::Number;
 use tracing::{debug, error};
 
+use crate::fetch::TIMESTAMP_PATH;
+
+use super::{paths::{resolve, BeatVersion}, transform::{known_kind, Kind}};
+
 /// A processor provides a way for a user of the Generic type to "preprocess"
-/// metrics before they are ingested, for example, converting bytes to kb.
+/// metrics before they are ingested, for example, turning a fraction into a percentage.
 /// `NoOpProcess` is provided for users who do not require processing
 pub trait Processor {
     /// The expected input type, usually f64 or u64
     type InValue;
     /// The type after `process()`. Must match the numerical type of the `Generic` instance
     type OutValue;
+    /// Create the processor. Processors made by [`Generic::from`] can't take any arguments, use [`Generic::new`] for one that does
     fn new() -> Self;
-    /// Process the metric
-    fn process(&self, raw: Self::InValue) -> Self::OutValue;
+    /// Process a new value of the metric under `key`. Returning `None` leaves a gap in the metric's series at this datapoint
+    fn process(&mut self, key: &str, raw: Self::InValue) -> Option<Self::OutValue>;
 }
 
 /// Do not process the metric before its ingested
@@ -34,22 +42,115 @@ impl<T> Processor for NoOpProcess<T>{
     fn new() -> Self {
         Self{data_type: PhantomData}
     }
-    fn process(&self, raw: Self::InValue) -> Self::OutValue {
-        raw
+    fn process(&mut self, _: &str, raw: Self::InValue) -> Option<Self::OutValue> {
+        Some(raw)
+    }
+}
+
+/// Turns cumulative counters into the change since the previous sample, as they're ingested.
+/// The first value of a counter has nothing to compare to, so it's left out, and a decrease is treated as a counter reset,
+/// in which case the new value is the change since the reset. Metrics known to be gauges, like `active`, are kept as they are.
+pub struct DeltaProcessor<T> {
+    /// When false, every value is kept as it is, so a group can take deltas only when asked to
+    enabled: bool,
+    previous: HashMap<String, T>,
+}
+
+impl<T> DeltaProcessor<T> {
+    /// A processor that only takes deltas if `enabled` is true
+    pub fn enabled(enabled: bool) -> Self {
+        DeltaProcessor { enabled, previous: HashMap::new() }
+    }
+}
+
+impl<T> Processor for DeltaProcessor<T>
+where
+    T: Copy + PartialOrd + Sub<Output = T>
+{
+    type InValue = T;
+    type OutValue = T;
+    fn new() -> Self {
+        Self::enabled(true)
+    }
+    fn process(&mut self, key: &str, raw: Self::InValue) -> Option<Self::OutValue> {
+        if !self.enabled || known_kind(key) == Some(Kind::Gauge) {
+            return Some(raw);
+        }
+        let previous = self.previous.insert(key.to_string(), raw)?;
+        Some(if raw >= previous { raw - previous } else { raw })
+    }
+}
+
+/// A single series of values, each paired with the index of the datapoint it was collected at.
+/// Metrics that were added mid-run or missing from some events will have gaps in the index.
+pub type Series<T> = Vec<(usize, T)>;
+
+/// A recognized structure within a group of metrics that should be rendered as a unit, instead of as unrelated lines.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    /// A summary of a distribution, like `{min, max, mean, p95, ...}`, stored under `key`
+    Summary { key: String },
+    /// Bucketed counts stored under `key`, with the names of the buckets sorted by their upper bound
+    Buckets { key: String, bounds: Vec<String> },
+}
+
+impl Shape {
+    /// The dot-notation key the structure is stored under
+    pub fn key(&self) -> &str {
+        match self {
+            Shape::Summary { key } | Shape::Buckets { key, .. } => key,
+        }
+    }
+}
+
+/// Stat names that mark the center of a summary, in order of preference
+pub const SUMMARY_CENTERS: [&str; 4] = ["mean", "avg", "median", "p50"];
+
+/// Regexes on dot-notation keys, for picking metrics out of the whole stats document, and for dropping metrics a group would otherwise monitor
+#[derive(Clone, Debug, Default)]
+pub struct KeyFilter {
+    /// Monitor every number in the document whose key matches any of these
+    pub include: Vec<Regex>,
+    /// Never monitor a key that matches any of these, however it was selected
+    pub exclude: Vec<Regex>,
+}
+
+impl KeyFilter {
+    /// true if the key shouldn't be monitored
+    fn excludes(&self, key: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(key))
     }
 }
 
 /// An individual metric field. We use this as we don't actually need a hashmap.
 struct MetricField<T: Clone > {
     key: String,
-    values: Vec<T>
+    // the concrete path of the metric in the beat's version, which may differ from the canonical key
+    path: String,
+    // indexed by the datapoint number since the start of the run, not the position in the retention window
+    values: VecDeque<(usize, T)>
 }
 
 /// A grouping of metrics of a single type.
  pub struct Generic<T: Clone + DeserializeOwned, Proc: Processor> {
     user_key: Vec<String>,
+    // keys added after the metrics were initialized, resolved on the next `update()`
+    pending_keys: Vec<String>,
     // data is lazily instantiated, as we can't verify the type until we get a json event
     data: Vec<MetricField<T>>,
+    // histogram-like structures found while instantiating the data
+    shapes: Vec<Shape>,
+    // the version of the beat, used to resolve metric paths
+    version: Option<BeatVersion>,
+    // when each datapoint in the retention window was collected, in milliseconds since the epoch, if the document says
+    times: VecDeque<Option<f64>>,
+    // extra keys to pick out of the document, and keys to leave out
+    filter: KeyFilter,
+    // keep at most this many datapoints, dropping the oldest
+    retain: Option<usize>,
+    // the number of the oldest datapoint in the retention window, and how many datapoints the last update dropped
+    first: usize,
+    dropped: usize,
     datapoints: usize, 
     processor: Proc
 }
@@ -77,6 +178,7 @@ where
     /// The elements of a group can either point to a list of individual metrics, or a map that `Generic`
     /// can reduce down to a list. 
     /// ```
+    /// # use beatperf::groups::generic::{Generic, NoOpProcess};
     /// // A single event, which will be of type u64
     /// let new: Generic<u64, NoOpProcess<u64>> = Generic::from(vec![".beat.runtime.goroutines"]);
     /// 
@@ -87,72 +189,175 @@ where
     /// All the metrics must be of type `T`, while `I` is the type as seen in the raw json event.
     /// The internal list of metrics is lazily instantiated, and all the internal types and fields will not be resolved until the first `update()`.
     pub fn new(group: Vec<String>, processor: Proc) -> Generic<T, Proc> {
-        Generic { user_key: group, data: Vec::new(), datapoints: 0 , processor}
+        Generic { user_key: group, pending_keys: Vec::new(), data: Vec::new(), shapes: Vec::new(), version: None, times: VecDeque::new(), filter: KeyFilter::default(), retain: None, first: 0, dropped: 0, datapoints: 0 , processor}
+    }
+
+    /// Also monitor the keys matched by the filter's include regexes, and never monitor keys matched by its exclude regexes
+    pub fn with_filter(mut self, filter: KeyFilter) -> Generic<T, Proc> {
+        self.filter = filter;
+        self
+    }
+
+    /// Keep only the most recent `retain` datapoints, so a long run doesn't grow without bound.
+    /// Datapoints are numbered from the start of the retention window, so the oldest one kept is always datapoint 0.
+    pub fn with_retain(mut self, retain: Option<usize>) -> Generic<T, Proc> {
+        self.retain = retain;
+        self
+    }
+
+    /// Add a new metric to the group mid-run. The metric is resolved on the next `update()`,
+    /// and will have no values for any datapoints collected before that.
+    pub fn add_metric(&mut self, key: String) {
+        if self.user_key.contains(&key) {
+            debug!("metric {} is already monitored", key);
+            return;
+        }
+        self.user_key.push(key.clone());
+        self.pending_keys.push(key);
     }
 
     /// Update the metrics
     pub fn update(&mut self, root: &serde_json::Map<String, serde_json::Value>)  {
         // lazily initialize the vectors
         if self.data.is_empty() {
-            self.init_metrics(root);
+            self.pending_keys.clear();
+            self.init_metrics(self.user_key.clone(), root);
+            self.init_regex(root);
+        } else if !self.pending_keys.is_empty() {
+            let pending = std::mem::take(&mut self.pending_keys);
+            self.init_metrics(pending, root);
         }
 
         for metric in &mut self.data {
-            let new_data = get_root_elem(root, &metric.key);
+            let new_data = get_root_elem(root, &metric.path);
             match new_data {
                 Some(val) => {
                     let raw: I = match serde_json::from_value(val.clone()){
                         Ok(v) => v,
                         Err(e) => {
                             error!("could not report {}, got unexpected type: {}", metric.key, e);
+                            crate::debug_dump::mismatch(&metric.key, root);
                             continue;
                         } 
                     };
-                    metric.values.push(self.processor.process(raw));
+                    if let Some(value) = self.processor.process(&metric.key, raw) {
+                        metric.values.push_back((self.datapoints, value));
+                    }
                 },
                 None => {
                     debug!("key {} does not exist", metric.key);
                 }
             }
         }
+        self.times.push_back(get_root_elem(root, TIMESTAMP_PATH).and_then(serde_json::Value::as_f64));
         self.datapoints+=1;
+        self.trim();
+
+    }
+
+    /// Count a datapoint that couldn't be collected, so every series has a gap where it would have been
+    pub fn skip(&mut self) {
+        self.times.push_back(None);
+        self.datapoints += 1;
+        self.trim();
+    }
+
+    /// Drop the datapoints that have fallen out of the retention window
+    fn trim(&mut self) {
+        self.dropped = self.retain.map_or(0, |retain| self.times.len().saturating_sub(retain));
+        if self.dropped == 0 {
+            return;
+        }
+        self.times.drain(..self.dropped);
+        // keep the window in one piece, so it can be handed out as a slice
+        self.times.make_contiguous();
+        self.first += self.dropped;
+        for metric in &mut self.data {
+            while metric.values.front().is_some_and(|(idx, _)| *idx < self.first) {
+                metric.values.pop_front();
+            }
+        }
+    }
 
+    /// A metric's values, numbered from the start of the retention window
+    fn window<'a>(&self, metric: &'a MetricField<T>) -> impl Iterator<Item = (usize, &'a T)> + 'a {
+        let first = self.first;
+        metric.values.iter().map(move |(idx, v)| (idx - first, v))
     }
 
     /// Turn our metrics into a hashmap
-    pub fn plot(&self) -> HashMap<String, Vec<T>> {
-        let mut acc: HashMap<String, Vec<T>> = HashMap::new();
+    pub fn plot(&self) -> HashMap<String, Series<T>> {
+        let mut acc: HashMap<String, Series<T>> = HashMap::new();
         for points in &self.data{
-            acc.insert(points.key.to_string(), points.values.clone());
+            acc.insert(points.key.to_string(), self.window(points).map(|(idx, v)| (idx, v.clone())).collect());
         }
         acc
     }
 
-    /// The total number of datapoints
+    /// Turn our metrics into a sorted map of f64 values, for exporting
+    pub fn export(&self) -> BTreeMap<String, Series<f64>>
+    where T: Copy + ToPrimitive {
+        self.data.iter().map(|field| {
+            let values = self.window(field).filter_map(|(idx, v)| Some((idx, v.to_f64()?))).collect();
+            (field.key.to_string(), values)
+        }).collect()
+    }
+
+    /// Any histogram-like structures found in the group's metrics
+    pub fn shapes(&self) -> &[Shape] {
+        &self.shapes
+    }
+
+    /// When each datapoint was collected, in milliseconds since the epoch
+    pub fn times(&self) -> &[Option<f64>] {
+        self.times.as_slices().0
+    }
+
+    /// The number of datapoints, which is every datapoint of the run unless a retention window is set
     pub fn datapoints(&self) -> usize {
-        self.datapoints
+        self.times.len()
+    }
+
+    /// How many of the oldest datapoints the last `update()` or `skip()` dropped, to make room in the retention window
+    pub fn dropped(&self) -> usize {
+        self.dropped
     }
 
     /// This is a little cursed, but it exists to deal with all the cases we can run into when we try to turn a bunch of 
     /// metrics in.dot.form into a 2D vector of values
-    fn init_metrics(&mut self, root: &serde_json::Map<String, serde_json::Value>) {
-        for metric_field in &self.user_key {
-            let new_data = get_root_elem(root, metric_field);
+    fn init_metrics(&mut self, keys: Vec<String>, root: &serde_json::Map<String, serde_json::Value>) {
+        if self.version.is_none() {
+            self.version = BeatVersion::detect(root);
+            if let Some(version) = self.version {
+                debug!("resolving metric paths for beat version {}", version);
+            }
+        }
+
+        for metric_field in &keys {
+            if is_glob(metric_field) {
+                self.init_glob(metric_field, root);
+                continue;
+            }
+            let path = resolve(metric_field, self.version);
+            let new_data = get_root_elem(root, &path);
 
-            let mut raw_fields: Vec<(String, Number)> = Vec::new();
+            // the canonical key, the concrete path, and the value
+            let mut raw_fields: Vec<(String, String, Number)> = Vec::new();
 
             match new_data {
                 // user has given us a value that maps to a single number value
                 Some(serde_json::Value::Number(val)) => {
-                    raw_fields.push((metric_field.to_string(), val.clone()));
+                    raw_fields.push((metric_field.to_string(), path.clone(), val.clone()));
                 }
                 // user has given us a value that maps to a map with multiple values, recusively find all of them.
                 Some(serde_json::Value::Object(inner)) => {
+                    find_shapes(metric_field, inner, &mut self.shapes);
                     // now we have a giant map we need to flatten
                     let flat_values = flatten_map(inner);
                     for (inner_key, inner_val) in flat_values {
                         let root_key = format!("{}.{}", metric_field, inner_key);
-                        raw_fields.push((root_key, inner_val));
+                        let root_path = format!("{}.{}", path, inner_key);
+                        raw_fields.push((root_key, root_path, inner_val));
                     }
                 },
                 _ => {
@@ -162,28 +367,121 @@ where
 
             // we now have an array of every key that comes from the user-supplied string. 
             // validate each against our generic type
-            for (field_key, field_val) in raw_fields {
-                    let raw: I = match serde_json::from_value(serde_json::Value::Number(field_val)){
-                    Ok(v) => {
-                        debug!("got value for key {}", field_key);
-                        v
-                    },
-                    Err(e) => {
-                        error!("could not add metric {} to monitor, got unexpected type: {}", metric_field, e);
-                        continue;
-                    } 
-                };
-                self.data.push(MetricField { key: field_key, values: vec![self.processor.process(raw)] });
+            for (field_key, field_path, field_val) in raw_fields {
+                if self.filter.excludes(&field_key) {
+                    debug!("excluding {}", field_key);
+                    continue;
+                }
+                if let Err(e) = serde_json::from_value::<I>(serde_json::Value::Number(field_val)) {
+                    error!("could not add metric {} to monitor, got unexpected type: {}", metric_field, e);
+                    crate::debug_dump::mismatch(&field_key, root);
+                    continue;
+                }
+                debug!("got value for key {}", field_key);
+                // the value itself gets pushed by the `update()` that triggered the init
+                self.data.push(MetricField { key: field_key, path: field_path, values: VecDeque::new() });
             }
             
         }
 
     }
 
+    /// Monitor every number in the document whose key matches a glob pattern.
+    /// Only the subtree above the first wildcard is searched, and the matching keys are used as-is, without resolving them for the beat's version.
+    fn init_glob(&mut self, pattern: &str, root: &serde_json::Map<String, serde_json::Value>) {
+        let prefix: Vec<&str> = pattern.split('.').take_while(|segment| !is_glob(segment)).collect();
+        let subtree = if prefix.is_empty() {
+            Some(root)
+        } else {
+            get_root_elem(root, &prefix.join(".")).and_then(serde_json::Value::as_object)
+        };
+        let Some(subtree) = subtree else {
+            error!("no metrics match {}", pattern);
+            return;
+        };
+
+        let mut matched = 0;
+        for (inner_key, val) in flatten_map(subtree) {
+            let key = if prefix.is_empty() { inner_key } else { format!("{}.{}", prefix.join("."), inner_key) };
+            if !glob_match(pattern, &key) || self.filter.excludes(&key) || self.data.iter().any(|metric| metric.key == key) {
+                continue;
+            }
+            if let Err(e) = serde_json::from_value::<I>(serde_json::Value::Number(val)) {
+                error!("could not add metric {} to monitor, got unexpected type: {}", key, e);
+                continue;
+            }
+            debug!("{} matches {}", key, pattern);
+            self.data.push(MetricField { key: key.clone(), path: key, values: VecDeque::new() });
+            matched += 1;
+        }
+        if matched == 0 {
+            error!("no metrics match {}", pattern);
+        }
+    }
+
+    /// Monitor every number in the document whose key matches one of the filter's include regexes
+    fn init_regex(&mut self, root: &serde_json::Map<String, serde_json::Value>) {
+        if self.filter.include.is_empty() {
+            return;
+        }
+
+        let mut matched = 0;
+        for (key, val) in flatten_map(root) {
+            if !self.filter.include.iter().any(|re| re.is_match(&key)) || self.filter.excludes(&key) || self.data.iter().any(|metric| metric.key == key) {
+                continue;
+            }
+            if let Err(e) = serde_json::from_value::<I>(serde_json::Value::Number(val)) {
+                error!("could not add metric {} to monitor, got unexpected type: {}", key, e);
+                continue;
+            }
+            debug!("{} matches a metrics regex", key);
+            self.data.push(MetricField { key: key.clone(), path: key, values: VecDeque::new() });
+            matched += 1;
+        }
+        if matched == 0 {
+            error!("no metrics match the metrics regexes");
+        }
+    }
+
+}
+
+/// true if a metric key is a glob pattern, with `*` or `?` wildcards
+fn is_glob(key: &str) -> bool {
+    key.contains(['*', '?'])
+}
+
+/// Match a dot-notation key against a glob pattern. `*` matches any run of characters, dots included, and `?` any single character.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let (pattern, key): (Vec<char>, Vec<char>) = (pattern.chars().collect(), key.chars().collect());
+    let (mut p, mut k) = (0, 0);
+    // the position of the last `*` seen, and of the key when we hit it, to backtrack to
+    let mut star: Option<(usize, usize)> = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                // let the last `*` swallow one more character
+                Some((star_p, star_k)) => {
+                    star = Some((star_p, star_k + 1));
+                    p = star_p + 1;
+                    k = star_k + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
-/// Flatten a map into a vector of dot-notated keys
-fn flatten_map(data: &serde_json::Map<String, serde_json::Value>) -> Vec<(String, Number)> {
+/// Flatten a map into a vector of dot-notated keys, keeping only the numeric values
+pub fn flatten_map(data: &serde_json::Map<String, serde_json::Value>) -> Vec<(String, Number)> {
     let mut acc: Vec<(String, Number)> = Vec::new();
 
     for (key, val) in data {
@@ -205,8 +503,57 @@ fn flatten_map(data: &serde_json::Map<String, serde_json::Value>) -> Vec<(String
     acc
 }
 
+/// Recursively look for histogram-like structures in a map
+fn find_shapes(key: &str, data: &serde_json::Map<String, serde_json::Value>, acc: &mut Vec<Shape>) {
+    if let Some(shape) = detect_shape(key, data) {
+        debug!("found histogram-like metric {}", key);
+        acc.push(shape);
+        return;
+    }
+
+    for (inner_key, val) in data {
+        if let serde_json::Value::Object(nested) = val {
+            find_shapes(&format!("{}.{}", key, inner_key), nested, acc);
+        }
+    }
+}
+
+/// Check if a map of numbers is a distribution summary or a set of buckets
+fn detect_shape(key: &str, data: &serde_json::Map<String, serde_json::Value>) -> Option<Shape> {
+    if data.len() < 2 || !data.values().all(|v| v.is_number()) {
+        return None;
+    }
+
+    if data.contains_key("min") && data.contains_key("max") && SUMMARY_CENTERS.iter().any(|c| data.contains_key(*c)) {
+        return Some(Shape::Summary { key: key.to_string() });
+    }
+
+    // plain numeric keys are ambiguous (load averages are keyed by 1, 5 and 15), so require an explicit bucket marker
+    if !data.keys().any(|k| k.starts_with("le") || bucket_bound(k) == Some(f64::INFINITY)) {
+        return None;
+    }
+
+    let mut bounds: Vec<(f64, String)> = Vec::new();
+    for bucket in data.keys() {
+        bounds.push((bucket_bound(bucket)?, bucket.to_string()));
+    }
+    bounds.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    Some(Shape::Buckets { key: key.to_string(), bounds: bounds.into_iter().map(|(_, name)| name).collect() })
+}
+
+/// Parse the upper bound from a bucket name, like `100`, `le_100` or `+Inf`
+fn bucket_bound(name: &str) -> Option<f64> {
+    let trimmed = name.trim_start_matches("le_").trim_start_matches("le");
+    match trimmed {
+        "+Inf" | "inf" | "Inf" => Some(f64::INFINITY),
+        _ => trimmed.parse().ok(),
+    }
+}
+
 /// simple recursive algo to fetch the the value from a hashmap when our key.is.formatted.like.this
-fn get_root_elem<'a>(data: &'a serde_json::Map<String, serde_json::Value>, nested_key: &str) -> Option<&'a serde_json::Value> {
+/// Find the value at a dot-notation path in a stats document
+pub fn get_root_elem<'a>(data: &'a serde_json::Map<String, serde_json::Value>, nested_key: &str) -> Option<&'a serde_json::Value> {
     let mut key_list: VecDeque<String> = nested_key.split(".").map(|e| e.to_string()).collect();
 
     if key_list.len() == 1 {
@@ -231,13 +578,13 @@ fn get_root_elem<'a>(data: &'a serde_json::Map<String, serde_json::Value>, neste
 mod test {
     use std::collections::HashMap;
 
-    use serde_json::Number;
+    use serde_json::{json, Number};
     use tracing::level_filters::LevelFilter;
     use tracing_subscriber::EnvFilter;
 
-    use crate::groups::generic::{Generic, NoOpProcess};
+    use crate::groups::generic::{DeltaProcessor, Generic, KeyFilter, NoOpProcess, Shape};
 
-    use super::flatten_map;
+    use super::{flatten_map, glob_match};
 
     fn create_nested_json(val_l3: u64, val_l2: u64) -> String {
         let json = format!(r#"{{
@@ -282,7 +629,7 @@ mod test {
         stats.update(&result1);
         stats.update(&result2);
 
-        let golden = HashMap::from([("root.l1.l2.metric".to_string(), vec![5u64, 5, 8]), ("root.l1.l2.l3.metric".to_string(), vec![42, 42, 63])]);
+        let golden = HashMap::from([("root.l1.l2.metric".to_string(), vec![(0, 5u64), (1, 5), (2, 8)]), ("root.l1.l2.l3.metric".to_string(), vec![(0, 42), (1, 42), (2, 63)])]);
         assert_eq!(golden, stats.plot());
         
 
@@ -290,4 +637,152 @@ mod test {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_init_records_first_sample_once() {
+        // the sample that finds a metric creates its series, and only the update it came in with records the value
+        let mut stats: Generic<u64, NoOpProcess<_>> = Generic::from(vec!["beat.runtime"]);
+        stats.update(json!({"beat": {"runtime": {"goroutines": 10}}}).as_object().unwrap());
+        assert_eq!(stats.plot()["beat.runtime.goroutines"], vec![(0, 10)]);
+
+        let mut stats: Generic<u64, DeltaProcessor<_>> = Generic::from(vec!["libbeat.output.events"]);
+        for acked in [10, 25] {
+            stats.update(json!({"libbeat": {"output": {"events": {"acked": acked}}}}).as_object().unwrap());
+        }
+        assert_eq!(stats.plot()["libbeat.output.events.acked"], vec![(1, 15)]);
+    }
+
+    #[test]
+    fn test_add_metric_midrun() -> anyhow::Result<()> {
+        let result1: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&create_nested_json(42, 5))?;
+        let result2: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&create_nested_json(63, 8))?;
+
+        let mut stats: Generic<u64, NoOpProcess<_>> = Generic::from(vec!["root.l1.l2.metric"]);
+        stats.update(&result1);
+        stats.add_metric("root.l1.l2.l3.metric".to_string());
+        stats.update(&result2);
+
+        let golden = HashMap::from([("root.l1.l2.metric".to_string(), vec![(0, 5u64), (1, 8)]), ("root.l1.l2.l3.metric".to_string(), vec![(1, 63)])]);
+        assert_eq!(golden, stats.plot());
+        assert_eq!(2, stats.datapoints());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain() -> anyhow::Result<()> {
+        let mut stats: Generic<u64, NoOpProcess<_>> = Generic::from(vec!["root.l1.l2.metric"]).with_retain(Some(2));
+        for val in [1, 2, 3] {
+            let result: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&create_nested_json(0, val))?;
+            stats.update(&result);
+        }
+        stats.skip();
+        assert_eq!(1, stats.dropped());
+
+        let golden = HashMap::from([("root.l1.l2.metric".to_string(), vec![(0, 3u64)])]);
+        assert_eq!(golden, stats.plot());
+        assert_eq!(2, stats.datapoints());
+        assert_eq!(2, stats.times().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delta_processor() -> anyhow::Result<()> {
+        let mut stats: Generic<u64, DeltaProcessor<_>> = Generic::from(vec!["libbeat.output.events"]);
+        for (acked, active) in [(10, 4), (25, 6), (3, 5)] {
+            let doc = json!({"libbeat": {"output": {"events": {"acked": acked, "active": active}}}});
+            stats.update(doc.as_object().unwrap());
+        }
+
+        // the first acked count has nothing to compare to, and the drop to 3 is a restart. Active is a gauge, so it's kept as is
+        let golden = HashMap::from([
+            ("libbeat.output.events.acked".to_string(), vec![(1, 15u64), (2, 3)]),
+            ("libbeat.output.events.active".to_string(), vec![(0, 4), (1, 6), (2, 5)]),
+        ]);
+        assert_eq!(golden, stats.plot());
+
+        let mut stats: Generic<u64, DeltaProcessor<_>> = Generic::new(vec!["libbeat.output.events".to_string()], DeltaProcessor::enabled(false));
+        stats.update(json!({"libbeat": {"output": {"events": {"acked": 10}}}}).as_object().unwrap());
+        assert_eq!(stats.plot()["libbeat.output.events.acked"], vec![(0, 10)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detect_shapes() -> anyhow::Result<()> {
+        let raw = r#"{"output": {
+            "latency": {"count": 4, "min": 1, "max": 40, "mean": 10.5, "p95": 38},
+            "batch": {"le_10": 3, "le_1": 1, "+Inf": 0, "le_100": 7},
+            "events": {"acked": 5, "failed": 0},
+            "load": {"1": 0.5, "5": 0.2, "15": 0.1}
+        }}"#;
+        let data: serde_json::Map<String, serde_json::Value> = serde_json::from_str(raw)?;
+
+        let mut stats: Generic<f64, NoOpProcess<_>> = Generic::from(vec!["output"]);
+        stats.update(&data);
+
+        let shapes = stats.shapes();
+        assert_eq!(shapes.len(), 2);
+        assert!(shapes.contains(&Shape::Summary { key: "output.latency".to_string() }));
+        let bounds = vec!["le_1".to_string(), "le_10".to_string(), "le_100".to_string(), "+Inf".to_string()];
+        assert!(shapes.contains(&Shape::Buckets { key: "output.batch".to_string(), bounds }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob() -> anyhow::Result<()> {
+        assert!(glob_match("libbeat.output.events.*", "libbeat.output.events.acked"));
+        assert!(glob_match("beat.memstats.*alloc*", "beat.memstats.memory_alloc"));
+        assert!(glob_match("beat.memstats.gc_?ext", "beat.memstats.gc_next"));
+        assert!(!glob_match("beat.memstats.*alloc*", "beat.memstats.rss"));
+        assert!(!glob_match("libbeat.output.events.*", "libbeat.output.write.bytes"));
+
+        let raw = r#"{"beat": {"memstats": {"memory_alloc": 10, "memory_total": 50, "rss": 80, "gc": {"alloc_count": 3}}}}"#;
+        let data: serde_json::Map<String, serde_json::Value> = serde_json::from_str(raw)?;
+
+        let mut stats: Generic<u64, NoOpProcess<_>> = Generic::from(vec!["beat.memstats.*alloc*"]);
+        stats.update(&data);
+
+        let golden = HashMap::from([("beat.memstats.memory_alloc".to_string(), vec![(0, 10u64)]), ("beat.memstats.gc.alloc_count".to_string(), vec![(0, 3)])]);
+        assert_eq!(golden, stats.plot());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_filter() -> anyhow::Result<()> {
+        let raw = r#"{"libbeat": {"output": {"write": {"bytes": 10, "errors": 0}}, "pipeline": {"queue": {"filled": {"bytes": 5, "pct": 0.1}}}}}"#;
+        let data: serde_json::Map<String, serde_json::Value> = serde_json::from_str(raw)?;
+
+        let filter = KeyFilter { include: vec![regex::Regex::new(r"\.bytes$")?], exclude: vec![regex::Regex::new(r"^libbeat\.output")?] };
+        let mut stats: Generic<f64, NoOpProcess<_>> = Generic::from(vec!["libbeat.pipeline.queue.filled"]).with_filter(filter);
+        stats.update(&data);
+
+        let mut keys: Vec<String> = stats.plot().into_keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["libbeat.pipeline.queue.filled.bytes", "libbeat.pipeline.queue.filled.pct"]);
+
+        let filter = KeyFilter { include: Vec::new(), exclude: vec![regex::Regex::new("pct")?] };
+        let mut stats: Generic<f64, NoOpProcess<_>> = Generic::from(vec!["libbeat.pipeline.queue.filled"]).with_filter(filter);
+        stats.update(&data);
+        assert_eq!(stats.plot().into_keys().collect::<Vec<_>>(), vec!["libbeat.pipeline.queue.filled.bytes"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_versioned_paths() -> anyhow::Result<()> {
+        let raw = r#"{"beat": {"info": {"version": "8.12.2"}}, "libbeat": {"pipeline": {"queue": {"filled": {"pct": {"events": 0.5}}}}}}"#;
+        let data: serde_json::Map<String, serde_json::Value> = serde_json::from_str(raw)?;
+
+        let mut stats: Generic<f64, NoOpProcess<_>> = Generic::from(vec!["libbeat.pipeline.queue.filled.pct"]);
+        stats.update(&data);
+
+        let golden = HashMap::from([("libbeat.pipeline.queue.filled.pct".to_string(), vec![(0, 0.5)])]);
+        assert_eq!(golden, stats.plot());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
+use regex::Regex;
+
+use crate::groups::*;
+use super::{generic::{Generic, KeyFilter, NoOpProcess, Series}, Watcher};
+
+const HEARTBEAT_KEY: &str = "heartbeat";
+/// The scheduler's running and waiting jobs and tasks, and the jobs that missed their deadline
+const SCHEDULER_KEY: &str = "heartbeat.scheduler";
+/// Metrics with this in their name are job durations rather than counts
+const DURATION: &str = "duration";
+
+/// Heartbeat's scheduler, and the jobs of each type of monitor. A saturated scheduler shows up as waiting tasks and
+/// missed deadlines, along with monitors whose jobs take longer and longer.
+pub struct Heartbeat {
+    jobs: Generic<u64, NoOpProcess<u64>>,
+    /// Every heartbeat metric as a float, of which only the durations are charted
+    durations: Generic<f64, NoOpProcess<f64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Heartbeat {
+    /// Split the job counts into the scheduler's and the monitors'
+    fn split_jobs(map: HashMap<String, Series<f64>>) -> (HashMap<String, Series<f64>>, HashMap<String, Series<f64>>) {
+        map.into_iter().partition(|(key, _)| key.strip_prefix(SCHEDULER_KEY).is_some_and(|rest| rest.starts_with('.')))
+    }
+}
+
+impl Watcher for Heartbeat {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let filter = KeyFilter { include: Vec::new(), exclude: vec![Regex::new(DURATION).expect("valid regex")] };
+        let jobs = Generic::from(vec![HEARTBEAT_KEY]).with_filter(filter).with_retain(opts.retain);
+        let durations = Generic::from(vec![HEARTBEAT_KEY]).with_retain(opts.retain);
+        Heartbeat { jobs, durations, fname: "heartbeat".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.jobs.update(new);
+        self.durations.update(new);
+        self.opts.slide(self.jobs.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.jobs.export();
+        series.extend(self.durations.export().into_iter().filter(|(key, _)| key.contains(DURATION)));
+        GroupData { name: self.fname.clone(), datapoints: self.jobs.datapoints(), series, times: self.jobs.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.jobs.datapoints());
+        self.jobs.skip();
+        self.durations.skip();
+        self.opts.slide(self.jobs.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.jobs.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.jobs.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let (scheduler, monitors) = Heartbeat::split_jobs(self.opts.prepare(self.jobs.plot(), self.jobs.times()));
+        let durations: HashMap<String, Series<f64>> = self.durations.plot().into_iter().filter(|(key, _)| key.contains(DURATION)).collect();
+        let panels = [!scheduler.is_empty(), !monitors.is_empty(), !durations.is_empty()].into_iter().filter(|shown| *shown).count();
+        let mut areas = root.split_evenly((panels.max(1), 1)).into_iter();
+        let (datapoints, times) = (self.jobs.datapoints(), self.jobs.times());
+
+        // the first panel shown gets any reference lines that don't name a panel
+        let (jobs_first, durations_first) = (scheduler.is_empty(), scheduler.is_empty() && monitors.is_empty());
+        if !scheduler.is_empty() || panels == 0 {
+            let area = areas.next().expect("one area per panel");
+            gen_events_graph("Scheduler".to_string(), scheduler, datapoints, times, &area, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, SCHEDULER_KEY, &self.opts.panel("scheduler", true))?;
+        }
+        if !monitors.is_empty() {
+            let area = areas.next().expect("one area per panel");
+            gen_events_graph("Monitor Jobs".to_string(), monitors, datapoints, times, &area, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, HEARTBEAT_KEY, &self.opts.panel("jobs", jobs_first))?;
+        }
+        if !durations.is_empty() {
+            let area = areas.next().expect("one area per panel");
+            gen_float_graph("Job Duration".to_string(), durations, datapoints, times, &area, HEARTBEAT_KEY, &|i| format!("{:.1}", i), &self.opts.panel("duration", durations_first))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::Heartbeat;
+
+    #[test]
+    fn test_split_jobs() {
+        let map: HashMap<String, Vec<(usize, f64)>> = ["heartbeat.scheduler.jobs.active", "heartbeat.http.monitor_starts", "heartbeat.schedulers"]
+        .into_iter().map(|key| (key.to_string(), vec![(0, 1.0)])).collect();
+        let (scheduler, monitors) = Heartbeat::split_jobs(map);
+        assert_eq!(scheduler.keys().collect::<Vec<_>>(), ["heartbeat.scheduler.jobs.active"]);
+        assert_eq!(monitors.len(), 2);
+    }
+}
@@ -0,0 +1,97 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::{fetch::TIMESTAMP_PATH, groups::*};
+use super::{generic::{get_root_elem, Generic, NoOpProcess}, memory::MemoryProcessor, Watcher};
+
+const LOAD_KEY: &str = "system.load";
+/// CFS periods, and how many of them, and for how long, the cgroup was throttled
+const CPU_KEY: &str = "beat.cgroup.cpu.stats";
+const MEMORY_KEY: &str = "beat.cgroup.memory.mem";
+
+/// The host and container the beat runs in, so CPU throttling and host load can be lined up with the beat's own metrics.
+/// Beats only report cgroup metrics when they run in one, so the cgroup panels are left out when there's nothing to draw.
+pub struct Host {
+    load: Generic<f64, NoOpProcess<f64>>,
+    cpu: Generic<u64, NoOpProcess<u64>>,
+    memory: Generic<f64, MemoryProcessor>,
+    /// When each datapoint was collected, kept here as any of the sub-groups may be missing from a sample
+    times: Vec<Option<f64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Host {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        Host { load: Generic::from(vec![LOAD_KEY]).with_retain(opts.retain), cpu: Generic::from(vec![CPU_KEY]).with_retain(opts.retain), memory: Generic::from(vec![MEMORY_KEY]).with_retain(opts.retain), times: Vec::new(), fname: "host".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        update_if_present(&mut self.load, LOAD_KEY, new);
+        update_if_present(&mut self.cpu, CPU_KEY, new);
+        update_if_present(&mut self.memory, MEMORY_KEY, new);
+        self.times.push(get_root_elem(new, TIMESTAMP_PATH).and_then(serde_json::Value::as_f64));
+        // the sub-groups all drop the same datapoints
+        self.times.drain(..self.load.dropped());
+        self.opts.slide(self.load.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.load.export();
+        series.extend(self.cpu.export());
+        series.extend(self.memory.export());
+        GroupData { name: self.fname.clone(), datapoints: self.load.datapoints(), series, times: self.times.clone() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.load.datapoints());
+        self.load.skip();
+        self.cpu.skip();
+        self.memory.skip();
+        self.times.push(None);
+        // the sub-groups all drop the same datapoints
+        self.times.drain(..self.load.dropped());
+        self.opts.slide(self.load.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.load.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.load.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let load = self.opts.prepare(self.load.plot(), &self.times);
+        // throttling is only visible as a rate, whatever the other panels are charted as
+        let cpu_opts = ChartOpts { counters: CounterMode::Rate, ..self.opts.panel("cpu", false) };
+        let cpu = cpu_opts.prepare(self.cpu.plot(), &self.times);
+        let memory = self.opts.prepare(self.memory.plot(), &self.times);
+        let panels = [!load.is_empty(), !cpu.is_empty(), !memory.is_empty()].into_iter().filter(|shown| *shown).count();
+        let mut areas = root.split_evenly((panels.max(1), 1)).into_iter();
+        let datapoints = self.load.datapoints();
+
+        if !load.is_empty() || panels == 0 {
+            let area = areas.next().expect("one area per panel");
+            gen_float_graph("Host Load".to_string(), load, datapoints, &self.times, &area, "system.", &|i| format!("{:.2}", i), &self.opts.panel("load", true))?;
+        }
+        if !cpu.is_empty() {
+            let area = areas.next().expect("one area per panel");
+            gen_events_graph("Cgroup CPU Throttling (per second)".to_string(), cpu, datapoints, &self.times, &area, 5, 18, CPU_KEY, &cpu_opts)?;
+        }
+        if !memory.is_empty() {
+            let area = areas.next().expect("one area per panel");
+            gen_bytes_graph("Cgroup Memory".to_string(), memory, datapoints, &self.times, &area, "beat.cgroup.memory.", &self.opts.panel("memory", false))?;
+        }
+
+        Ok(())
+    }
+}
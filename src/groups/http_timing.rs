@@ -0,0 +1,90 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess}, Watcher};
+
+const TIMING_KEY: &str = "beatperf.http";
+
+/// Timings of each stats request beatperf makes, as measured by beatperf itself
+pub struct HttpTiming {
+    group: Generic<f64, NoOpProcess<f64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for HttpTiming {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::from(vec![TIMING_KEY]).with_retain(opts.retain);
+        HttpTiming { group, fname: "http_timing".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let map_data = self.opts.prepare(self.group.plot(), self.group.times());
+        if self.opts.facet {
+            return gen_small_multiples(self.fname.clone(), &map_data, self.group.datapoints(), self.group.times(), root, TIMING_KEY, &self.opts);
+        }
+
+        let (min, max) = get_min_max_float(&map_data)?;
+        let max = self.opts.clamp_max(&map_data, max);
+        let (min, max) = extend_range(&self.opts.hlines, min, max);
+        let headroom = (max - min) * HEADROOM_CHART_MAX;
+
+        let mut chart = setup_graph(self.fname.clone(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, &self.opts.theme);
+        let x_axis = TimeAxis::new(self.group.times(), self.group.datapoints());
+        let x_desc = x_axis.desc();
+        let mut chart_con = chart.build_cartesian_2d(x_axis, min..(max + headroom))?;
+        self.opts.theme.mesh(&mut chart_con.configure_mesh()).x_desc(x_desc).y_desc("Time since request start").y_label_formatter(&|ms| format!("{:.1} ms", ms)).draw()?;
+        draw_markers(&mut chart_con, &self.opts.markers, &self.opts.theme)?;
+
+        // sort the phases by their average time, so the legend reads in the same order as the request
+        let mut phases: Vec<(&String, &Series<f64>)> = map_data.iter().collect();
+        phases.sort_by(|a, b| mean(a.1).total_cmp(&mean(b.1)));
+        let colors = self.opts.colors(map_data.keys());
+        for (name, group) in phases {
+            let color = colors[name.as_str()].mix(0.9);
+            draw_line(&mut chart_con, group, color.stroke_width(2), &self.opts)?
+            .label(name.trim_start_matches(TIMING_KEY).trim_start_matches('.'))
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+        }
+        draw_hlines(&mut chart_con, &self.opts.hlines, self.group.datapoints(), self.opts.theme.foreground)?;
+
+        self.opts.theme.legend(&mut chart_con.configure_series_labels()).draw()?;
+
+        Ok(())
+    }
+}
+
+fn mean(series: &Series<f64>) -> f64 {
+    series.iter().map(|(_, v)| v).sum::<f64>() / series.len().max(1) as f64
+}
@@ -0,0 +1,71 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess}, Watcher};
+
+const HARVESTER_KEY: &str = "filebeat.harvester";
+const EVENTS_KEY: &str = "filebeat.events";
+
+/// Filebeat's harvesters and the events they've read, the first place to look when filebeat isn't keeping up
+pub struct Inputs {
+    harvester: Generic<u64, NoOpProcess<u64>>,
+    events: Generic<u64, NoOpProcess<u64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Inputs {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let harvester = Generic::from(vec![HARVESTER_KEY]).with_retain(opts.retain);
+        let events = Generic::from(vec![EVENTS_KEY]).with_retain(opts.retain);
+        Inputs { harvester, events, fname: "inputs".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.harvester.update(new);
+        self.events.update(new);
+        self.opts.slide(self.harvester.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.harvester.export();
+        series.extend(self.events.export());
+        GroupData { name: self.fname.clone(), datapoints: self.harvester.datapoints(), series, times: self.harvester.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.harvester.datapoints());
+        self.harvester.skip();
+        self.events.skip();
+        self.opts.slide(self.harvester.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.harvester.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.harvester.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper, lower) = root.split_vertically(height / 2);
+
+        let map_harvester = self.opts.prepare(self.harvester.plot(), self.harvester.times());
+        gen_events_graph("Harvesters".to_string(), map_harvester, self.harvester.datapoints(), self.harvester.times(), &upper, 5, 18, HARVESTER_KEY, &self.opts.panel("harvester", true))?;
+
+        let map_events = self.opts.prepare(self.events.plot(), self.events.times());
+        gen_events_graph("Events".to_string(), map_events, self.events.datapoints(), self.events.times(), &lower, 5, 18, EVENTS_KEY, &self.opts.panel("events", false))?;
+
+        Ok(())
+    }
+}
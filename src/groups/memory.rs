@@ -1,12 +1,15 @@
-use anyhow::Context;
-use plotters::prelude::*;
-use tracing::debug;
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
 
 use crate::groups::*;
 
 use super::{generic::{Generic, Processor}, Watcher};
 
-/// A processor for turning our bytes into kB
+/// The beat's resident set size, charted apart from the Go heap
+const RSS_KEY: &str = "beat.memstats.rss";
+
+/// A processor for charting byte counts, which are labelled by [`bytes_formatter`]
 pub struct MemoryProcessor {}
 
 impl Processor for MemoryProcessor {
@@ -15,60 +18,81 @@ impl Processor for MemoryProcessor {
     fn new() -> Self {
         Self {  }
     }
-    fn process(&self, raw: Self::InValue) -> Self::OutValue {
-        raw as f64 / 1000.0
+    fn process(&mut self, _: &str, raw: Self::InValue) -> Option<Self::OutValue> {
+        Some(raw as f64)
     }
 }
 
 pub struct MemoryMetrics {
     group: Generic<f64, MemoryProcessor>,
-    fname: String
+    fname: String,
+    opts: ChartOpts
 }
 
 impl Watcher for MemoryMetrics {
 
-    fn new(_ : Option<Vec<String>>) -> Self {
-        let group = Generic::from(vec!["beat.memstats"]);
-        MemoryMetrics { group, fname: "memstat".to_string() }
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::from(vec!["beat.memstats"]).with_retain(opts.retain);
+        MemoryMetrics { group, fname: "memstat".to_string(), opts }
     }
 
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
         self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
     }
 
-    fn plot(&self) -> anyhow::Result<()> {
-        let mut map_data = self.group.plot();
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let mut map_data = self.opts.prepare(self.group.plot(), self.group.times());
         // filter out the memory_total metric, which is a massive counter that sums all memory bytes
         map_data.remove("beat.memstats.memory_total");
+        if self.opts.facet {
+            return gen_small_multiples(self.fname.clone(), &map_data, self.group.datapoints(), self.group.times(), root, "beat.memstats", &self.opts);
+        }
+        if self.opts.stack {
+            return gen_stacked_graph(self.fname.clone(), &map_data, self.group.datapoints(), self.group.times(), root, "", &|i| bytes_formatter(*i, self.opts.byte_units), &self.opts);
+        }
+
+        // RSS is usually much bigger than the heap, so it gets its own panel instead of flattening the heap and GC lines
+        let (rss, heap): (HashMap<_, _>, HashMap<_, _>) = map_data.into_iter().partition(|(key, _)| key == RSS_KEY);
+        let panels = [!rss.is_empty(), !heap.is_empty()].into_iter().filter(|shown| *shown).count();
+        let mut areas = root.split_evenly((panels.max(1), 1)).into_iter();
 
-        let (min, max) = get_min_max_float(&map_data)?;
-
-        // give the top of the chart some headroom, this way the legend won't collide with the graphs.
-        let headroom = (max - min) * HEADROOM_CHART_MAX;
-
-        let name = format!("./{}_plot.svg", self.fname);
-        debug!("writing {}...", name);
-
-        let root = SVGBackend::new(&name, SVG_SIZE).into_drawing_area();
-        root.fill(&WHITE)?;
-    
-        let mut chart = setup_graph(self.fname.clone(), &root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT);
-        let mut chart_con = chart.build_cartesian_2d(0usize..self.group.datapoints(), min..(max + headroom))?;
-    
-        chart_con.configure_mesh().x_desc("Datapoints").y_desc("Memory Usage").y_label_formatter(&|i| kbyte_formatter(*i)).draw()?;
-    
-        for (idx, (name, group)) in map_data.iter().enumerate() {
-            let color = Palette99::pick(idx).mix(0.9);
-            chart_con.draw_series(LineSeries::new(group.iter().enumerate().map(|(p_idx, d)| (p_idx, *d)), color.stroke_width(2)))?
-            .label(name)
-            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
-    
+        if !rss.is_empty() {
+            let area = areas.next().expect("one area per panel");
+            gen_bytes_graph("RSS".to_string(), rss, self.group.datapoints(), self.group.times(), &area, "beat.memstats.", &self.opts.panel("rss", true))?;
+        }
+        // reference lines that don't name a panel go on the heap panel when there's no RSS, like with --expvar
+        if !heap.is_empty() || panels == 0 {
+            let area = areas.next().expect("one area per panel");
+            gen_bytes_graph("Heap and GC".to_string(), heap, self.group.datapoints(), self.group.times(), &area, "beat.memstats.", &self.opts.panel("heap", panels < 2))?;
         }
-    
-        chart_con.configure_series_labels().border_style(BLACK).position(SeriesLabelPosition::UpperLeft).draw()?;
-    
-        root.present().context("could not write file")?;
 
         Ok(())
     }
-}
\ No newline at end of file
+}
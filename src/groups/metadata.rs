@@ -0,0 +1,82 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::{fetch::TIMESTAMP_PATH, groups::*};
+use super::{generic::{get_root_elem, Generic, NoOpProcess}, Watcher};
+
+const KUBERNETES_KEY: &str = "processor.add_kubernetes_metadata";
+const DOCKER_KEY: &str = "processor.add_docker_metadata";
+
+/// The add_kubernetes_metadata and add_docker_metadata processors' cache hits and misses, and their watchers' errors.
+/// A beat usually runs only one of them, so a processor's panel is left out when it reports nothing.
+pub struct Metadata {
+    kubernetes: Generic<u64, NoOpProcess<u64>>,
+    docker: Generic<u64, NoOpProcess<u64>>,
+    /// When each datapoint was collected, kept here as either processor may be missing from a sample
+    times: Vec<Option<f64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Metadata {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        Metadata { kubernetes: Generic::from(vec![KUBERNETES_KEY]).with_retain(opts.retain), docker: Generic::from(vec![DOCKER_KEY]).with_retain(opts.retain), times: Vec::new(), fname: "metadata".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        update_if_present(&mut self.kubernetes, KUBERNETES_KEY, new);
+        update_if_present(&mut self.docker, DOCKER_KEY, new);
+        self.times.push(get_root_elem(new, TIMESTAMP_PATH).and_then(serde_json::Value::as_f64));
+        // the sub-groups all drop the same datapoints
+        self.times.drain(..self.kubernetes.dropped());
+        self.opts.slide(self.kubernetes.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.kubernetes.export();
+        series.extend(self.docker.export());
+        GroupData { name: self.fname.clone(), datapoints: self.kubernetes.datapoints(), series, times: self.times.clone() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.kubernetes.datapoints());
+        self.kubernetes.skip();
+        self.docker.skip();
+        self.times.push(None);
+        // the sub-groups all drop the same datapoints
+        self.times.drain(..self.kubernetes.dropped());
+        self.opts.slide(self.kubernetes.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.kubernetes.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.kubernetes.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let kubernetes = self.opts.prepare(self.kubernetes.plot(), &self.times);
+        let docker = self.opts.prepare(self.docker.plot(), &self.times);
+        let datapoints = self.kubernetes.datapoints();
+
+        match (kubernetes.is_empty(), docker.is_empty()) {
+            (false, false) => {
+                let height = root.dim_in_pixel().1;
+                let (upper, lower) = root.split_vertically(height / 2);
+                gen_events_graph("Kubernetes Metadata".to_string(), kubernetes, datapoints, &self.times, &upper, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, KUBERNETES_KEY, &self.opts.panel("kubernetes", true))?;
+                gen_events_graph("Docker Metadata".to_string(), docker, datapoints, &self.times, &lower, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, DOCKER_KEY, &self.opts.panel("docker", false))
+            }
+            (true, false) => gen_events_graph("Docker Metadata".to_string(), docker, datapoints, &self.times, root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, DOCKER_KEY, &self.opts.panel("docker", true)),
+            _ => gen_events_graph("Kubernetes Metadata".to_string(), kubernetes, datapoints, &self.times, root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, KUBERNETES_KEY, &self.opts.panel("kubernetes", true)),
+        }
+    }
+}
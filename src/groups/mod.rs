@@ -5,28 +5,249 @@
  * 
  */
 
-use std::collections::HashMap;
-use anyhow::anyhow;
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, time::Duration};
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
 
-use plotters::{chart::ChartBuilder, coord::Shift, prelude::*};
+use plotters::{chart::{ChartBuilder, SeriesAnno}, coord::{ranged1d::{Ranged, ValueFormatter}, Shift}, prelude::*};
+use generic::{get_root_elem, Generic, KeyFilter, Series, SUMMARY_CENTERS};
+use annotations::{draw_hlines, draw_markers, extend_range, mark_event, mark_outage, mark_span, slide_markers, Marker, RefLine};
+use anomaly::Detector;
+use palette::{ChartPalette, SeriesColors};
+use theme::Theme;
+use time_axis::TimeAxis;
+use transform::{downsample, percentile, percentile_band, CounterMode, Scale};
+use tracing::debug;
 
+use crate::fetch::STATE_PATH;
+
+pub mod cpu;
 pub mod processdb;
 pub mod memory;
 pub mod pipeline;
 pub mod output;
+pub mod es_output;
+pub mod failures;
+pub mod inputs;
+pub mod per_input;
+pub mod queue;
+pub mod host;
+pub mod metadata;
 pub mod custom;
 pub mod kernel_tracing;
+pub mod auditbeat;
+pub mod packetbeat;
+pub mod heartbeat;
+pub mod http_timing;
+pub mod palette;
+pub mod theme;
+pub mod paths;
+pub mod annotations;
+pub mod transform;
+pub mod diff;
+pub mod anomaly;
+pub mod time_axis;
 
-mod generic;
+pub mod generic;
  
 /// A trait for groups of metrics that allows a group to have their own opinions about how a set of metrics should be graphed and ordered
 pub trait Watcher {
     /// Update the metrics based on a map we get from beats
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>);
-    /// Generate an SVG plot
-    fn plot(&self) -> anyhow::Result<()>;
+    /// Draw the group's chart(s) onto the given drawing area
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()>;
+    /// The base name used for the group's chart and output files, unless the user gives it another
+    fn fname(&self) -> &str;
     /// Create a new instance with optional metrics. 
-    fn new(additional_fields: Option<Vec<String>>) -> Self;
+    fn new(additional_fields: Option<Vec<String>>, opts: ChartOpts) -> Self;
+
+    /// Snapshot all of the group's raw series, for exporting
+    fn export(&self) -> GroupData;
+
+    /// Record a datapoint that couldn't be collected, leaving a gap in every series
+    fn skip(&mut self);
+
+    /// Highlight the latest datapoint with a labelled span, like a breached threshold
+    fn mark(&mut self, label: &str);
+
+    /// Mark the latest datapoint with a labelled line, like the moment a load generator was started
+    fn annotate(&mut self, label: &str);
+
+    /// The theme the group's charts are drawn in
+    fn theme(&self) -> &Theme;
+
+    /// Add a metric to the group mid-run. Returns false if the group doesn't accept additional metrics.
+    fn add_metric(&mut self, _key: &str) -> bool {
+        false
+    }
+
+    /// Write the chart to an SVG file
+    fn plot(&self, path: &str) -> anyhow::Result<()> {
+        debug!("writing {}...", path);
+        self.present(SVGBackend::new(path, SVG_SIZE).into_drawing_area()).context("could not write file")
+    }
+
+    /// Write the chart to a PNG file, for places that don't display SVGs
+    fn plot_png(&self, path: &str) -> anyhow::Result<()> {
+        debug!("writing {}...", path);
+        self.present(BitMapBackend::new(path, SVG_SIZE).into_drawing_area()).context("could not write file")
+    }
+
+    /// Render the current state of the chart to an in-memory SVG document
+    fn plot_to_string(&self) -> anyhow::Result<String> {
+        let mut buf = String::new();
+        self.present(SVGBackend::with_string(&mut buf, SVG_SIZE).into_drawing_area()).context("could not render chart")?;
+
+        Ok(buf)
+    }
+
+    /// Draw the chart onto a blank drawing area, and write it out to the backend
+    fn present<DB: DrawingBackend<ErrorType: 'static>>(&self, root: DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        root.fill(&self.theme().background)?;
+        self.draw(&root)?;
+        root.present()?;
+
+        Ok(())
+    }
+}
+
+/// A snapshot of a group's collected series, with all values converted to f64
+#[derive(Clone, Debug, Default)]
+pub struct GroupData {
+    /// The name of the group, as used for its chart
+    pub name: String,
+    /// The number of datapoints the group has seen
+    pub datapoints: usize,
+    /// Every series, keyed by its dot-notation metric key
+    pub series: BTreeMap<String, Series<f64>>,
+    /// When each datapoint was collected, in milliseconds since the epoch
+    pub times: Vec<Option<f64>>,
+}
+
+/// User-configurable options that apply to the charts of every group
+#[derive(Clone, Debug, Default)]
+pub struct ChartOpts {
+    /// The palette used to color the individual series
+    pub palette: ChartPalette,
+    /// The colors of the background, text and grid, and maybe the series
+    pub theme: Theme,
+    /// The colors given to the group's series so far
+    pub series_colors: SeriesColors,
+    /// How cumulative counters are charted
+    pub counters: CounterMode,
+    /// The units byte counts are labelled in
+    pub byte_units: ByteUnits,
+    /// Take the change between samples of the counters in the groups that support it, as they're collected, and chart that instead
+    pub deltas: bool,
+    /// The time between samples, used to calculate rates
+    pub interval: Duration,
+    /// Horizontal reference lines for this group
+    pub hlines: Vec<RefLine>,
+    /// Spans of the run to highlight, filled in as the run goes on
+    pub markers: Vec<Marker>,
+    /// Draw every series in its own small panel, instead of overlapping them on one chart
+    pub facet: bool,
+    /// Draw the series stacked on top of each other as areas, so their total and its composition are both visible
+    pub stack: bool,
+    /// The scale of the y-axis
+    pub scale: Scale,
+    /// Clamp the top of the y-axis to this percentile of the data, so a few outliers don't flatten the rest of the chart
+    pub clamp: Option<f64>,
+    /// Regexes that pick metrics out of the stats document, or leave them out. Only used by the custom group
+    pub filter: KeyFilter,
+    /// Draw at most this many points of a series, downsampling longer ones. Exports always keep every datapoint
+    pub max_points: Option<usize>,
+    /// Keep only this many of the most recent datapoints, so a long run uses a bounded amount of memory
+    pub retain: Option<usize>,
+    /// Draw a p50-p95 band behind each line of an events chart, over a sliding window of this many datapoints
+    pub band: Option<usize>,
+    /// Mark the samples this detector flags as outliers on every line
+    pub anomalies: Option<Detector>,
+}
+
+impl ChartOpts {
+    /// Pick a color for the series at the given index. The theme's palette wins over the default one,
+    /// but not over one the user or the group asked for.
+    pub fn color(&self, idx: usize) -> RGBColor {
+        match self.palette {
+            ChartPalette::Default if !self.theme.palette.is_empty() => self.theme.palette[idx % self.theme.palette.len()],
+            palette => palette.pick(idx),
+        }
+    }
+
+    /// Pick a color for every series of a chart, by key, in key order. A series keeps its color for the whole run, see [`SeriesColors`].
+    pub fn colors<'k>(&self, keys: impl IntoIterator<Item = &'k String>) -> BTreeMap<&'k str, RGBColor> {
+        self.series_colors.assign(keys.into_iter().map(String::as_str)).into_iter().map(|(key, idx)| (key, self.color(idx))).collect()
+    }
+
+    /// Prepare a group's series for plotting, as floats since counters are charted as rates.
+    /// `times` is when each datapoint was collected, see [`generic::Generic::times`].
+    pub fn prepare<T>(&self, map: HashMap<String, Series<T>>, times: &[Option<f64>]) -> HashMap<String, Series<f64>>
+    where T: Copy + PartialOrd + num_traits::ToPrimitive {
+        self.counters.apply(map, self.interval, times)
+    }
+
+    /// Lower the top of the y-axis to the configured percentile of the data, if one is set
+    pub fn clamp_max<T>(&self, map: &HashMap<String, Series<T>>, max: T) -> T
+    where T: Copy + PartialOrd + num_traits::ToPrimitive + num_traits::NumCast {
+        let Some(pct) = self.clamp else {
+            return max;
+        };
+        let values: Vec<f64> = map.values().flatten().filter_map(|(_, v)| v.to_f64()).collect();
+        match percentile(&values, pct).and_then(<T as num_traits::NumCast>::from) {
+            Some(top) if top < max => top,
+            _ => max,
+        }
+    }
+
+    /// Mark a datapoint that couldn't be collected as part of an outage
+    pub fn mark_outage(&mut self, datapoint: usize) {
+        mark_outage(&mut self.markers, datapoint);
+    }
+
+    /// Highlight a datapoint as part of a labelled span, like a breached threshold
+    pub fn mark(&mut self, datapoint: usize, label: &str) {
+        mark_span(&mut self.markers, datapoint, label);
+    }
+
+    /// Mark a datapoint as a labelled moment of the run, drawn as a vertical line
+    pub fn annotate(&mut self, datapoint: usize, label: &str) {
+        mark_event(&mut self.markers, datapoint, label);
+    }
+
+    /// Draw a reference line at the queue size in the beat's `/state`, if the sample came with one.
+    /// The line is replaced whenever the state is fetched again, in case the queue was reconfigured.
+    pub fn queue_line(&mut self, new: &serde_json::Map<String, serde_json::Value>, group: &str, panel: &str) {
+        let Some(max) = get_root_elem(new, &format!("{}.{}", STATE_PATH, QUEUE_MAX_STATE)).and_then(serde_json::Value::as_f64) else {
+            return;
+        };
+        let label = Some(QUEUE_MAX_STATE.to_string());
+        self.hlines.retain(|line| line.label != label);
+        self.hlines.push(RefLine { group: group.to_string(), panel: Some(panel.to_string()), value: max, label });
+    }
+
+    /// Keep the highlighted spans lined up with the datapoints after the group dropped some from its retention window
+    pub fn slide(&mut self, dropped: usize) {
+        slide_markers(&mut self.markers, dropped);
+    }
+
+    /// Narrow the options down to a single panel of a multi-panel group.
+    /// Reference lines that don't name a panel are drawn on the group's primary panel.
+    pub fn panel(&self, panel: &str, primary: bool) -> ChartOpts {
+        let hlines = self.hlines.iter()
+        .filter(|line| line.panel.as_deref().map_or(primary, |p| p == panel))
+        .cloned().collect();
+        ChartOpts { hlines, ..self.clone() }
+    }
+
+    /// The options for series that may have been turned into deltas by a [`generic::DeltaProcessor`].
+    /// With `deltas` set they're no longer cumulative, so they're charted as they are, whatever `counters` says
+    pub fn for_deltas(self) -> ChartOpts {
+        match self.deltas {
+            true => ChartOpts { counters: CounterMode::Raw, ..self },
+            false => self,
+        }
+    }
 }
 
 /// The default margin percentage for a graph
@@ -36,29 +257,73 @@ const LABEL_SIZE_LEFT: i32 = 9;
 /// The default bottom label size
 const LABEL_SIZE_BOTTOM: i32 = 12;
 /// The graph dimensions
-const SVG_SIZE: (u32, u32) = (1024, 768);
+pub const SVG_SIZE: (u32, u32) = (1024, 768);
 /// The default font size for labels
 const CHART_NAME_FONT_PCT_SIZE: i32 = 5;
+/// The font size for the names of small-multiple panels
+const FACET_NAME_FONT_SIZE: i32 = 14;
+/// The number of y-axis labels on events charts
+const EVENTS_Y_LABELS: usize = 10;
+/// The number of y-axis labels on charts of floating point values
+const FLOAT_Y_LABELS: usize = 5;
 /// The defauld additional y axis to add, to make way for the graph legend
 const HEADROOM_CHART_MAX: f64 = 0.10;
+/// The size of the queue in a beat's `/state` document
+const QUEUE_MAX_STATE: &str = "queue.max_events";
 
-/// Helper for the plotter that formats the y-axis value for kilobytes
-fn kbyte_formatter(raw: f64) -> String {
-    if raw >= 100_000.0 {
-        format!("{} MB", raw /1000.0)
+/// Update a sub-group if the sample has its metrics, and otherwise count the datapoint as missing,
+/// so metrics a beat never reports don't get looked up, and complained about, on every sample
+fn update_if_present<T, I, P>(group: &mut Generic<T, P>, key: &str, new: &serde_json::Map<String, serde_json::Value>)
+where
+    T: Clone + serde::de::DeserializeOwned,
+    I: Clone + serde::de::DeserializeOwned,
+    P: generic::Processor<InValue = I, OutValue = T>,
+{
+    if get_root_elem(new, key).is_some() {
+        group.update(new);
     } else {
-        format!("{} KB", raw)
+        group.skip();
     }
 }
 
+/// The units byte counts are labelled in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ByteUnits {
+    /// Powers of 1000: KB, MB, GB and TB
+    #[default]
+    Decimal,
+    /// Powers of 1024: KiB, MiB, GiB and TiB
+    Binary,
+}
+
+/// Format a number of bytes in a few characters, in the largest unit it has at least one of.
+/// Rates of a few bytes a second are common, so plain bytes keep their decimals.
+fn bytes_formatter(bytes: f64, units: ByteUnits) -> String {
+    let (base, names) = match units {
+        ByteUnits::Decimal => (1000.0, ["KB", "MB", "GB", "TB"]),
+        ByteUnits::Binary => (1024.0, ["KiB", "MiB", "GiB", "TiB"]),
+    };
+    let mut scaled = bytes / base;
+    if scaled.abs() < 1.0 {
+        return format!("{} B", (bytes * 100.0).round() / 100.0);
+    }
+    for (idx, name) in names.iter().enumerate() {
+        if scaled.abs() < base || idx == names.len() - 1 {
+            return format!("{:.1} {}", scaled, name);
+        }
+        scaled /= base;
+    }
+    unreachable!("the last unit always matches")
+}
+
 fn pct_formatter(raw: f64) -> String {
     format!("{:.2}%", raw)
 }
 
 /// Helper to set up the base graph object
-fn setup_graph<'e, DB: DrawingBackend>(name: String, root: &DrawingArea<DB, Shift>, margin: i32, label_left_size: i32 ) ->  ChartBuilder<'_, 'e, DB> {
+fn setup_graph<'a, 'e, DB: DrawingBackend>(name: String, root: &'a DrawingArea<DB, Shift>, margin: i32, label_left_size: i32, theme: &Theme) ->  ChartBuilder<'a, 'e, DB> {
     let mut chart_new = ChartBuilder::on(root);
-    chart_new.caption(name, ("sans-serif", (CHART_NAME_FONT_PCT_SIZE).percent_height()))
+    chart_new.caption(name, theme.font((CHART_NAME_FONT_PCT_SIZE).percent_height()))
     .set_label_area_size(LabelAreaPosition::Left, (label_left_size).percent())
     .set_label_area_size(LabelAreaPosition::Bottom, (LABEL_SIZE_BOTTOM).percent())
     .margin((margin).percent());
@@ -66,12 +331,11 @@ fn setup_graph<'e, DB: DrawingBackend>(name: String, root: &DrawingArea<DB, Shif
     chart_new
 }
 
-
-fn get_min_max_float(map: &HashMap<String, Vec<f64>>) -> anyhow::Result<(f64, f64)> {
-    let max = map.iter().filter_map(| (_key, value) | value.iter().copied().reduce(f64::max))
+fn get_min_max_float(map: &HashMap<String, Series<f64>>) -> anyhow::Result<(f64, f64)> {
+    let max = map.values().filter_map(|value| value.iter().map(|(_, v)| *v).reduce(f64::max))
     .reduce(f64::max).ok_or_else(||anyhow!("data does not have any values"))?;
 
-    let mut min = map.iter().filter_map(| (_key, value) | value.iter().copied().reduce(f64::min))
+    let mut min = map.values().filter_map(|value| value.iter().map(|(_, v)| *v).reduce(f64::min))
     .reduce(f64::min).ok_or_else(||anyhow!("data does not have any values"))?;
 
     if min == max {
@@ -81,39 +345,402 @@ fn get_min_max_float(map: &HashMap<String, Vec<f64>>) -> anyhow::Result<(f64, f6
     Ok((min, max))
 }
 
-fn get_min_max_uint(map: &HashMap<String, Vec<u64>>) -> anyhow::Result<(u64, u64)> {
-    let max = map.iter().filter_map(| (_key, value) | value.iter().max())
-    .max().copied().ok_or_else(||anyhow!("data does not have any values"))?;
+/// Split a series into runs of consecutive datapoints
+fn contiguous_runs<T: Copy>(series: &Series<T>) -> Vec<Series<T>> {
+    let mut runs: Vec<Series<T>> = Vec::new();
+    for point in series {
+        match runs.last_mut() {
+            Some(run) if run.last().is_some_and(|(idx, _)| idx + 1 == point.0) => run.push(*point),
+            _ => runs.push(vec![*point]),
+        }
+    }
+    runs
+}
 
-    let mut min = map.iter().filter_map(| (_key, value) | value.iter().min())
-    .min().copied().ok_or_else(||anyhow!("data does not have any values"))?;
+/// Draw a series as a line that breaks wherever datapoints are missing, instead of joining across the gap.
+/// Points above a clamped y-axis are pinned to the top of the chart and marked with a triangle.
+/// Series longer than the options' `max_points` are downsampled, with each run getting its share of the points, and outliers are marked with a cross.
+/// Returns the annotation of the first segment, so the caller can label the whole series once.
+fn draw_line<'a, 'b, DB, Y>(chart: &'b mut ChartContext<'a, DB, Cartesian2d<TimeAxis, Y>>, series: &Series<Y::ValueType>, style: ShapeStyle, opts: &ChartOpts) -> anyhow::Result<&'b mut SeriesAnno<'a, DB>>
+where
+    DB: DrawingBackend<ErrorType: 'static>,
+    Y: Ranged,
+    Y::ValueType: Copy + PartialOrd + num_traits::ToPrimitive + 'static,
+{
+    let top = chart.y_range().end;
+    let clipped: Vec<(usize, Y::ValueType)> = series.iter().filter(|(_, v)| *v > top).map(|(x, _)| (*x, top)).collect();
+    let outliers: Vec<usize> = opts.anomalies.map(|detector| detector.outliers(series).into_iter().map(|outlier| outlier.datapoint).collect()).unwrap_or_default();
+    let series: Series<Y::ValueType> = series.iter().map(|(x, v)| (*x, if *v > top { top } else { *v })).collect();
 
-    if min == max {
-        min = 0
+    let mut runs = contiguous_runs(&series);
+    if let Some(max) = opts.max_points.filter(|max| series.len() > *max) {
+        runs = runs.iter().map(|run| downsample(run, (max * run.len()).div_ceil(series.len()))).collect();
+    }
+    let mut runs = runs.into_iter();
+    let first = runs.next().unwrap_or_default();
+    for run in runs {
+        chart.draw_series(LineSeries::new(run, style))?;
+    }
+    if !clipped.is_empty() {
+        chart.draw_series(clipped.into_iter().map(|point| TriangleMarker::new(point, 5, style.color.filled())))?;
+    }
+    if !outliers.is_empty() {
+        let points = series.iter().filter(|(x, _)| outliers.contains(x));
+        chart.draw_series(points.map(|point| Cross::new(*point, 6, style.color.stroke_width(2))))?;
     }
+    Ok(chart.draw_series(LineSeries::new(first, style))?)
+}
 
-    Ok((min, max))
+/// Shade the p50-p95 band of a series over a sliding window, so the baseline stays visible under spiky data.
+/// Like the line itself, the band breaks wherever datapoints are missing.
+fn draw_band<DB, Y>(chart: &mut ChartContext<'_, DB, Cartesian2d<TimeAxis, Y>>, series: &Series<Y::ValueType>, window: usize, color: RGBAColor) -> anyhow::Result<()>
+where
+    DB: DrawingBackend<ErrorType: 'static>,
+    Y: Ranged,
+    Y::ValueType: Copy + num_traits::ToPrimitive + num_traits::NumCast + 'static,
+{
+    for run in contiguous_runs(series) {
+        let band = percentile_band(&run, window, 50.0, 95.0);
+        let outline: Vec<(usize, Y::ValueType)> = band.iter().map(|(idx, _, high)| (*idx, *high))
+        .chain(band.iter().rev().map(|(idx, low, _)| (*idx, *low))).collect();
+        chart.draw_series(std::iter::once(Polygon::new(outline, color.filled())))?;
+    }
+    Ok(())
 }
 
 /// Genterate the basic setup for the graph
+#[allow(clippy::too_many_arguments)]
 fn gen_events_graph<DB: DrawingBackend<ErrorType: 'static>>
-(name: String, map: HashMap<String, Vec<u64>>, datapoints: usize, area: &DrawingArea<DB, Shift>, margin: i32, label_left_size: i32, name_prefix: &str) -> anyhow::Result<()> {
-    let (min, max) = get_min_max_uint(&map)?;
+(name: String, map: HashMap<String, Series<f64>>, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, margin: i32, label_left_size: i32, name_prefix: &str, opts: &ChartOpts) -> anyhow::Result<()> {
+    if opts.facet {
+        return gen_small_multiples(name, &map, datapoints, times, area, name_prefix, opts);
+    }
+    if opts.stack {
+        return gen_stacked_graph(name, &map, datapoints, times, area, name_prefix, &events_formatter, opts);
+    }
+
+    let (min, max) = get_min_max_float(&map)?;
+    let max = opts.clamp_max(&map, max);
+    let (min, max) = extend_range(&opts.hlines, min, max);
+    let y_axis = YAxis { desc: Some("events"), labels: EVENTS_Y_LABELS, fmt: &events_formatter };
+
+    let mut chart_events = setup_graph(name, area, margin, label_left_size, &opts.theme);
+    if opts.scale == Scale::Linear {
+        let max = if max > min { max } else { min + 1.0 };
+        let headroom = (max - min) * HEADROOM_CHART_MAX;
+        let mut chart_con = chart_events.build_cartesian_2d(TimeAxis::new(times, datapoints), min..max + headroom)?;
+        return draw_lines_chart(&mut chart_con, &map, datapoints, name_prefix, y_axis, opts.band, opts);
+    }
+    let max = if max > min { max } else { min + 1.0 };
+    let mut chart_con = chart_events.build_cartesian_2d(TimeAxis::new(times, datapoints), (log_floor(&map, min)..max).log_scale())?;
+    draw_lines_chart(&mut chart_con, &map, datapoints, name_prefix, y_axis, opts.band, opts)
+}
 
-    let mut chart_events = setup_graph(name, area, margin, label_left_size);
-    let mut chart_context_events = chart_events.build_cartesian_2d(0usize..datapoints,(min..max).log_scale())?;
-    chart_context_events.configure_mesh().y_desc("events").draw()?;
+/// Label an event count or rate, with a decimal place only when it's a small rate that would otherwise round away
+fn events_formatter(v: &f64) -> String {
+    if *v != 0.0 && v.abs() < 10.0 && v.fract() != 0.0 {
+        format!("{:.1}", v)
+    } else {
+        format!("{:.0}", v)
+    }
+}
 
+/// How a chart's y-axis is described and labelled
+struct YAxis<'f, V> {
+    desc: Option<&'static str>,
+    /// The number of labels, roughly
+    labels: usize,
+    fmt: &'f dyn Fn(&V) -> String,
+}
+
+/// Draw every series of a group as a line on a chart that's already been built on a linear or a log scale, along with the chart's grid,
+/// its highlighted spans, reference lines and legend. With `band`, a p50-p95 band over a sliding window of that many datapoints is shaded behind each line.
+#[allow(clippy::too_many_arguments)]
+fn draw_lines_chart<'a, DB, Y>(chart: &mut ChartContext<'a, DB, Cartesian2d<TimeAxis, Y>>, map: &HashMap<String, Series<Y::ValueType>>, datapoints: usize, name_prefix: &str, y_axis: YAxis<'_, Y::ValueType>, band: Option<usize>, opts: &ChartOpts) -> anyhow::Result<()>
+where
+    DB: DrawingBackend<ErrorType: 'static> + 'a,
+    Y: Ranged + ValueFormatter<Y::ValueType>,
+    Y::ValueType: Copy + PartialOrd + num_traits::ToPrimitive + num_traits::NumCast + 'static,
+{
+    let x_desc = chart.as_coord_spec().x_spec().desc();
+    let mut mesh = chart.configure_mesh();
+    opts.theme.mesh(&mut mesh).x_desc(x_desc).y_labels(y_axis.labels).y_label_formatter(y_axis.fmt);
+    if let Some(desc) = y_axis.desc {
+        mesh.y_desc(desc);
+    }
+    mesh.draw()?;
+    draw_markers(chart, &opts.markers, &opts.theme)?;
+    let colors = opts.colors(map.keys());
+    if let Some(window) = band {
+        for (name, color) in &colors {
+            draw_band(chart, &map[*name], window, color.mix(0.15))?;
+        }
+    }
 
-    for (idx, (name, group)) in map.iter().enumerate() {
-        let color = Palette99::pick(idx).mix(0.9);
-        chart_context_events.draw_series(LineSeries::new(group.iter().enumerate().map(|(p_idx, d)| (p_idx, *d)), color.stroke_width(2)))?
+    for (name, color) in colors {
+        let color = color.mix(0.9);
+        draw_line(chart, &map[name], color.stroke_width(2), opts)?
         .label(name.trim_start_matches(name_prefix))
         .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+    }
+    draw_hlines(chart, &opts.hlines, datapoints, opts.theme.foreground)?;
+    opts.theme.legend(&mut chart.configure_series_labels()).draw()?;
+
+    Ok(())
+}
+
+/// The running totals of a group's series stacked on top of each other, in key order: the top edge of each layer at every
+/// datapoint any of the series has a value at. A series that's missing a datapoint adds nothing to the stack there.
+fn stack_series<T: Copy + num_traits::ToPrimitive>(map: &HashMap<String, Series<T>>) -> Vec<(&String, Series<f64>)> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    let mut totals: BTreeMap<usize, f64> = map.values().flatten().map(|(x, _)| (*x, 0.0)).collect();
+
+    keys.into_iter().map(|key| {
+        for (x, v) in &map[key] {
+            *totals.entry(*x).or_default() += v.to_f64().unwrap_or_default();
+        }
+        (key, totals.iter().map(|(x, total)| (*x, *total)).collect())
+    }).collect()
+}
+
+/// Draw a group's series as stacked areas, so the chart shows the composition of their total as well as the total itself.
+/// Only makes sense for series that add up, like the acked, failed and dropped events of an output.
+#[allow(clippy::too_many_arguments)]
+fn gen_stacked_graph<DB: DrawingBackend<ErrorType: 'static>, T: Copy + num_traits::ToPrimitive>
+(name: String, map: &HashMap<String, Series<T>>, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, name_prefix: &str, y_fmt: &dyn Fn(&f64) -> String, opts: &ChartOpts) -> anyhow::Result<()> {
+    let layers = stack_series(map);
+    let total = layers.last().map(|(_, top)| top).ok_or_else(||anyhow!("data does not have any values"))?;
+    let max = total.iter().map(|(_, v)| *v).fold(0.0, f64::max);
+    let (min, max) = extend_range(&opts.hlines, 0.0, max);
+    let max = if max > min { max } else { min + 1.0 };
+    let headroom = (max - min) * HEADROOM_CHART_MAX;
+    // long runs keep the datapoints that shape the total, in every layer, so the layers still line up
+    let keep: Option<BTreeSet<usize>> = opts.max_points.filter(|max| total.len() > *max).map(|max| {
+        contiguous_runs(total).iter()
+        .flat_map(|run| downsample(run, (max * run.len()).div_ceil(total.len())))
+        .map(|(x, _)| x).collect()
+    });
+    let kept = |(x, _): &&(usize, f64)| keep.as_ref().is_none_or(|keep| keep.contains(x));
+
+    let mut chart = setup_graph(name, area, 5, 18, &opts.theme);
+    let x_axis = TimeAxis::new(times, datapoints);
+    let x_desc = x_axis.desc();
+    let mut chart_con = chart.build_cartesian_2d(x_axis, min..max + headroom)?;
+    opts.theme.mesh(&mut chart_con.configure_mesh()).x_desc(x_desc).y_labels(5).y_label_formatter(y_fmt).draw()?;
+    draw_markers(&mut chart_con, &opts.markers, &opts.theme)?;
+
+    let mut bottom: Series<f64> = total.iter().map(|(x, _)| (*x, 0.0)).collect();
+    let colors = opts.colors(map.keys());
+    for (key, top) in &layers {
+        let color = colors[key.as_str()].mix(0.9);
+        for (run, (upper, lower)) in contiguous_runs(top).into_iter().zip(contiguous_runs(&bottom)).enumerate() {
+            let outline: Vec<(usize, f64)> = upper.iter().filter(kept).chain(lower.iter().rev().filter(kept)).copied().collect();
+            let area = chart_con.draw_series(std::iter::once(Polygon::new(outline, color.mix(0.7).filled())))?;
+            if run == 0 {
+                area.label(key.trim_start_matches(name_prefix))
+                .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+            }
+            chart_con.draw_series(LineSeries::new(upper.iter().filter(kept).copied(), color.stroke_width(1)))?;
+        }
+        bottom = top.clone();
+    }
+    draw_hlines(&mut chart_con, &opts.hlines, datapoints, opts.theme.foreground)?;
+    opts.theme.legend(&mut chart_con.configure_series_labels()).draw()?;
+
+    Ok(())
+}
+
+/// Draw a panel of byte counts, for groups that chart bytes alongside other metrics
+fn gen_bytes_graph<DB: DrawingBackend<ErrorType: 'static>>
+(name: String, map: HashMap<String, Series<f64>>, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, name_prefix: &str, opts: &ChartOpts) -> anyhow::Result<()> {
+    gen_float_graph(name, map, datapoints, times, area, name_prefix, &|i| bytes_formatter(*i, opts.byte_units), opts)
+}
+
+/// Draw a panel of floating point series, on a linear scale unless the options ask for a log one, labelling the y-axis with `y_fmt`
+#[allow(clippy::too_many_arguments)]
+fn gen_float_graph<DB: DrawingBackend<ErrorType: 'static>>
+(name: String, map: HashMap<String, Series<f64>>, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, name_prefix: &str, y_fmt: &dyn Fn(&f64) -> String, opts: &ChartOpts) -> anyhow::Result<()> {
+    if opts.stack {
+        return gen_stacked_graph(name, &map, datapoints, times, area, name_prefix, y_fmt, opts);
+    }
+
+    let (min, max) = get_min_max_float(&map)?;
+    let max = opts.clamp_max(&map, max);
+    let (min, max) = extend_range(&opts.hlines, min, max);
+    // a flat series, like an idle connection's, still needs some height to be drawn in
+    let max = if max > min { max } else { min + 1.0 };
+    let headroom = (max - min) * HEADROOM_CHART_MAX;
+    let y_axis = YAxis { desc: None, labels: FLOAT_Y_LABELS, fmt: y_fmt };
+
+    let mut chart = setup_graph(name, area, 5, 18, &opts.theme);
+    if opts.scale == Scale::Log {
+        let mut chart_con = chart.build_cartesian_2d(TimeAxis::new(times, datapoints), (log_floor(&map, min)..max + headroom).log_scale())?;
+        return draw_lines_chart(&mut chart_con, &map, datapoints, name_prefix, y_axis, None, opts);
+    }
+    let mut chart_con = chart.build_cartesian_2d(TimeAxis::new(times, datapoints), min..max + headroom)?;
+    draw_lines_chart(&mut chart_con, &map, datapoints, name_prefix, y_axis, None, opts)
+}
+
+/// The bottom of a log-scale y-axis: the smallest value if it's positive, and otherwise the smallest positive value,
+/// since zero and below can't be drawn on a log scale
+fn log_floor(map: &HashMap<String, Series<f64>>, min: f64) -> f64 {
+    if min > 0.0 {
+        return min;
+    }
+    map.values().flatten().map(|(_, v)| *v).filter(|v| *v > 0.0).reduce(f64::min).unwrap_or(1.0)
+}
 
+/// Draw every series in its own small panel, stacked with a shared x-axis, so series with very different magnitudes all stay readable
+fn gen_small_multiples<DB: DrawingBackend<ErrorType: 'static>, T: Copy + num_traits::ToPrimitive>
+(name: String, map: &HashMap<String, Series<T>>, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, name_prefix: &str, opts: &ChartOpts) -> anyhow::Result<()> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    if keys.is_empty() {
+        return Err(anyhow!("data does not have any values"));
     }
 
-    chart_context_events.configure_series_labels().border_style(BLACK).background_style(WHITE.mix(0.8)).position(SeriesLabelPosition::UpperLeft).draw()?;
+    let title_size = f64::from(area.dim_in_pixel().1) * f64::from(CHART_NAME_FONT_PCT_SIZE) / 100.0;
+    let area = area.titled(&name, opts.theme.font(title_size).into_text_style(area))?;
+    let panels = area.split_evenly((keys.len(), 1));
+    let x_axis = TimeAxis::new(times, datapoints);
+    let colors = opts.colors(map.keys());
+    for (idx, (key, panel)) in keys.into_iter().zip(panels.iter()).enumerate() {
+        let series: Series<f64> = map[key].iter().filter_map(|(x, v)| Some((*x, v.to_f64()?))).collect();
+        let single = HashMap::from([(key.clone(), series)]);
+        let (min, max) = get_min_max_float(&single).unwrap_or((0.0, 0.0));
+        let max = opts.clamp_max(&single, max);
+        let (min, max) = extend_range(&opts.hlines, min, max);
+        // a flat series still needs some height to be drawn in
+        let max = if max > min { max } else { min + 1.0 };
+        let last = idx == map.len() - 1;
+
+        let mut chart = ChartBuilder::on(panel);
+        chart.caption(key.trim_start_matches(name_prefix).trim_start_matches('.'), opts.theme.font(FACET_NAME_FONT_SIZE))
+        .set_label_area_size(LabelAreaPosition::Left, (LABEL_SIZE_LEFT).percent())
+        .set_label_area_size(LabelAreaPosition::Bottom, if last { 20 } else { 0 })
+        .margin(5);
+        let mut chart_con = chart.build_cartesian_2d(x_axis.clone(), min..max)?;
+        // only the bottom panel labels the shared x-axis
+        opts.theme.mesh(&mut chart_con.configure_mesh()).y_labels(3).x_label_formatter(&|x| if last { x_axis.label(x) } else { String::new() }).draw()?;
+        draw_markers(&mut chart_con, &opts.markers, &opts.theme)?;
+
+        draw_line(&mut chart_con, &single[key], colors[key.as_str()].mix(0.9).stroke_width(2), opts)?;
+        draw_hlines(&mut chart_con, &opts.hlines, datapoints, opts.theme.foreground)?;
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Draw a distribution summary stored under `key` as a min-max band, with a line for its center
+fn gen_band_graph<DB: DrawingBackend<ErrorType: 'static>>
+(map: &HashMap<String, Series<f64>>, key: &str, datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, opts: &ChartOpts) -> anyhow::Result<()> {
+    let lower = map.get(&format!("{}.min", key)).ok_or_else(||anyhow!("summary {} has no min", key))?;
+    let upper = map.get(&format!("{}.max", key)).ok_or_else(||anyhow!("summary {} has no max", key))?;
+    let (center_name, center) = SUMMARY_CENTERS.iter()
+    .find_map(|c| map.get(&format!("{}.{}", key, c)).map(|series| (*c, series)))
+    .ok_or_else(||anyhow!("summary {} has no center value", key))?;
+
+    let (min, max) = get_min_max_float(&HashMap::from([("min".to_string(), lower.clone()), ("max".to_string(), upper.clone())]))?;
+    let headroom = (max - min) * HEADROOM_CHART_MAX;
+
+    let mut chart = setup_graph(key.to_string(), area, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, &opts.theme);
+    let x_axis = TimeAxis::new(times, datapoints);
+    let x_desc = x_axis.desc();
+    let mut chart_con = chart.build_cartesian_2d(x_axis, min..(max + headroom))?;
+    opts.theme.mesh(&mut chart_con.configure_mesh()).x_desc(x_desc).draw()?;
+    draw_markers(&mut chart_con, &opts.markers, &opts.theme)?;
+
+    let color = opts.color(0);
+    // like the line, the band breaks wherever datapoints are missing
+    for (run, (high, low)) in contiguous_runs(upper).into_iter().zip(contiguous_runs(lower)).enumerate() {
+        let outline: Vec<(usize, f64)> = high.into_iter().chain(low.into_iter().rev()).collect();
+        let band = chart_con.draw_series(std::iter::once(Polygon::new(outline, color.mix(0.2).filled())))?;
+        if run == 0 {
+            band.label("min-max")
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.mix(0.2).filled()));
+        }
+    }
+
+    draw_line(&mut chart_con, center, color.stroke_width(2), opts)?
+    .label(center_name)
+    .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.filled()));
+
+    opts.theme.legend(&mut chart_con.configure_series_labels()).draw()?;
+
+    Ok(())
+}
+
+/// Draw bucketed counts stored under `key` as a heatmap, with one row per bucket
+fn gen_heatmap_graph<DB: DrawingBackend<ErrorType: 'static>>
+(map: &HashMap<String, Series<f64>>, key: &str, bounds: &[String], datapoints: usize, times: &[Option<f64>], area: &DrawingArea<DB, Shift>, opts: &ChartOpts) -> anyhow::Result<()> {
+    let rows = heatmap_rows(map, key, bounds);
+    let max = rows.iter().flat_map(|(_, bucket)| bucket.iter().map(|(_, count)| *count)).fold(0.0, f64::max);
+
+    let mut chart = setup_graph(key.to_string(), area, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, &opts.theme);
+    let x_axis = TimeAxis::new(times, datapoints);
+    let x_desc = x_axis.desc();
+    let mut chart_con = chart.build_cartesian_2d(x_axis, 0usize..rows.len())?;
+    opts.theme.mesh(&mut chart_con.configure_mesh()).x_desc(x_desc).y_desc("Bucket").disable_y_mesh()
+    .y_label_formatter(&|idx| rows.get(*idx).map(|(bound, _)| bound.to_string()).unwrap_or_default()).draw()?;
+
+    for (b_idx, (_, bucket)) in rows.iter().enumerate() {
+        chart_con.draw_series(bucket.iter().map(|(x, count)| {
+            let intensity = if max > 0.0 { (count / max) as f32 } else { 0.0 };
+            Rectangle::new([(*x, b_idx), (x + 1, b_idx + 1)], ViridisRGB.get_color(intensity).filled())
+        }))?;
+    }
+
+    Ok(())
+}
+
+/// The heatmap's rows: each bucket of `key` that has a series, with the bound it's labelled by
+fn heatmap_rows<'a>(map: &'a HashMap<String, Series<f64>>, key: &str, bounds: &'a [String]) -> Vec<(&'a str, &'a Series<f64>)> {
+    bounds.iter().filter_map(|bound| map.get(&format!("{}.{}", key, bound)).map(|bucket| (bound.as_str(), bucket))).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{bytes_formatter, heatmap_rows, stack_series, ByteUnits};
+
+    #[test]
+    fn test_heatmap_rows() {
+        let map = HashMap::from([
+            ("latency.10".to_string(), vec![(0, 1.0)]),
+            ("latency.1000".to_string(), vec![(0, 3.0)]),
+            ("other".to_string(), vec![(0, 500.0)]),
+        ]);
+        let bounds = ["10".to_string(), "100".to_string(), "1000".to_string()];
+        let rows: Vec<(&str, f64)> = heatmap_rows(&map, "latency", &bounds).into_iter().map(|(bound, bucket)| (bound, bucket[0].1)).collect();
+        assert_eq!(rows, vec![("10", 1.0), ("1000", 3.0)]);
+    }
+
+    #[test]
+    fn test_stack_series() {
+        let map = HashMap::from([
+            ("failed".to_string(), vec![(0, 1u64), (1, 2), (3, 1)]),
+            ("acked".to_string(), vec![(0, 10u64), (1, 20), (3, 30)]),
+            ("dropped".to_string(), vec![(1, 5u64)]),
+        ]);
+        let layers: Vec<(&str, Vec<(usize, f64)>)> = stack_series(&map).into_iter().map(|(key, top)| (key.as_str(), top)).collect();
+        assert_eq!(layers, vec![
+            ("acked", vec![(0, 10.0), (1, 20.0), (3, 30.0)]),
+            ("dropped", vec![(0, 10.0), (1, 25.0), (3, 30.0)]),
+            ("failed", vec![(0, 11.0), (1, 27.0), (3, 31.0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_bytes_formatter() {
+        assert_eq!(bytes_formatter(0.25, ByteUnits::Decimal), "0.25 B");
+        assert_eq!(bytes_formatter(250.0, ByteUnits::Decimal), "250 B");
+        assert_eq!(bytes_formatter(200_000_000.0, ByteUnits::Decimal), "200.0 MB");
+        assert_eq!(bytes_formatter(2_500_000_000.0, ByteUnits::Decimal), "2.5 GB");
+        assert_eq!(bytes_formatter(3e15, ByteUnits::Decimal), "3000.0 TB");
+        assert_eq!(bytes_formatter(1000.0, ByteUnits::Binary), "1000 B");
+        assert_eq!(bytes_formatter(1536.0 * 1024.0 * 1024.0, ByteUnits::Binary), "1.5 GiB");
+        assert_eq!(bytes_formatter(-2_000_000.0, ByteUnits::Decimal), "-2.0 MB");
+    }
+}
@@ -1,41 +1,57 @@
-use anyhow::Context;
-use plotters::prelude::*;
-use tracing::debug;
+use plotters::{coord::Shift, prelude::*};
 
 use crate::groups::*;
-use super::{generic::{Generic, NoOpProcess}, Watcher};
+use super::{generic::{DeltaProcessor, Generic}, Watcher};
 
 const PROCDB_KEY: &str = "libbeat.output.events";
 
 pub struct Output {
-    group: Generic<u64, NoOpProcess<u64>>,
-    fname: String
+    group: Generic<u64, DeltaProcessor<u64>>,
+    fname: String,
+    opts: ChartOpts
 }
 
 
 impl Watcher for Output {
-    fn new(_ : Option<Vec<String>>) -> Self {
-        let group = Generic::from(vec![PROCDB_KEY]);
-        Output { group, fname: "Output Events".to_string() }
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::new(vec![PROCDB_KEY.to_string()], DeltaProcessor::enabled(opts.deltas)).with_retain(opts.retain);
+        Output { group, fname: "Output Events".to_string(), opts }
     }
 
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
         self.group.update(new);
+        self.opts.slide(self.group.dropped());
     }
 
-    fn plot(&self) -> anyhow::Result<()> {
-        let map_data = self.group.plot();
+    fn fname(&self) -> &str {
+        &self.fname
+    }
 
-        let name = format!("./{}_plot.svg", &self.fname);
-        debug!("writing {}...", name);
-    
-        let root = SVGBackend::new(&name, SVG_SIZE).into_drawing_area();
-        root.fill(&WHITE)?;
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
 
-        gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), &root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, PROCDB_KEY)?;
-    
-        root.present().context("could not write file")?;
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
 
-        Ok(())
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let opts = self.opts.clone().for_deltas();
+        let map_data = opts.prepare(self.group.plot(), self.group.times());
+        gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), self.group.times(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, PROCDB_KEY, &opts)
     }
 }
\ No newline at end of file
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess, Series}, Watcher};
+
+const PACKETBEAT_KEY: &str = "packetbeat";
+
+/// The panels packetbeat's metrics are split into, as (panel name, chart title, section of the stats document).
+/// Every panel is keyed off its section, so its series are labelled by what comes after it, like `.http.transactions`.
+const FACETS: &[(&str, &str, &str)] = &[
+    ("packets", "Sniffed Packets", "packetbeat.sniffer"),
+    ("flows", "Flows", "packetbeat.flows"),
+    ("transactions", "Transactions by Protocol", "packetbeat.protocols"),
+];
+
+/// Find the index of the panel a metric belongs in, if any
+fn facet(key: &str) -> Option<usize> {
+    FACETS.iter().position(|(_, _, section)| key.strip_prefix(section).is_some_and(|rest| rest.starts_with('.')))
+}
+
+/// Packetbeat's sniffer, flow and per-protocol transaction counters
+pub struct Packetbeat {
+    group: Generic<u64, NoOpProcess<u64>>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Packetbeat {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::from(FACETS.iter().map(|(_, _, section)| *section).collect::<Vec<_>>()).with_retain(opts.retain);
+        Packetbeat { group, fname: "packetbeat".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let map_data = self.opts.prepare(self.group.plot(), self.group.times());
+
+        let mut facets: Vec<HashMap<String, Series<f64>>> = vec![HashMap::new(); FACETS.len()];
+        for (key, series) in map_data {
+            if let Some(idx) = facet(&key) {
+                facets[idx].insert(key, series);
+            }
+        }
+        let facets: Vec<_> = FACETS.iter().zip(facets).filter(|(_, map)| !map.is_empty()).collect();
+        if facets.len() <= 1 {
+            let prefix = facets.first().map_or(PACKETBEAT_KEY, |(facet, _)| facet.2);
+            let map_data = facets.into_iter().flat_map(|(_, map)| map).collect();
+            return gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), self.group.times(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, prefix, &self.opts);
+        }
+
+        // the first panel with any data gets any reference lines that don't name a panel
+        let areas = root.split_evenly((facets.len(), 1));
+        for (idx, ((panel, title, section), map)) in facets.into_iter().enumerate() {
+            gen_events_graph(title.to_string(), map, self.group.datapoints(), self.group.times(), &areas[idx], DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, section, &self.opts.panel(panel, idx == 0))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::facet;
+
+    #[test]
+    fn test_facet() {
+        assert_eq!(facet("packetbeat.sniffer.packets.dropped"), Some(0));
+        assert_eq!(facet("packetbeat.flows.active"), Some(1));
+        assert_eq!(facet("packetbeat.protocols.http.transactions"), Some(2));
+        assert_eq!(facet("packetbeat.sniffers.packets"), None);
+    }
+}
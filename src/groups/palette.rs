@@ -0,0 +1,114 @@
+/*!
+ * Selectable color palettes for chart series.
+ * The default mirrors plotters' `Palette99`, the others are picked so series stay distinguishable
+ * for users with color vision deficiencies.
+ *
+ * Series are given colors by their metric key rather than by where they happen to come in a chart, so a metric keeps its color
+ * from one render to the next.
+ */
+
+use std::{collections::BTreeMap, sync::{Arc, Mutex, PoisonError}};
+
+use clap::ValueEnum;
+use plotters::style::{Palette, Palette99, RGBColor};
+
+/// The Okabe-Ito palette, safe for the common forms of color blindness
+const OKABE_ITO: [RGBColor; 8] = [
+    RGBColor(230, 159, 0),
+    RGBColor(86, 180, 233),
+    RGBColor(0, 158, 115),
+    RGBColor(240, 228, 66),
+    RGBColor(0, 114, 178),
+    RGBColor(213, 94, 0),
+    RGBColor(204, 121, 167),
+    RGBColor(0, 0, 0),
+];
+
+/// Paul Tol's "bright" qualitative palette, also colorblind-safe
+const TOL_BRIGHT: [RGBColor; 7] = [
+    RGBColor(68, 119, 170),
+    RGBColor(238, 102, 119),
+    RGBColor(34, 136, 51),
+    RGBColor(204, 187, 68),
+    RGBColor(102, 204, 238),
+    RGBColor(170, 51, 119),
+    RGBColor(187, 187, 187),
+];
+
+/// Reds and oranges, for charts where every series is something going wrong
+const ALERT: [RGBColor; 6] = [
+    RGBColor(215, 48, 39),
+    RGBColor(244, 109, 67),
+    RGBColor(165, 0, 38),
+    RGBColor(253, 174, 97),
+    RGBColor(120, 0, 0),
+    RGBColor(230, 85, 13),
+];
+
+/// The palette used to color the series of a chart
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ChartPalette {
+    /// plotters' 99-color palette
+    #[default]
+    Default,
+    /// The Okabe-Ito colorblind-safe palette
+    Colorblind,
+    /// Paul Tol's bright colorblind-safe palette
+    Tol,
+    /// Reds and oranges, used by groups that only chart errors
+    #[value(skip)]
+    Alert,
+}
+
+impl ChartPalette {
+    /// Pick a color for the series at the given index, wrapping around if we run out of colors
+    pub fn pick(&self, idx: usize) -> RGBColor {
+        match self {
+            ChartPalette::Default => {
+                let (r, g, b) = Palette99::COLORS[idx % Palette99::COLORS.len()];
+                RGBColor(r, g, b)
+            }
+            ChartPalette::Colorblind => OKABE_ITO[idx % OKABE_ITO.len()],
+            ChartPalette::Tol => TOL_BRIGHT[idx % TOL_BRIGHT.len()],
+            ChartPalette::Alert => ALERT[idx % ALERT.len()],
+        }
+    }
+}
+
+/// The palette positions of a group's series, by key, in the order the keys were first charted. Shared by every panel of a group,
+/// and kept for the whole run, so a series keeps its color between renders even as other series come and go.
+#[derive(Clone, Debug, Default)]
+pub struct SeriesColors(Arc<Mutex<Vec<String>>>);
+
+impl SeriesColors {
+    /// The palette position of every key. Keys that haven't been charted before take the next positions, in key order.
+    pub fn assign<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> BTreeMap<&'k str, usize> {
+        let mut keys: Vec<&str> = keys.into_iter().collect();
+        keys.sort();
+        let mut seen = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        keys.into_iter().map(|key| {
+            let idx = seen.iter().position(|seen| seen == key).unwrap_or_else(|| {
+                seen.push(key.to_string());
+                seen.len() - 1
+            });
+            (key, idx)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SeriesColors;
+
+    #[test]
+    fn test_assign() {
+        let colors = SeriesColors::default();
+        let first = colors.assign(["output.failed", "output.acked"]);
+        assert_eq!((first["output.acked"], first["output.failed"]), (0, 1));
+
+        // a new series sorts first, but doesn't take the colors the others already have
+        let second = colors.assign(["output.failed", "output.acked", "output.active"]);
+        assert_eq!((second["output.acked"], second["output.failed"], second["output.active"]), (0, 1, 2));
+        assert_eq!(colors.clone().assign(["output.active"])["output.active"], 2);
+    }
+}
@@ -0,0 +1,92 @@
+/*!
+ * Metric paths occasionally move between beat versions. Groups always use the canonical (newest) path for a metric,
+ * and this maps it to the concrete path used by the version of the beat we're talking to.
+ */
+
+use std::fmt;
+
+/// The `major.minor` version of a beat
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BeatVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl BeatVersion {
+    /// Parse a version string like `8.15.0` or `9.0.0-SNAPSHOT`
+    pub fn parse(raw: &str) -> Option<BeatVersion> {
+        let mut parts = raw.split(['.', '-']);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some(BeatVersion { major, minor })
+    }
+
+    /// Find the version in a stats document
+    pub fn detect(root: &serde_json::Map<String, serde_json::Value>) -> Option<BeatVersion> {
+        let raw = root.get("beat")?.get("info")?.get("version")?.as_str()?;
+        Self::parse(raw)
+    }
+}
+
+impl fmt::Display for BeatVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// A metric that lived at a different path before a given version
+struct PathRule {
+    canonical: &'static str,
+    before: BeatVersion,
+    path: &'static str,
+}
+
+/// Known relocations, newest first. A key matches a rule if it's the canonical path, or nested under it.
+const PATH_RULES: &[PathRule] = &[
+    // the queue fill percentage was only reported for events, before byte-based queues added a combined value
+    PathRule { canonical: "libbeat.pipeline.queue.filled.pct", before: BeatVersion { major: 8, minor: 13 }, path: "libbeat.pipeline.queue.filled.pct.events" },
+];
+
+/// Resolve a canonical metric key to the path used by the given beat version.
+/// If we don't know the version, we assume it's recent and use the canonical path.
+pub fn resolve(key: &str, version: Option<BeatVersion>) -> String {
+    let Some(version) = version else {
+        return key.to_string();
+    };
+
+    for rule in PATH_RULES {
+        if version >= rule.before {
+            continue;
+        }
+        if key == rule.canonical {
+            return rule.path.to_string();
+        }
+        if let Some(nested) = key.strip_prefix(rule.canonical).and_then(|rest| rest.strip_prefix('.')) {
+            return format!("{}.{}", rule.path, nested);
+        }
+    }
+
+    key.to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve, BeatVersion};
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(BeatVersion::parse("8.15.2"), Some(BeatVersion { major: 8, minor: 15 }));
+        assert_eq!(BeatVersion::parse("9.0-SNAPSHOT"), Some(BeatVersion { major: 9, minor: 0 }));
+        assert_eq!(BeatVersion::parse("unknown"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let old = BeatVersion::parse("8.12.1");
+        let new = BeatVersion::parse("8.15.0");
+        assert_eq!(resolve("libbeat.pipeline.queue.filled.pct", old), "libbeat.pipeline.queue.filled.pct.events");
+        assert_eq!(resolve("libbeat.pipeline.queue.filled.pct", new), "libbeat.pipeline.queue.filled.pct");
+        assert_eq!(resolve("libbeat.pipeline.queue.filled.pct", None), "libbeat.pipeline.queue.filled.pct");
+        assert_eq!(resolve("beat.memstats.rss", old), "beat.memstats.rss");
+    }
+}
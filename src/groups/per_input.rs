@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
+
+use crate::{fetch::INPUTS_PATH, groups::*};
+use super::{generic::{Generic, NoOpProcess}, transform::CounterMode, Watcher};
+
+const EVENTS: &str = "events_processed_total";
+const ERRORS: &str = "processing_errors_total";
+
+/// Filebeat's metrics for each of its inputs, from its `/inputs/` endpoint, so a single misbehaving input can be told apart from the rest.
+/// Inputs are picked up as they appear, and everything is charted as a per-second rate, like [`super::failures::Failures`].
+pub struct PerInput {
+    events: Generic<f64, NoOpProcess<f64>>,
+    errors: Generic<f64, NoOpProcess<f64>>,
+    /// The IDs of every input seen so far
+    ids: Vec<String>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl PerInput {
+    /// Key a panel's series by the input they belong to, for the legend
+    fn by_input(map: HashMap<String, Series<f64>>, metric: &str) -> HashMap<String, Series<f64>> {
+        let suffix = format!(".{}", metric);
+        map.into_iter().map(|(key, series)| {
+            let id = key.trim_start_matches(INPUTS_PATH).trim_start_matches('.').trim_end_matches(&suffix).to_string();
+            (id, series)
+        }).collect()
+    }
+}
+
+impl Watcher for PerInput {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let opts = ChartOpts { counters: CounterMode::Rate, ..opts };
+        let events = Generic::from(Vec::<String>::new()).with_retain(opts.retain);
+        let errors = Generic::from(Vec::<String>::new()).with_retain(opts.retain);
+        PerInput { events, errors, ids: Vec::new(), fname: "per_input".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        if let Some(serde_json::Value::Object(inputs)) = get_root_elem(new, INPUTS_PATH) {
+            for id in inputs.keys().filter(|id| !self.ids.contains(id)).cloned().collect::<Vec<_>>() {
+                self.events.add_metric(format!("{}.{}.{}", INPUTS_PATH, id, EVENTS));
+                self.errors.add_metric(format!("{}.{}.{}", INPUTS_PATH, id, ERRORS));
+                self.ids.push(id);
+            }
+        }
+        self.events.update(new);
+        self.errors.update(new);
+        self.opts.slide(self.events.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.events.export();
+        series.extend(self.errors.export());
+        GroupData { name: self.fname.clone(), datapoints: self.events.datapoints(), series, times: self.events.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.events.datapoints());
+        self.events.skip();
+        self.errors.skip();
+        self.opts.slide(self.events.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.events.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.events.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper, lower) = root.split_vertically(height * 2 / 3);
+        let per_sec = |i: &f64| format!("{:.1}", i);
+
+        let map_events = PerInput::by_input(self.opts.prepare(self.events.plot(), self.events.times()), EVENTS);
+        gen_float_graph("Events Processed per Input (per second)".to_string(), map_events, self.events.datapoints(), self.events.times(), &upper, "", &per_sec, &self.opts.panel("events", true))?;
+
+        let map_errors = PerInput::by_input(self.opts.prepare(self.errors.plot(), self.errors.times()), ERRORS);
+        gen_float_graph("Processing Errors per Input (per second)".to_string(), map_errors, self.errors.datapoints(), self.errors.times(), &lower, "", &per_sec, &self.opts.panel("errors", false))?;
+
+        Ok(())
+    }
+}
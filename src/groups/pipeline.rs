@@ -2,18 +2,18 @@
 use std::collections::HashMap;
 
 use crate::groups::*;
-use super::{generic::{Generic, NoOpProcess, Processor}, Watcher};
-use anyhow::Context;
-use tracing::debug;
+use super::{generic::{DeltaProcessor, Generic, Processor}, Watcher};
+use plotters::coord::Shift;
 
 const EVENTS_KEY: &str = "libbeat.pipeline.events";
 const QUEUE_KEY: &str = "libbeat.pipeline.queue";
 const FILLED_PCT_KEY: &str = "libbeat.pipeline.queue.filled.pct";
 pub struct Pipeline {
-    group_events: Generic<u64, NoOpProcess<u64>>,
-    group_queue: Generic<u64, NoOpProcess<u64>>,
+    group_events: Generic<u64, DeltaProcessor<u64>>,
+    group_queue: Generic<u64, DeltaProcessor<u64>>,
     filled_pct: Generic<f64, PctProcessor>,
-    fname: String
+    fname: String,
+    opts: ChartOpts
 }
 
 pub struct PctProcessor {}
@@ -24,71 +24,107 @@ impl Processor for PctProcessor {
     fn new() -> Self {
         Self {  }
     }
-    fn process(&self, raw: Self::InValue) -> Self::OutValue {
-        raw  * 100.0
+    fn process(&mut self, _: &str, raw: Self::InValue) -> Option<Self::OutValue> {
+        Some(raw  * 100.0)
     }
 }
 
 
 impl Watcher for Pipeline {
-    fn new(_ : Option<Vec<String>>) -> Self {
-        let group_events = Generic::from(vec![EVENTS_KEY]);
-        let group_queue = Generic::from(vec![QUEUE_KEY]);
-        let filled_pct = Generic::from(vec![FILLED_PCT_KEY]);
-        Pipeline { group_events, group_queue, filled_pct, fname: "pipeline".to_string() }
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group_events = Generic::new(vec![EVENTS_KEY.to_string()], DeltaProcessor::enabled(opts.deltas)).with_retain(opts.retain);
+        let group_queue = Generic::new(vec![QUEUE_KEY.to_string()], DeltaProcessor::enabled(opts.deltas)).with_retain(opts.retain);
+        let filled_pct = Generic::from(vec![FILLED_PCT_KEY]).with_retain(opts.retain);
+        Pipeline { group_events, group_queue, filled_pct, fname: "pipeline".to_string(), opts }
     }
 
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
         self.group_events.update(new);
         self.group_queue.update(new);
         self.filled_pct.update(new);
+        self.opts.queue_line(new, &self.fname, "queue");
+        self.opts.slide(self.group_events.dropped());
     }
 
-    fn plot(&self) -> anyhow::Result<()> {  
-        let name = format!("./{}_plot.svg", &self.fname);
-        debug!("writing {}...", name);
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.group_events.export();
+        series.extend(self.group_queue.export());
+        series.extend(self.filled_pct.export());
+        GroupData { name: self.fname.clone(), datapoints: self.group_events.datapoints(), series, times: self.group_events.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group_events.datapoints());
+        self.group_events.skip();
+        self.group_queue.skip();
+        self.filled_pct.skip();
+        self.opts.slide(self.group_events.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group_events.datapoints().saturating_sub(1), label);
+    }
 
-    
-        let root = SVGBackend::new(&name, SVG_SIZE).into_drawing_area();
-        root.fill(&WHITE)?;
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group_events.datapoints().saturating_sub(1), label);
+    }
 
-        let (upper_q, lower_3q) = root.split_vertically(SVG_SIZE.1/4);
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper_q, lower_3q) = root.split_vertically(height/4);
 
-        let (upper_bottom, lower_bottom) = lower_3q.split_vertically(((SVG_SIZE.1/4)*3)/2);
+        let (upper_bottom, lower_bottom) = lower_3q.split_vertically(((height/4)*3)/2);
 
         // set up events subgraph
-        let map_data_events = self.group_events.plot();
-        gen_events_graph("Events".to_string(), map_data_events, self.group_events.datapoints(), &lower_bottom, 5, 18, EVENTS_KEY)?;
+        let events_opts = self.opts.panel("events", true).for_deltas();
+        let map_data_events = events_opts.prepare(self.group_events.plot(), self.group_events.times());
+        gen_events_graph("Events".to_string(), map_data_events, self.group_events.datapoints(), self.group_events.times(), &lower_bottom, 5, 18, EVENTS_KEY, &events_opts)?;
 
         // set up queue subgraph
-        let map_data_queue = self.group_queue.plot();
+        let queue_opts = self.opts.panel("queue", false).for_deltas();
+        let map_data_queue = queue_opts.prepare(self.group_queue.plot(), self.group_queue.times());
         // skip any values ending in `pct` or `bytes`
-        let filtered_map: HashMap<String, Vec<u64>> = map_data_queue.into_iter().filter(|(k, _)| !k.contains("bytes") && !k.contains("pct")).collect();
-        gen_events_graph("Queue".to_string(), filtered_map, self.group_events.datapoints(), &upper_bottom, 5, 18, QUEUE_KEY)?;
+        let filtered_map: HashMap<String, Series<f64>> = map_data_queue.into_iter().filter(|(k, _)| !k.contains("bytes") && !k.contains("pct")).collect();
+        gen_events_graph("Queue".to_string(), filtered_map, self.group_events.datapoints(), self.group_events.times(), &upper_bottom, 5, 18, QUEUE_KEY, &queue_opts)?;
 
         // set up percent full
-        let map_data_full = self.filled_pct.plot();
-        gen_pct_graph("Queue % Full".to_string(), map_data_full, self.filled_pct.datapoints(), upper_q)?;
-    
-        root.present().context("could not write file")?;
+        let map_data_full = self.opts.prepare(self.filled_pct.plot(), self.filled_pct.times());
+        gen_pct_graph("Queue % Full".to_string(), map_data_full, self.filled_pct.datapoints(), self.filled_pct.times(), upper_q, &self.opts.panel("filled", false))?;
 
         Ok(())
     }
 }
 
-fn gen_pct_graph<DB: DrawingBackend<ErrorType: 'static>>(name: String, map: HashMap<String, Vec<f64>>, datapoints: usize, area : DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+fn gen_pct_graph<DB: DrawingBackend<ErrorType: 'static>>(name: String, map: HashMap<String, Series<f64>>, datapoints: usize, times: &[Option<f64>], area : DrawingArea<DB, Shift>, opts: &ChartOpts) -> anyhow::Result<()> {
     let (min, max) = get_min_max_float(&map)?;
+    let max = opts.clamp_max(&map, max);
+    let (min, max) = extend_range(&opts.hlines, min, max);
 
     let headroom = (max - min) * HEADROOM_CHART_MAX;
 
-    let mut chart_events = setup_graph(name, &area, 5, 18);
-    let mut chart_context_events = chart_events.build_cartesian_2d(0usize..datapoints,min..max+headroom)?;
-    chart_context_events.configure_mesh().y_label_formatter(&|i| pct_formatter(*i)).draw()?;
-
-    for (idx, (name, group)) in map.iter().enumerate() {
-        let color = Palette99::pick(idx).mix(0.9);
-        chart_context_events.draw_series(LineSeries::new(group.iter().enumerate().map(|(p_idx, d)| (p_idx, *d)), color.stroke_width(2)))?
-        .label(name.clone());
+    let mut chart_events = setup_graph(name, &area, 5, 18, &opts.theme);
+    let x_axis = TimeAxis::new(times, datapoints);
+    let x_desc = x_axis.desc();
+    let mut chart_context_events = chart_events.build_cartesian_2d(x_axis, min..max+headroom)?;
+    opts.theme.mesh(&mut chart_context_events.configure_mesh()).x_desc(x_desc).y_label_formatter(&|i| pct_formatter(*i)).draw()?;
+    draw_markers(&mut chart_context_events, &opts.markers, &opts.theme)?;
+
+    for (name, color) in opts.colors(map.keys()) {
+        let color = color.mix(0.9);
+        draw_line(&mut chart_context_events, &map[name], color.stroke_width(2), opts)?
+        .label(name);
+    }
+    draw_hlines(&mut chart_context_events, &opts.hlines, datapoints, opts.theme.foreground)?;
+    if opts.hlines.iter().any(|line| line.label.is_some()) || opts.markers.iter().any(|marker| marker.start != marker.end) {
+        opts.theme.legend(&mut chart_context_events.configure_series_labels()).draw()?;
     }
 
     Ok(())
@@ -1,41 +1,103 @@
-use anyhow::Context;
-use plotters::prelude::*;
-use tracing::debug;
+use std::collections::HashMap;
+
+use plotters::{coord::Shift, prelude::*};
 
 use crate::groups::*;
-use super::{generic::{Generic, NoOpProcess}, Watcher};
+use super::{generic::{Generic, NoOpProcess, Series}, Watcher};
 
 const PROCDB_KEY: &str = "processor.add_session_metadata.processdb";
 
+/// The panels the processdb metrics are split into, as (panel name, chart title, name fragments).
+/// A metric goes in the first panel with a fragment in its name, or the first panel if none match.
+const FACETS: &[(&str, &str, &[&str])] = &[
+    ("processes", "Processes", &[]),
+    ("failures", "Lookup Failures", &["fail", "error"]),
+    ("reaping", "Reaping", &["reap"]),
+];
+
+/// Find the index of the panel a metric belongs in
+fn facet(key: &str) -> usize {
+    let name = key.trim_start_matches(PROCDB_KEY);
+    FACETS.iter().position(|(_, _, frags)| frags.iter().any(|frag| name.contains(frag))).unwrap_or(0)
+}
+
 pub struct ProcessDB {
     group: Generic<u64, NoOpProcess<u64>>,
-    fname: String
+    fname: String,
+    opts: ChartOpts
 }
 
 
 impl Watcher for ProcessDB {
-    fn new(_ : Option<Vec<String>>) -> Self {
-        let group = Generic::from(vec![PROCDB_KEY]);
-        ProcessDB { group, fname: "processdb".to_string() }
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let group = Generic::from(vec![PROCDB_KEY]).with_retain(opts.retain);
+        ProcessDB { group, fname: "processdb".to_string(), opts }
     }
 
     fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
         self.group.update(new);
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        GroupData { name: self.fname.clone(), datapoints: self.group.datapoints(), series: self.group.export(), times: self.group.times().to_vec() }
     }
 
-    fn plot(&self) -> anyhow::Result<()> {
-        let map_data = self.group.plot();
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.group.datapoints());
+        self.group.skip();
+        self.opts.slide(self.group.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.group.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.group.datapoints().saturating_sub(1), label);
+    }
 
-        let name = format!("./{}_plot.svg", &self.fname);
-        debug!("writing {}...", name);
-    
-        let root = SVGBackend::new(&name, SVG_SIZE).into_drawing_area();
-        root.fill(&WHITE)?;
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let map_data = self.opts.prepare(self.group.plot(), self.group.times());
 
-        gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), &root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, PROCDB_KEY)?;
-    
-        root.present().context("could not write file")?;
+        let mut facets: Vec<HashMap<String, Series<f64>>> = vec![HashMap::new(); FACETS.len()];
+        for (key, series) in map_data {
+            facets[facet(&key)].insert(key, series);
+        }
+        let facets: Vec<_> = FACETS.iter().zip(facets).filter(|(_, map)| !map.is_empty()).collect();
+        if facets.len() <= 1 {
+            let map_data = facets.into_iter().flat_map(|(_, map)| map).collect();
+            return gen_events_graph(self.fname.clone(), map_data, self.group.datapoints(), self.group.times(), root, DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, PROCDB_KEY, &self.opts);
+        }
+
+        // the first panel with any data gets any reference lines that don't name a panel
+        let areas = root.split_evenly((facets.len(), 1));
+        for (idx, ((panel, title, _), map)) in facets.into_iter().enumerate() {
+            gen_events_graph(title.to_string(), map, self.group.datapoints(), self.group.times(), &areas[idx], DEFAULT_GRAPH_MARGIN, LABEL_SIZE_LEFT, PROCDB_KEY, &self.opts.panel(panel, idx == 0))?;
+        }
 
         Ok(())
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{facet, PROCDB_KEY};
+
+    #[test]
+    fn test_facet() {
+        let panel = |name: &str| facet(&format!("{}.{}", PROCDB_KEY, name));
+        assert_eq!(panel("processes"), 0);
+        assert_eq!(panel("entry_leader_lookup_fail"), 1);
+        assert_eq!(panel("failed_process_lookup_count"), 1);
+        assert_eq!(panel("reaped_processes"), 2);
+    }
 }
\ No newline at end of file
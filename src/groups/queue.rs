@@ -0,0 +1,72 @@
+use plotters::{coord::Shift, prelude::*};
+
+use crate::groups::*;
+use super::{generic::{Generic, NoOpProcess}, memory::MemoryProcessor, Watcher};
+
+const QUEUE_KEY: &str = "libbeat.pipeline.queue";
+const COUNTS: [&str; 6] = ["acked", "max_events", "added.events", "consumed.events", "removed.events", "filled.events"];
+const BYTES: [&str; 2] = ["added.bytes", "filled.bytes"];
+
+/// The pipeline queue in detail, with the byte counts that [`super::pipeline::Pipeline`] leaves out
+pub struct Queue {
+    counts: Generic<u64, NoOpProcess<u64>>,
+    bytes: Generic<f64, MemoryProcessor>,
+    fname: String,
+    opts: ChartOpts
+}
+
+impl Watcher for Queue {
+    fn new(_ : Option<Vec<String>>, opts: ChartOpts) -> Self {
+        let keys = |names: &[&str]| names.iter().map(|name| format!("{}.{}", QUEUE_KEY, name)).collect::<Vec<_>>();
+        Queue { counts: Generic::from(keys(&COUNTS)).with_retain(opts.retain), bytes: Generic::from(keys(&BYTES)).with_retain(opts.retain), fname: "queue".to_string(), opts }
+    }
+
+    fn update(&mut self, new: &serde_json::Map<String, serde_json::Value>) {
+        self.counts.update(new);
+        self.bytes.update(new);
+        self.opts.queue_line(new, &self.fname, "events");
+        self.opts.slide(self.counts.dropped());
+    }
+
+    fn fname(&self) -> &str {
+        &self.fname
+    }
+
+    fn theme(&self) -> &Theme {
+        &self.opts.theme
+    }
+
+    fn export(&self) -> GroupData {
+        let mut series = self.counts.export();
+        series.extend(self.bytes.export());
+        GroupData { name: self.fname.clone(), datapoints: self.counts.datapoints(), series, times: self.counts.times().to_vec() }
+    }
+
+    fn skip(&mut self) {
+        self.opts.mark_outage(self.counts.datapoints());
+        self.counts.skip();
+        self.bytes.skip();
+        self.opts.slide(self.counts.dropped());
+    }
+
+    fn mark(&mut self, label: &str) {
+        self.opts.mark(self.counts.datapoints().saturating_sub(1), label);
+    }
+
+    fn annotate(&mut self, label: &str) {
+        self.opts.annotate(self.counts.datapoints().saturating_sub(1), label);
+    }
+
+    fn draw<DB: DrawingBackend<ErrorType: 'static>>(&self, root: &DrawingArea<DB, Shift>) -> anyhow::Result<()> {
+        let height = root.dim_in_pixel().1;
+        let (upper, lower) = root.split_vertically(height / 2);
+
+        let map_counts = self.opts.prepare(self.counts.plot(), self.counts.times());
+        gen_events_graph("Queue Events".to_string(), map_counts, self.counts.datapoints(), self.counts.times(), &upper, 5, 18, QUEUE_KEY, &self.opts.panel("events", true))?;
+
+        let map_bytes = self.opts.prepare(self.bytes.plot(), self.bytes.times());
+        gen_bytes_graph("Queue Bytes".to_string(), map_bytes, self.bytes.datapoints(), self.bytes.times(), &lower, "libbeat.pipeline.queue.", &self.opts.panel("bytes", false))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,180 @@
+/*!
+ * Chart themes, setting the colors of everything that isn't a series: the background, the text and axes, and the grid.
+ * A theme can also bring its own series palette, which is used unless another `--palette` is asked for.
+ * Besides the built-in light and dark themes, a theme can be loaded from a TOML file.
+ */
+
+use std::{fs, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use plotters::{chart::{MeshStyle, SeriesLabelPosition, SeriesLabelStyle}, coord::{ranged1d::Ranged, CoordTranslate}, prelude::*, style::SizeDesc};
+use serde::Deserialize;
+
+/// Paul Tol's "light" qualitative palette, which stays readable on a dark background
+const TOL_LIGHT: [RGBColor; 9] = [
+    RGBColor(119, 170, 221),
+    RGBColor(238, 136, 102),
+    RGBColor(238, 221, 136),
+    RGBColor(255, 170, 187),
+    RGBColor(153, 221, 255),
+    RGBColor(68, 187, 153),
+    RGBColor(187, 204, 51),
+    RGBColor(170, 170, 0),
+    RGBColor(221, 221, 221),
+];
+
+/// The relative size of axis labels, the same as plotters' default
+const LABEL_FONT_PCT_SIZE: i32 = 12;
+/// The smallest axis labels are drawn, in pixels
+const LABEL_FONT_MIN_SIZE: i32 = 12;
+/// The font size of the series labels box
+const LEGEND_FONT_SIZE: i32 = 12;
+
+/// The colors a chart is drawn in, apart from its series
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    /// The fill behind the whole chart
+    pub background: RGBColor,
+    /// Text, axes, reference lines, and the border of the series labels
+    pub foreground: RGBColor,
+    /// The grid lines
+    pub mesh: RGBColor,
+    /// Series colors, used instead of the default palette. Empty to keep the palette
+    pub palette: Vec<RGBColor>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::light()
+    }
+}
+
+impl Theme {
+    /// Black on white, as plotters draws by default
+    pub fn light() -> Theme {
+        Theme { background: WHITE, foreground: BLACK, mesh: RGBColor(204, 204, 204), palette: Vec::new() }
+    }
+
+    /// Light text on a dark grey, with a brighter palette
+    pub fn dark() -> Theme {
+        Theme { background: RGBColor(30, 30, 30), foreground: RGBColor(220, 220, 220), mesh: RGBColor(72, 72, 72), palette: TOL_LIGHT.to_vec() }
+    }
+
+    /// Load a theme from a TOML file. Colors are `#rrggbb` strings, and anything the file leaves out is taken from
+    /// the theme named by `base`, or from the light theme.
+    pub fn load(path: &str) -> anyhow::Result<Theme> {
+        let raw = fs::read_to_string(path).with_context(|| format!("error reading theme file {}", path))?;
+        let file: ThemeFile = toml::from_str(&raw).with_context(|| format!("error parsing theme file {}", path))?;
+        let base = match file.base.as_deref() {
+            None | Some("light") => Theme::light(),
+            Some("dark") => Theme::dark(),
+            Some(other) => return Err(anyhow!("unknown base theme '{}' in {}, expected light or dark", other, path)),
+        };
+        let color = |value: Option<String>, default: RGBColor| value.map_or(Ok(default), |value| parse_color(&value));
+
+        Ok(Theme {
+            background: color(file.background, base.background)?,
+            foreground: color(file.foreground, base.foreground)?,
+            mesh: color(file.mesh, base.mesh)?,
+            palette: match file.palette {
+                Some(palette) => palette.iter().map(|value| parse_color(value)).collect::<anyhow::Result<_>>()?,
+                None => base.palette,
+            },
+        })
+    }
+
+    /// A font of the given size in the theme's text color
+    pub fn font<'f, S: SizeDesc + 'f>(&self, size: S) -> impl IntoTextStyle<'f> {
+        ("sans-serif", size).with_color(self.foreground)
+    }
+
+    /// Color a chart's grid, axes and axis labels
+    pub fn mesh<'m, 'a, 'b, X, Y, DB>(&self, mesh: &'m mut MeshStyle<'a, 'b, X, Y, DB>) -> &'m mut MeshStyle<'a, 'b, X, Y, DB>
+    where
+        X: Ranged,
+        Y: Ranged,
+        DB: DrawingBackend,
+    {
+        let labels = self.font((LABEL_FONT_PCT_SIZE).percent().max(LABEL_FONT_MIN_SIZE));
+        mesh.bold_line_style(self.mesh)
+        .light_line_style(self.mesh.mix(0.5))
+        .axis_style(self.foreground)
+        .label_style(labels)
+    }
+
+    /// Color the box of series labels, and put it in the upper left corner
+    pub fn legend<'m, 'a, 'b, DB, CT>(&self, labels: &'m mut SeriesLabelStyle<'a, 'b, DB, CT>) -> &'m mut SeriesLabelStyle<'a, 'b, DB, CT>
+    where
+        DB: DrawingBackend + 'a,
+        CT: CoordTranslate,
+    {
+        labels.border_style(self.foreground)
+        .background_style(self.background.mix(0.8))
+        .label_font(self.font(LEGEND_FONT_SIZE))
+        .position(SeriesLabelPosition::UpperLeft)
+    }
+}
+
+impl FromStr for Theme {
+    type Err = anyhow::Error;
+
+    /// `light`, `dark`, or the path to a TOML theme file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "light" => Ok(Theme::light()),
+            "dark" => Ok(Theme::dark()),
+            path => Theme::load(path),
+        }
+    }
+}
+
+/// A theme file, where every color is optional
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    base: Option<String>,
+    background: Option<String>,
+    foreground: Option<String>,
+    mesh: Option<String>,
+    palette: Option<Vec<String>>,
+}
+
+/// Parse a `#rrggbb` color
+fn parse_color(value: &str) -> anyhow::Result<RGBColor> {
+    let hex = value.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii())
+    .ok_or_else(|| anyhow!("invalid color '{}', expected #rrggbb", value))?;
+    let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).with_context(|| format!("invalid color '{}'", value));
+
+    Ok(RGBColor(channel(0)?, channel(2)?, channel(4)?))
+}
+
+#[cfg(test)]
+mod test {
+    use plotters::style::RGBColor;
+
+    use super::{parse_color, Theme, ThemeFile};
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#1e1E1e").unwrap(), RGBColor(30, 30, 30));
+        assert!(parse_color("1e1e1e").is_err());
+        assert!(parse_color("#1e1e").is_err());
+        assert!(parse_color("#1e1e1g").is_err());
+    }
+
+    #[test]
+    fn test_load() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("beatperf-theme-{}.toml", std::process::id()));
+        std::fs::write(&path, "base = \"dark\"\nbackground = \"#000000\"\npalette = [\"#ff0000\", \"#00ff00\"]\n")?;
+        let theme = Theme::load(path.to_str().unwrap());
+        std::fs::remove_file(&path)?;
+
+        let theme = theme?;
+        assert_eq!(theme.background, RGBColor(0, 0, 0));
+        assert_eq!(theme.foreground, Theme::dark().foreground);
+        assert_eq!(theme.palette, vec![RGBColor(255, 0, 0), RGBColor(0, 255, 0)]);
+
+        assert!(toml::from_str::<ThemeFile>("backgroud = \"#000000\"").is_err());
+        Ok(())
+    }
+}
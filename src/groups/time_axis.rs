@@ -0,0 +1,156 @@
+/*!
+ * The x-axis every chart shares. Datapoints are placed by the time they were collected, so a gap in a run or a change in
+ * its interval shows up as space on the chart. Runs without timestamps, like recordings from older versions, are placed
+ * by datapoint number. Either way the axis is indexed by datapoint, so series are drawn with the same coordinates they're stored with.
+ */
+
+use std::ops::Range;
+
+use chrono::DateTime;
+use plotters::coord::ranged1d::{KeyPointHint, NoDefaultFormatting, Ranged, ValueFormatter};
+
+/// A day, in milliseconds
+const DAY_MS: f64 = 86_400_000.0;
+
+/// A chart's x-axis over its datapoints, with room for one more after the last
+#[derive(Clone, Debug)]
+pub struct TimeAxis {
+    /// Where each datapoint sits on the axis, in milliseconds since the epoch, or its number if the run has no timestamps
+    positions: Vec<f64>,
+    /// How the time of a datapoint is labelled, or `None` to label it with its number
+    format: Option<&'static str>,
+}
+
+impl TimeAxis {
+    /// An axis for `datapoints` datapoints, collected at `times`. Datapoints without a time, like the ones that were missed,
+    /// are placed between their neighbours, and at least two datapoints need a time for the axis to be placed by them.
+    pub fn new(times: &[Option<f64>], datapoints: usize) -> TimeAxis {
+        let known: Vec<(usize, f64)> = times.iter().take(datapoints).enumerate().filter_map(|(idx, time)| Some((idx, (*time)?))).collect();
+        let (Some(first), Some(last)) = (known.first(), known.last()) else {
+            return TimeAxis::numbered(datapoints);
+        };
+        if last.1 <= first.1 {
+            return TimeAxis::numbered(datapoints);
+        }
+
+        // the average interval places datapoints before the first time and after the last one
+        let step = (last.1 - first.1) / (last.0 - first.0) as f64;
+        let mut positions = Vec::with_capacity(datapoints + 1);
+        let mut next = 0;
+        for idx in 0..=datapoints {
+            while next < known.len() && known[next].0 < idx {
+                next += 1;
+            }
+            let position = match (next.checked_sub(1).map(|prev| known[prev]), known.get(next)) {
+                (_, Some((at, time))) if *at == idx => *time,
+                (Some((before, from)), Some((after, to))) => from + (to - from) * (idx - before) as f64 / (after - before) as f64,
+                (Some((before, from)), None) => from + step * (idx - before) as f64,
+                (None, Some((after, to))) => to - step * (after - idx) as f64,
+                (None, None) => unreachable!("there are at least two known times"),
+            };
+            // times that go backwards, like a capture appended to after a clock change, are drawn where the last one was
+            let floor = positions.last().copied().unwrap_or(f64::NEG_INFINITY);
+            positions.push(position.max(floor));
+        }
+        // runs that last days need the date, shorter ones need the seconds
+        let format = if last.1 - first.1 >= DAY_MS { "%m-%d %H:%M" } else { "%H:%M:%S" };
+
+        TimeAxis { positions, format: Some(format) }
+    }
+
+    /// An axis placing each datapoint by its number
+    fn numbered(datapoints: usize) -> TimeAxis {
+        TimeAxis { positions: (0..=datapoints).map(|idx| idx as f64).collect(), format: None }
+    }
+
+    /// What the axis is labelled with
+    pub fn desc(&self) -> &'static str {
+        if self.format.is_some() { "Time (UTC)" } else { "Datapoints" }
+    }
+
+    /// The label of a datapoint: the UTC time it was collected at, or its number
+    pub fn label(&self, idx: &usize) -> String {
+        let Some(format) = self.format else {
+            return idx.to_string();
+        };
+        DateTime::from_timestamp_millis(self.position(*idx) as i64).map(|time| time.format(format).to_string()).unwrap_or_default()
+    }
+
+    /// Where a datapoint sits on the axis. Datapoints past the end carry on at the last interval, so nothing drawn there panics.
+    fn position(&self, idx: usize) -> f64 {
+        match self.positions.get(idx) {
+            Some(position) => *position,
+            None => {
+                let last = self.positions.len() - 1;
+                let step = if last > 0 { self.positions[last] - self.positions[last - 1] } else { 1.0 };
+                self.positions[last] + step * (idx - last) as f64
+            }
+        }
+    }
+}
+
+impl Ranged for TimeAxis {
+    type FormatOption = NoDefaultFormatting;
+    type ValueType = usize;
+
+    fn map(&self, value: &usize, limit: (i32, i32)) -> i32 {
+        let (start, end) = (self.positions[0], self.positions[self.positions.len() - 1]);
+        if end <= start {
+            return limit.0;
+        }
+        let share = (self.position(*value) - start) / (end - start);
+        limit.0 + (share * f64::from(limit.1 - limit.0)).round() as i32
+    }
+
+    /// The datapoints closest to evenly spaced places along the axis
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<usize> {
+        let datapoints = self.positions.len() - 1;
+        let count = hint.max_num_points().min(datapoints);
+        if count == 0 {
+            return Vec::new();
+        }
+        let (start, end) = (self.positions[0], self.positions[datapoints - 1]);
+        let mut points: Vec<usize> = (0..count).map(|point| {
+            let place = if count > 1 { start + (end - start) * point as f64 / (count - 1) as f64 } else { start };
+            self.positions[..datapoints].partition_point(|position| *position < place).min(datapoints - 1)
+        }).collect();
+        points.dedup();
+        points
+    }
+
+    fn range(&self) -> Range<usize> {
+        0..self.positions.len() - 1
+    }
+}
+
+impl ValueFormatter<usize> for TimeAxis {
+    fn format_ext(&self, value: &usize) -> String {
+        self.label(value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use plotters::coord::ranged1d::Ranged;
+
+    use super::TimeAxis;
+
+    #[test]
+    fn test_time_axis() {
+        // the third sample was missed, and the fourth came in late
+        let times = [Some(1_700_000_000_000.0), Some(1_700_000_001_000.0), None, Some(1_700_000_009_000.0)];
+        let axis = TimeAxis::new(&times, 4);
+        assert_eq!(axis.desc(), "Time (UTC)");
+        assert_eq!((axis.label(&0).as_str(), axis.label(&2).as_str(), axis.label(&3).as_str()), ("22:13:20", "22:13:25", "22:13:29"));
+        // the axis runs from the first sample to one average interval past the last
+        let limit = (0, 1200);
+        assert_eq!((axis.map(&0, limit), axis.map(&1, limit), axis.map(&2, limit), axis.map(&3, limit), axis.map(&4, limit)), (0, 100, 500, 900, 1200));
+
+        let times = [Some(1_700_000_000_000.0), Some(1_700_200_000_000.0)];
+        assert_eq!(TimeAxis::new(&times, 2).label(&1), "11-17 05:46");
+
+        let axis = TimeAxis::new(&[None, None], 2);
+        assert_eq!((axis.desc(), axis.label(&1).as_str()), ("Datapoints", "1"));
+        assert_eq!((axis.map(&1, (0, 100)), axis.range()), (50, 0..2));
+    }
+}
@@ -0,0 +1,356 @@
+/*!
+ * Transformations applied to a group's series before they're plotted.
+ * Most importantly, this classifies each series as a cumulative counter or a gauge,
+ * so counters can be charted as rates instead of ever-growing lines.
+ */
+
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context};
+use clap::ValueEnum;
+use num_traits::{NumCast, ToPrimitive};
+
+use super::generic::Series;
+
+/// How many samples the counter heuristic looks at
+const CLASSIFY_SAMPLES: usize = 10;
+
+/// Whether a series is a monotonic counter or a gauge that can go up and down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+/// Well-known beat metric names, checked in order before falling back to the heuristic.
+/// A name matches if it's one of the key's segments, or the key ends with it.
+const KNOWN_KINDS: &[(&str, Kind)] = &[
+    // beatperf's own measurements, like request timings
+    ("beatperf", Kind::Gauge),
+    // gauges that live under otherwise counter-y subtrees go first
+    ("filled", Kind::Gauge),
+    ("active", Kind::Gauge),
+    ("max_events", Kind::Gauge),
+    ("memory_total", Kind::Counter),
+    ("rss", Kind::Gauge),
+    ("memory_alloc", Kind::Gauge),
+    ("memory_sys", Kind::Gauge),
+    ("gc_next", Kind::Gauge),
+    ("goroutines", Kind::Gauge),
+    ("open_files", Kind::Gauge),
+    ("running", Kind::Gauge),
+    ("load", Kind::Gauge),
+    ("pct", Kind::Gauge),
+    ("limit", Kind::Gauge),
+    ("clients", Kind::Gauge),
+    // memory sizes, like system.memory.used.bytes
+    ("used", Kind::Gauge),
+    ("free", Kind::Gauge),
+    // cgroup memory usage and CPU quotas
+    ("usage", Kind::Gauge),
+    ("cfs", Kind::Gauge),
+    ("time.ms", Kind::Counter),
+    ("ticks", Kind::Counter),
+    ("total", Kind::Counter),
+    ("acked", Kind::Counter),
+    ("failed", Kind::Counter),
+    ("dropped", Kind::Counter),
+    ("published", Kind::Counter),
+    ("retry", Kind::Counter),
+    ("filtered", Kind::Counter),
+    ("batches", Kind::Counter),
+    ("duplicates", Kind::Counter),
+    ("toomany", Kind::Counter),
+    ("read.bytes", Kind::Counter),
+    ("write.bytes", Kind::Counter),
+    ("errors", Kind::Counter),
+];
+
+/// How counters are charted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum CounterMode {
+    /// Classify each series, and chart counters as per-second rates and gauges as raw values
+    #[default]
+    Auto,
+    /// Chart every series as its raw value
+    Raw,
+    /// Chart every series as a per-second rate, for counters the classification gets wrong
+    Rate,
+}
+
+/// The kind of a well-known beat metric, by its key alone
+pub fn known_kind(key: &str) -> Option<Kind> {
+    let segments: Vec<&str> = key.split('.').collect();
+    KNOWN_KINDS.iter().find(|(name, _)| segments.contains(name) || key.ends_with(&format!(".{}", name))).map(|(_, kind)| *kind)
+}
+
+/// Classify a series, first by its key, then by looking at its first few samples.
+/// A series that never decreases and increases at least once is treated as a counter.
+pub fn classify<T: PartialOrd>(key: &str, series: &Series<T>) -> Kind {
+    if let Some(kind) = known_kind(key) {
+        return kind;
+    }
+
+    let head = &series[..series.len().min(CLASSIFY_SAMPLES)];
+    let monotonic = head.windows(2).all(|w| w[1].1 >= w[0].1);
+    let increased = head.windows(2).any(|w| w[1].1 > w[0].1);
+    if monotonic && increased {
+        Kind::Counter
+    } else {
+        Kind::Gauge
+    }
+}
+
+/// Turn a cumulative counter into a per-second rate. The time between two datapoints comes from `times` (milliseconds since the epoch,
+/// by datapoint) if both have one, otherwise it's assumed to be `interval`. If `interval` is zero, the rate is per-sample.
+/// The first sample has no previous value and is dropped. A decrease is treated as a counter reset,
+/// in which case the new value is the delta since the reset. Rates are rarely whole numbers, so they're always floats.
+pub fn rate<T: Copy + ToPrimitive>(series: &Series<T>, interval: Duration, times: &[Option<f64>]) -> Series<f64> {
+    let secs = interval.as_secs_f64();
+    let time = |idx: usize| times.get(idx).copied().flatten();
+    series.windows(2).filter_map(|w| {
+        let (prev_idx, prev) = (w[0].0, w[0].1.to_f64()?);
+        let (idx, cur) = (w[1].0, w[1].1.to_f64()?);
+        let delta = if cur >= prev { cur - prev } else { cur };
+        let elapsed = match (time(prev_idx), time(idx)) {
+            (Some(start), Some(end)) if end > start => (end - start) / 1000.0,
+            _ => (idx - prev_idx) as f64 * if secs > 0.0 { secs } else { 1.0 },
+        };
+        Some((idx, delta / elapsed))
+    }).collect()
+}
+
+/// A series as floats, as it's charted
+fn to_float<T: ToPrimitive>(series: Series<T>) -> Series<f64> {
+    series.into_iter().filter_map(|(idx, value)| Some((idx, value.to_f64()?))).collect()
+}
+
+impl CounterMode {
+    /// Apply the counter mode to every series in a group
+    pub fn apply<T>(&self, map: HashMap<String, Series<T>>, interval: Duration, times: &[Option<f64>]) -> HashMap<String, Series<f64>>
+    where T: Copy + PartialOrd + ToPrimitive {
+        match self {
+            CounterMode::Raw => map.into_iter().map(|(key, series)| (key, to_float(series))).collect(),
+            CounterMode::Rate => map.into_iter().map(|(key, series)| (key, rate(&series, interval, times))).collect(),
+            CounterMode::Auto => map.into_iter().map(|(key, series)| {
+                let series = match classify(&key, &series) {
+                    Kind::Counter => rate(&series, interval, times),
+                    Kind::Gauge => to_float(series),
+                };
+                (key, series)
+            }).collect(),
+        }
+    }
+}
+
+/// The scale of a chart's y-axis
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scale {
+    /// Whatever suits the chart: log for event counts, which span orders of magnitude, and linear for everything else
+    #[default]
+    Auto,
+    Linear,
+    Log,
+}
+
+impl FromStr for Scale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(Scale::Auto),
+            "linear" => Ok(Scale::Linear),
+            "log" => Ok(Scale::Log),
+            other => Err(anyhow!("unknown scale '{}', expected auto, linear or log", other)),
+        }
+    }
+}
+
+/// The y-axis scale for every group, or a single group, parsed from `[<group>=]<scale>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScaleSpec {
+    /// The group the scale applies to, or `None` for every group without its own
+    pub group: Option<String>,
+    pub scale: Scale,
+}
+
+impl FromStr for ScaleSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((group, scale)) => Ok(ScaleSpec { group: Some(group.trim().to_string()), scale: scale.parse()? }),
+            None => Ok(ScaleSpec { group: None, scale: s.parse()? }),
+        }
+    }
+}
+
+/// Clamp the y-axis of a group's charts to a percentile of its data, parsed from `<group>=<percentile>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clamp {
+    pub group: String,
+    pub percentile: f64,
+}
+
+impl FromStr for Clamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (group, pct) = s.split_once('=').ok_or_else(|| anyhow!("clamp must be formatted as <group>=<percentile>"))?;
+        let percentile: f64 = pct.trim().parse().with_context(|| format!("invalid percentile '{}'", pct))?;
+        if !(percentile > 0.0 && percentile <= 100.0) {
+            return Err(anyhow!("percentile must be above 0, and at most 100"));
+        }
+        Ok(Clamp { group: group.to_string(), percentile })
+    }
+}
+
+/// The nearest-rank percentile of a set of values
+pub fn percentile(values: &[f64], pct: f64) -> Option<f64> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// The `low` and `high` percentiles of a run of consecutive datapoints, each over the `window` datapoints up to and including it.
+/// The window is shorter at the start of the run, until enough datapoints have been collected.
+pub fn percentile_band<T>(run: &[(usize, T)], window: usize, low: f64, high: f64) -> Vec<(usize, T, T)>
+where T: Copy + ToPrimitive + NumCast {
+    run.iter().enumerate().filter_map(|(pos, (idx, _))| {
+        let values: Vec<f64> = run[(pos + 1).saturating_sub(window)..=pos].iter().filter_map(|(_, v)| v.to_f64()).collect();
+        Some((*idx, T::from(percentile(&values, low)?)?, T::from(percentile(&values, high)?)?))
+    }).collect()
+}
+
+/// Reduce a run of consecutive datapoints to at most `points` of them with largest-triangle-three-buckets,
+/// which keeps the spikes and dips that make a chart worth looking at. The first and last datapoints are always kept.
+pub fn downsample<T: Copy + ToPrimitive>(run: &[(usize, T)], points: usize) -> Series<T> {
+    if run.len() <= points.max(2) {
+        return run.to_vec();
+    }
+    if points < 3 {
+        return vec![run[0], run[run.len() - 1]];
+    }
+
+    let x = |idx: usize| run[idx].0 as f64;
+    let y = |idx: usize| run[idx].1.to_f64().unwrap_or(0.0);
+    // the datapoints between the first and last are split into evenly sized buckets, and one is picked from each
+    let width = (run.len() - 2) as f64 / (points - 2) as f64;
+    let bound = |bucket: usize| ((bucket as f64 * width) as usize + 1).min(run.len() - 1);
+
+    let mut picked = vec![run[0]];
+    let mut prev = 0;
+    for bucket in 0..points - 2 {
+        let (start, end) = (bound(bucket), bound(bucket + 1));
+        // the average of the next bucket, or the last datapoint for the last bucket
+        let next = bound(bucket + 1)..bound(bucket + 2);
+        let (next_x, next_y) = if next.is_empty() {
+            (x(run.len() - 1), y(run.len() - 1))
+        } else {
+            let len = next.len() as f64;
+            (next.clone().map(x).sum::<f64>() / len, next.map(y).sum::<f64>() / len)
+        };
+        let area = |idx: usize| ((x(prev) - next_x) * (y(idx) - y(prev)) - (x(prev) - x(idx)) * (next_y - y(prev))).abs();
+        let best = (start..end.max(start + 1)).max_by(|a, b| area(*a).total_cmp(&area(*b))).unwrap_or(start);
+        picked.push(run[best]);
+        prev = best;
+    }
+    picked.push(run[run.len() - 1]);
+
+    picked
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::{classify, downsample, percentile, percentile_band, rate, Clamp, CounterMode, Kind, Scale, ScaleSpec};
+
+    #[test]
+    fn test_classify() {
+        let rising = vec![(0, 1u64), (1, 5), (2, 5), (3, 9)];
+        let wobbly = vec![(0, 1u64), (1, 5), (2, 3)];
+        assert_eq!(classify("custom.thing", &rising), Kind::Counter);
+        assert_eq!(classify("custom.thing", &wobbly), Kind::Gauge);
+        // rss grows at startup, but the known-key table knows better
+        assert_eq!(classify("beat.memstats.rss", &rising), Kind::Gauge);
+        assert_eq!(classify("libbeat.pipeline.queue.filled.bytes", &rising), Kind::Gauge);
+        assert_eq!(classify("beat.cgroup.memory.mem.usage.bytes", &rising), Kind::Gauge);
+        assert_eq!(classify("beat.cpu.total.time.ms", &wobbly), Kind::Counter);
+        assert_eq!(classify("libbeat.output.write.bytes", &wobbly), Kind::Counter);
+        assert_eq!(classify("system.memory.used.bytes", &rising), Kind::Gauge);
+        assert_eq!(classify("custom.heap.bytes", &wobbly), Kind::Gauge);
+    }
+
+    #[test]
+    fn test_rate_with_reset() {
+        let series = vec![(0, 10u64), (1, 30), (3, 70), (4, 5)];
+        assert_eq!(rate(&series, Duration::from_secs(2), &[]), vec![(1, 10.0), (3, 10.0), (4, 2.5)]);
+    }
+
+    #[test]
+    fn test_rate_with_times() {
+        let series = vec![(0, 0u64), (1, 10), (2, 30)];
+        // the second sample came 1s after the first, the third only 500ms later
+        let times = [Some(1000.0), Some(2000.0), Some(2500.0)];
+        assert_eq!(rate(&series, Duration::from_secs(5), &times), vec![(1, 10.0), (2, 40.0)]);
+    }
+
+    #[test]
+    fn test_rate_mode() {
+        let map = HashMap::from([("beat.memstats.rss".to_string(), vec![(0, 100u64), (1, 300), (2, 200)])]);
+        assert_eq!(CounterMode::Auto.apply(map.clone(), Duration::from_secs(1), &[])["beat.memstats.rss"], vec![(0, 100.0), (1, 300.0), (2, 200.0)]);
+        // a gauge still gets charted as a rate, with a drop treated as a reset
+        assert_eq!(CounterMode::Rate.apply(map, Duration::from_secs(2), &[])["beat.memstats.rss"], vec![(1, 100.0), (2, 100.0)]);
+    }
+
+    #[test]
+    fn test_percentile() -> anyhow::Result<()> {
+        let values: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert_eq!(percentile(&values, 99.0), Some(99.0));
+        assert_eq!(percentile(&values, 100.0), Some(100.0));
+        assert_eq!(percentile(&[5.0], 50.0), Some(5.0));
+        assert_eq!(percentile(&[], 50.0), None);
+
+        assert_eq!("memory=99.5".parse::<Clamp>()?, Clamp { group: "memory".to_string(), percentile: 99.5 });
+        assert!("memory=0".parse::<Clamp>().is_err());
+        assert!("memory=150".parse::<Clamp>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_scale() -> anyhow::Result<()> {
+        assert_eq!("linear".parse::<ScaleSpec>()?, ScaleSpec { group: None, scale: Scale::Linear });
+        assert_eq!("output=log".parse::<ScaleSpec>()?, ScaleSpec { group: Some("output".to_string()), scale: Scale::Log });
+        assert!("output=logarithmic".parse::<ScaleSpec>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_percentile_band() {
+        let run: Vec<(usize, u64)> = vec![(4, 10), (5, 20), (6, 1000), (7, 30)];
+        let band = percentile_band(&run, 3, 50.0, 95.0);
+        assert_eq!(band, vec![(4, 10, 10), (5, 10, 20), (6, 20, 1000), (7, 30, 1000)]);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut run: Vec<(usize, f64)> = (0..1000).map(|idx| (idx, 1.0)).collect();
+        run[500].1 = 50.0;
+
+        let small = downsample(&run, 100);
+        assert_eq!(small.len(), 100);
+        assert_eq!(small.first(), Some(&(0, 1.0)));
+        assert_eq!(small.last(), Some(&(999, 1.0)));
+        // the spike survives
+        assert!(small.contains(&(500, 50.0)));
+        assert!(small.windows(2).all(|w| w[0].0 < w[1].0));
+
+        assert_eq!(downsample(&run[..10], 100).len(), 10);
+        assert_eq!(downsample(&run, 1), vec![(0, 1.0), (999, 1.0)]);
+    }
+}
@@ -0,0 +1,114 @@
+/*!
+ * Discovers beat pods in a Kubernetes cluster by label selector, so a DaemonSet can be watched in one run instead of one port-forward
+ * per pod. Pods are listed through the cluster API, and each pod's stats are fetched through the API server's proxy to the pod,
+ * so nothing but the API has to be reachable. The API is usually reached through `kubectl proxy`.
+ */
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use beatperf::fetch::Client;
+
+/// The port beats serve their monitoring endpoint on, unless `http.port` says otherwise
+const MONITORING_PORT: u16 = 5066;
+
+/// The response of the pod list endpoint
+#[derive(Debug, Deserialize)]
+struct PodList {
+    items: Vec<PodItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PodItem {
+    metadata: Metadata,
+    #[serde(default)]
+    status: Status,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    name: String,
+    namespace: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Status {
+    #[serde(default)]
+    phase: String,
+}
+
+/// A running pod matched by the selector
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pod {
+    /// The pod's namespace and name, like `kube-system/filebeat-x7k2p`
+    pub name: String,
+    /// The base URL of the pod's monitoring endpoint, through the API server's proxy
+    pub endpoint: String,
+}
+
+/// List the running pods that match a label selector, like `app=filebeat`, in a namespace or in every namespace.
+/// `api` is the base URL of the cluster API, like `http://localhost:8001` for `kubectl proxy`.
+pub async fn discover(client: &Client, api: &str, namespace: Option<&str>, selector: &str) -> anyhow::Result<Vec<Pod>> {
+    let api = api.trim_end_matches('/');
+    let url = pods_url(api, namespace, selector);
+    let (body, _) = client.get(&url).await
+    .with_context(|| format!("error listing pods from {}. Is kubectl proxy running, or is --k8s-api set?", api))?;
+    let list: PodList = serde_json::from_str(&body).with_context(|| format!("unexpected response from {}", url))?;
+
+    let mut pods = Vec::new();
+    for item in list.items {
+        let name = format!("{}/{}", item.metadata.namespace, item.metadata.name);
+        if item.status.phase != "Running" {
+            warn!("skipping pod {}, it's {}", name, item.status.phase.to_lowercase());
+            continue;
+        }
+        info!("found pod {}", name);
+        pods.push(Pod { endpoint: proxy_url(api, &item.metadata), name });
+    }
+
+    Ok(pods)
+}
+
+/// The URL that lists the pods matching a selector
+fn pods_url(api: &str, namespace: Option<&str>, selector: &str) -> String {
+    let pods = match namespace {
+        Some(namespace) => format!("{}/api/v1/namespaces/{}/pods", api, namespace),
+        None => format!("{}/api/v1/pods", api),
+    };
+    format!("{}?labelSelector={}", pods, encode(selector))
+}
+
+/// The base URL of a pod's monitoring endpoint, through the API server's proxy
+fn proxy_url(api: &str, pod: &Metadata) -> String {
+    format!("{}/api/v1/namespaces/{}/pods/{}:{}/proxy", api, pod.namespace, pod.name, MONITORING_PORT)
+}
+
+/// Percent-encode a query parameter
+fn encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        b => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pods_url, proxy_url, PodList};
+
+    #[test]
+    fn test_pods() {
+        let list: PodList = serde_json::from_str(r#"{"kind": "PodList", "items": [
+            {"metadata": {"name": "filebeat-x7k2p", "namespace": "kube-system", "labels": {"app": "filebeat"}}, "status": {"phase": "Running"}},
+            {"metadata": {"name": "filebeat-q9z4m", "namespace": "kube-system"}, "status": {"phase": "Pending"}}
+        ]}"#).unwrap();
+        assert_eq!(list.items[1].status.phase, "Pending");
+        assert_eq!(proxy_url("http://localhost:8001", &list.items[0].metadata),
+            "http://localhost:8001/api/v1/namespaces/kube-system/pods/filebeat-x7k2p:5066/proxy");
+
+        assert_eq!(pods_url("http://localhost:8001", None, "app=filebeat,tier!=test"),
+            "http://localhost:8001/api/v1/pods?labelSelector=app%3Dfilebeat%2Ctier%21%3Dtest");
+        assert_eq!(pods_url("http://localhost:8001", Some("kube-system"), "app=filebeat"),
+            "http://localhost:8001/api/v1/namespaces/kube-system/pods?labelSelector=app%3Dfilebeat");
+    }
+}
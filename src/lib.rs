@@ -0,0 +1,55 @@
+/*!
+ * The metric collection and charting engine behind the beatperf CLI.
+ *
+ * Metrics are collected into [`groups`], each of which knows how to chart its own metrics. For embedding beatperf in tests,
+ * [`recorder::Recorder`] runs samples through the same groups and returns summary statistics instead of charts.
+ *
+ * A run is the same as the CLI's: a [`poller::Poller`] fetches samples from a beat and broadcasts them as [`watchers::Sample`]s
+ * to a task per group, each started with [`watchers::run_watch`]. Once the poller stops, every watcher writes its final outputs
+ * and returns its series as a [`watchers::Finished`]. New groups implement [`groups::Watcher`], usually on top of
+ * [`groups::generic::Generic`], which collects any set of metrics and converts them with a [`groups::generic::Processor`].
+ *
+ * ```no_run
+ * use std::time::Duration;
+ *
+ * use beatperf::{fetch::Client, groups::{memory::MemoryMetrics, ChartOpts}, poller::Poller, watchers::{run_watch, WatchOpts}};
+ * use tokio::{sync::broadcast, task::JoinSet};
+ * use tokio_util::sync::CancellationToken;
+ *
+ * # async fn load_test() {
+ * let (tx, _) = broadcast::channel(100);
+ * let (control, _) = broadcast::channel(16);
+ * let mut watchers = JoinSet::new();
+ * let opts = WatchOpts { output_dir: "out".to_string(), ..Default::default() };
+ * run_watch::<MemoryMetrics>(&mut watchers, &tx, &control, None, ChartOpts::default(), opts);
+ *
+ * let token = CancellationToken::new();
+ * let poller = Poller::new("http://localhost:5066/stats".to_string(), Duration::from_secs(1), Client::default());
+ * tokio::spawn(poller.run(tx, token.clone()));
+ * // generate load, then stop polling
+ * token.cancel();
+ * while let Some(Ok(done)) = watchers.join_next().await {
+ *     println!("{}: {} datapoints", done.data.name, done.data.datapoints);
+ * }
+ * # }
+ * ```
+ */
+
+pub mod adaptive;
+pub mod animation;
+pub mod capture;
+pub mod debug_dump;
+pub mod elasticsearch;
+pub mod expvar;
+pub mod export;
+pub mod fetch;
+pub mod groups;
+pub mod poller;
+pub mod recorder;
+pub mod registry;
+pub mod restarts;
+pub mod schedule;
+pub mod stability;
+pub mod thresholds;
+pub mod timeline;
+pub mod watchers;
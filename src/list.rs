@@ -0,0 +1,35 @@
+/*!
+ * `beatperf list`, which prints the key of every metric a beat reports, in the dot-notation `--metrics` takes, so keys can be
+ * looked up instead of guessed.
+ */
+
+use std::io::{self, ErrorKind, Write};
+
+use anyhow::Context;
+use serde_json::{Map, Value};
+
+use beatperf::{expvar, fetch::{self, Client}, groups::generic::flatten_map};
+
+/// Fetch one sample from `stats_url` and print every numeric key in it that starts with `prefix`, along with its value
+pub async fn run(client: &Client, stats_url: &str, prefix: Option<&str>) -> anyhow::Result<()> {
+    let (body, timings) = client.get(stats_url).await
+    .with_context(|| format!("error fetching {}. Is it correct, and is the beat running?", stats_url))?;
+    let mut doc: Map<String, Value> = serde_json::from_str(&body).with_context(|| format!("{} didn't return a JSON object", stats_url))?;
+    if expvar::is_expvar(stats_url) {
+        doc = expvar::to_stats(doc);
+    }
+    fetch::add_meta(&mut doc, stats_url, timings);
+
+    let metrics: Vec<_> = flatten_map(&doc).into_iter().filter(|(key, _)| prefix.is_none_or(|prefix| key.starts_with(prefix))).collect();
+    let width = metrics.iter().map(|(key, _)| key.len()).max().unwrap_or_default();
+    let mut out = io::stdout().lock();
+    for (key, value) in metrics {
+        match writeln!(out, "{:<width$}  {}", key, value) {
+            // stop quietly when piped into something like head
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => break,
+            res => res?,
+        }
+    }
+
+    Ok(())
+}
@@ -1,30 +1,47 @@
-use std::{fs::{read_to_string, File, OpenOptions}, time::Duration};
+use std::{fs::{self, OpenOptions}, path::Path, sync::{atomic::Ordering, Arc, Mutex}, time::Duration};
+#[cfg(feature = "serve")]
+use std::time::Instant;
 
-use anyhow::Context;
-use clap::{ArgGroup, Parser};
-use groups::{custom::CustomMetrics, kernel_tracing::KernelTracing, memory::MemoryMetrics, output::Output, pipeline::Pipeline, processdb::ProcessDB};
-use reqwest::IntoUrl;
+use anyhow::{anyhow, Context};
+use beat_config::BeatConfig;
+use clap::{parser::ValueSource, Arg, ArgAction, ArgGroup, CommandFactory, FromArgMatches, Parser, Subcommand};
+use chrono::DateTime;
+use beatperf::{adaptive::Adaptive, capture::{self, Rotation}, debug_dump, elasticsearch::Indexer, expvar, poller::{Poller, Retry, RunStats}, recorder::{Recorder, Summary}, restarts::Restarts, schedule, stability::Stability, thresholds::{Alerts, Threshold, BREACH_EXIT_CODE}, timeline::Annotations, watchers::{checkpoint_every, forward_control, run_watch, Control, Finished, Sample, WatchOpts}, export::{self, CsvLayout, FileName, FormatSpec, OutputFormat}, fetch::{self, Client, Header, STATE_PATH, TIMESTAMP_PATH}, groups::{annotations::RefLine, anomaly::{Anomaly, Detector}, diff::{write_summary, GroupDiff}, generic::{get_root_elem, KeyFilter}, GroupData, palette::{ChartPalette, SeriesColors}, theme::Theme, transform::{Clamp, CounterMode, Scale, ScaleSpec}, ByteUnits, ChartOpts, custom::CustomMetrics}, registry::Registry};
+use regex::Regex;
 use serde_json::{Map, Value};
 use spinners::{Spinner, Spinners};
-use tokio::{signal, sync::broadcast::{self, Sender}, task::JoinSet, time};
+use tokio::{sync::broadcast::{self, Sender}, task::JoinSet};
 use tokio_util::sync::CancellationToken;
-use tracing::{debug, error, info, level_filters::LevelFilter};
+use tracing::{error, info, level_filters::LevelFilter, warn};
 use tracing_subscriber::EnvFilter;
-use watchers::run_watch;
-use std::io::prelude::*;
+#[cfg(feature = "serve")]
+use control::ControlState;
+use replay::ReplaySpeed;
+use duration::{parse_duration, GroupInterval};
 
-mod groups;
-mod watchers;
+mod agent;
+mod beat_config;
+mod check;
+mod config;
+#[cfg(feature = "serve")]
+mod control;
+mod daemon;
+mod docker;
+mod duration;
+mod k8s;
+mod list;
+mod metrics_file;
+mod pprof;
+mod replay;
+mod signals;
+#[cfg(feature = "tui")]
+mod tui;
 
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
-#[clap(group(
-    ArgGroup::new("fields")
-        .args(&["metrics", "memory", "cpu", "processdb", "pipeline", "output", "ndjson", "kernel_tracing"]) // if you're adding new metric groups, be sure to add them here
-        .multiple(true)
-        .required(true)
-))]
+// options from a config file come first, so later options on the command line replace them
+#[command(args_override_self = true)]
 #[clap(group(
     ArgGroup::new("reader")
     .required(false)
@@ -32,213 +49,1220 @@ mod watchers;
     .conflicts_with("ndjson"),
 ))]
 struct Cli {
-    /// the hostname:port combination of the beat stat endpoint
-    #[arg(default_value_t = default_endpoint() )]
-    endpoint: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// the hostname:port combination of the beat stat endpoint, with an optional http:// or https:// scheme and path, like
+    /// https://host/beats/filebeat/stats. /stats is added to URLs that don't end with it.
+    /// Can be repeated, or a comma-separated list, to watch several beats
+    #[arg(default_values_t = [default_endpoint()], value_delimiter = ',')]
+    endpoint: Vec<String>,
+
+    /// Read options from a YAML or TOML file. Options on the command line take precedence, and lists are combined
+    #[arg(long, value_name = "FILE")]
+    config: Option<String>,
+
+    /// How often to fetch stats, like 250ms, 5s, 1m or 1m30s. A bare number is taken as seconds.
+    #[arg(long, short, default_value = "5s", value_parser = parse_duration)]
+    interval: Duration,
 
-    /// How often to fetch stats, in seconds.
-    #[arg(long, short, default_value_t = 5 )]
-    interval: u64,
+    /// Chart a group's samples only this often, as <group>=<duration>, for groups that don't need every sample fetched. Can be repeated
+    #[arg(long, value_name = "GROUP=DURATION")]
+    group_interval: Vec<GroupInterval>,
 
-    /// A list of custom metrics to monitor, in dot-notation
+    /// A list of custom metrics to monitor, in dot-notation. Give a metric a shorter name in the legend with <alias>=<key>
     #[arg(long, short)]
     metrics: Option<Vec<String>>,
 
-    /// report memory metrics
+    /// A file listing custom metrics to monitor, one per line. The file is watched for newly added metrics
     #[arg(long)]
-    memory: bool,
+    metrics_file: Option<String>,
 
-    /// report CPU metrics
-    #[arg(long)]
-    cpu: bool,
+    /// Monitor every metric whose dot-notation key matches this regex, anywhere in the stats document. Can be repeated
+    #[arg(long, value_name = "REGEX")]
+    metrics_regex: Vec<Regex>,
+
+    /// Leave out custom metrics whose key matches this regex, however they were picked. Can be repeated
+    #[arg(long, value_name = "REGEX")]
+    metrics_exclude_regex: Vec<Regex>,
 
-    /// report add_session_metadata's processDB metrics
+    /// The names of the enabled metric groups, from the flags added for each group in the registry
+    #[arg(skip)]
+    groups: Vec<&'static str>,
+
+    /// Debug logging
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Show a live terminal dashboard of every group, instead of re-rendering the charts as samples come in.
+    /// Charts are still written at the end of the run, and logs go to beatperf.log
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with_all = ["read", "daemon"])]
+    tui: bool,
+
+    /// Keep watching in the background until stopped with a signal, logging to beatperf.log in the output directory instead of the terminal
+    #[arg(long, conflicts_with = "read")]
+    daemon: bool,
+
+    /// Where --daemon writes its pid, relative to the output directory. It's removed when the daemon stops
+    #[arg(long, value_name = "FILE", default_value = "beatperf.pid", requires = "daemon")]
+    pid_file: String,
+
+    /// dump all beat metrics to an ndjson file, gzip-compressed if its name ends in .gz
     #[arg(long)]
-    processdb: bool,
+    ndjson: Option<String>,
+
+    /// Roll the --ndjson dump over to a new file once the current one is this big on disk, like 100MB. A .gz dump is measured compressed.
+    /// Files after the first are numbered, like out.1.ndjson, and --read-rotated reads the whole set in order
+    #[arg(long, value_name = "SIZE", value_parser = capture::parse_size, requires = "ndjson")]
+    ndjson_max_size: Option<u64>,
 
-    /// report libbeat pipeline metrics
+    /// Roll the --ndjson dump over to a new file once the current one has been written to for this long, like 1h
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "ndjson")]
+    ndjson_rotate: Option<Duration>,
+
+    /// Keep only this many files of a rotated --ndjson dump, removing the oldest as it rolls over. Counts the file being written
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), requires = "ndjson_rotation")]
+    ndjson_keep: Option<u64>,
+
+    /// Remove files of a rotated --ndjson dump last written to longer ago than this, like 168h, checked as it rolls over
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "ndjson_rotation")]
+    ndjson_max_age: Option<Duration>,
+
+    ///Read metrics from an file, instead of from a a beat http endpoint. Files ending in .gz are decompressed. Use - to read from stdin.
     #[arg(long)]
-    pipeline: bool,
+    read: Option<String>,
 
-    /// report add_sesson_metadata's kernel_tracing metrics
+    /// Keep reading the --read file as it grows, like tail -f, until Ctrl-C. Lets one beatperf capture with --ndjson while another charts
+    #[arg(long, requires = "read")]
+    read_follow: bool,
+
+    /// Read a capture written with --ndjson-max-size or --ndjson-rotate as a whole set: the file given, then out.1.ndjson, out.2.ndjson
+    /// and so on, in order. Applies to --read and diff
     #[arg(long)]
-    kernel_tracing: bool,
+    read_rotated: bool,
+
+    /// How fast to replay --read: realtime, a multiple like 10x, or max. Samples are paced by their timestamps, or by --interval if they don't have any
+    #[arg(long, value_name = "SPEED", default_value = "max", requires = "read")]
+    replay_speed: ReplaySpeed,
+
+    /// Render an animated SVG of each chart, capturing a frame every N samples
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    animate: Option<u64>,
+
+    /// The color palette used for chart series
+    #[arg(long, value_enum, default_value_t = ChartPalette::Default)]
+    palette: ChartPalette,
+
+    /// The chart theme: light, dark, or the path to a TOML file with background, foreground, mesh and palette colors
+    #[arg(long, value_name = "THEME", default_value = "light")]
+    theme: Theme,
+
+    /// How cumulative counters are charted
+    #[arg(long, value_enum, default_value_t = CounterMode::Auto)]
+    counters: CounterMode,
 
+    /// Chart every series of a group as a per-second rate, whatever --counters is set to. Can be repeated
+    #[arg(long, value_name = "GROUP")]
+    rate: Vec<String>,
 
-    /// Report output event metrics
+    /// Label byte counts in decimal units, like MB, or binary ones, like MiB
+    #[arg(long, value_enum, default_value_t = ByteUnits::Decimal)]
+    byte_units: ByteUnits,
+
+    /// Chart the counters of the cpu, output and pipeline groups as the change since the previous sample, instead of as rates
     #[arg(long)]
-    output: bool,
+    deltas: bool,
 
-    /// Debug logging
-    #[arg(long, short)]
-    verbose: bool,
+    /// Read a beat's YAML config, enable the groups relevant to it, and draw any configured limits on the charts
+    #[arg(long, value_name = "FILE")]
+    beat_config: Option<String>,
+
+    /// Write all collected series to an Excel workbook at the end of the run
+    #[cfg(feature = "xlsx")]
+    #[arg(long, value_name = "FILE")]
+    xlsx: Option<String>,
+
+    /// Write a single HTML page with every group's chart and summary table at the end of the run
+    #[arg(long, value_name = "FILE")]
+    report: Option<String>,
+
+    /// Write every group's series, with timestamps and summary statistics, to a JSON file at the end of the run
+    #[arg(long, value_name = "FILE")]
+    json_out: Option<String>,
+
+    /// Draw a horizontal reference line on a group's chart, as <group>[.<panel>]=<value>[:label]. Can be repeated
+    #[arg(long, value_name = "LINE")]
+    hline: Vec<RefLine>,
 
-    /// dump all beat metrics to an ndjson file
+    /// Draw a labelled vertical line on every chart for each line of this file, formatted as <time> <label>,
+    /// where the time is RFC 3339 or seconds since the epoch
+    #[arg(long, value_name = "FILE")]
+    annotations: Option<Annotations>,
+
+    /// Draw every series of a group in its own small panel, instead of overlapping them on one chart. Can be repeated
+    #[arg(long, value_name = "GROUP")]
+    facet: Vec<String>,
+
+    /// Draw a group's series stacked on top of each other as areas, for series that add up to a total. Can be repeated
+    #[arg(long, value_name = "GROUP")]
+    stack: Vec<String>,
+
+    /// The scale of the charts' y-axis, as [<group>=]<scale> with scales auto, linear and log. auto draws event counts on a log scale and everything else on a linear one. Without a group, sets the scale of every group that isn't given its own. Can be repeated
+    #[arg(long, value_name = "SCALE")]
+    scale: Vec<ScaleSpec>,
+
+    /// Clamp the y-axis of a group's charts to a percentile of its data, as <group>=<percentile>. Clipped points are marked. Can be repeated
+    #[arg(long, value_name = "GROUP=PCT")]
+    clamp: Vec<Clamp>,
+
+    /// Draw at most this many points of each series, downsampling longer runs so their charts stay small and quick to render.
+    /// Exports keep every datapoint
+    #[arg(long, value_name = "N", default_value_t = 2000, value_parser = clap::value_parser!(u64).range(2..))]
+    max_points: u64,
+
+    /// Keep only the most recent N datapoints of every group, dropping the oldest as new ones come in, so a watch that runs for days
+    /// uses a bounded amount of memory. Charts and exports only cover the retained datapoints
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(2..))]
+    retain: Option<u64>,
+
+    /// Draw a band from the median to the 95th percentile behind every line of a group's events charts, over a sliding window of
+    /// --band-window datapoints, so the baseline stays visible under spiky data. Can be repeated
+    #[arg(long, value_name = "GROUP")]
+    band: Vec<String>,
+
+    /// The number of datapoints the percentiles of --band are taken over
+    #[arg(long, value_name = "N", default_value_t = 12, value_parser = clap::value_parser!(u64).range(2..))]
+    band_window: u64,
+
+    /// Mark samples that stand out from the ones before them on every chart, and list them at the end of the run.
+    /// A sample is an outlier when its modified z-score, against the median and MAD of the preceding samples, is above --anomaly-threshold
     #[arg(long)]
-    ndjson: Option<String>,
+    anomalies: bool,
+
+    /// The number of preceding samples each sample is compared to by --anomalies
+    #[arg(long, value_name = "N", default_value_t = 30, value_parser = clap::value_parser!(u64).range(3..), requires = "anomalies")]
+    anomaly_window: u64,
+
+    /// The modified z-score above which --anomalies flags a sample
+    #[arg(long, value_name = "SCORE", default_value_t = 3.5, requires = "anomalies")]
+    anomaly_threshold: f64,
+
+    /// The formats to write charts and data in, as [<group>=]<format>[,<format>...] with formats svg, png, html, csv and csv-long.
+    /// Without a group, sets the formats of every group that isn't given its own. Can be repeated [default: svg]
+    #[arg(long, value_name = "FORMATS")]
+    format: Vec<FormatSpec>,
+
+    /// Also write every group's series as CSV, whatever its formats are. long, the default, writes <group>_series.csv with a row
+    /// for every sample of every metric, and wide writes <group>.csv with a row for every sample, the same as --format csv-long and csv
+    #[arg(long, value_name = "LAYOUT", num_args = 0..=1, require_equals = true, default_missing_value = "long")]
+    csv: Option<CsvLayout>,
+
+    /// Write a group's charts and exports under this name instead of the group's own, as <group>=<name>. Can be repeated
+    #[arg(long, value_name = "GROUP=NAME")]
+    file_name: Vec<FileName>,
+
+    /// Start the name of every chart and export with this, like run1_, so runs in the same directory don't overwrite each other
+    #[arg(long, value_name = "PREFIX", default_value = "", hide_default_value = true)]
+    file_prefix: String,
+
+    /// The directory charts, exports, checkpoints and the --ndjson dump are written to. Created if it doesn't exist
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    output_dir: String,
+
+    /// Write payloads that can't be parsed, or have values of an unexpected type, to this directory
+    #[arg(long, value_name = "DIR")]
+    debug_dump: Option<String>,
+
+    /// Delay each fetch by a random amount up to this long, so samples don't line up with other periodic work on the host
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "read")]
+    jitter: Option<Duration>,
+
+    /// Retry a failed fetch this many times before giving up on the sample. Retries never run past the next fetch
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    retries: u32,
+
+    /// How long to wait before retrying a failed fetch, doubling for each retry after the first
+    #[arg(long, value_name = "DURATION", default_value = "100ms", value_parser = parse_duration)]
+    retry_backoff: Duration,
+
+    /// Give up on a request to the beat that takes longer than this, and record the sample as missed.
+    /// A fetch is always given up on after one interval, or a second if that's longer
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    http_timeout: Option<Duration>,
+
+    /// Poll faster while any of these metrics are changing quickly, and slower while they're flat. Can be repeated
+    #[arg(long, value_name = "KEY", conflicts_with = "read")]
+    adaptive: Vec<String>,
+
+    /// The shortest interval adaptive polling will use [default: a quarter of --interval]
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_duration, requires = "adaptive")]
+    min_interval: Option<Duration>,
+
+    /// The longest interval adaptive polling will use [default: four times --interval]
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_duration, requires = "adaptive")]
+    max_interval: Option<Duration>,
+
+    /// Stop the watch after this long, like 30m, and write the final charts and exports, as Ctrl-C would
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with = "read")]
+    duration: Option<Duration>,
+
+    /// Stop the watch once this many samples have been fetched from each endpoint, so runs that are compared have the same number of datapoints
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), conflicts_with = "read")]
+    samples: Option<u64>,
+
+    /// Stop once all of these metrics have stayed within --stable-tolerance of their mean for --stable-for. Can be repeated
+    #[arg(long, value_name = "KEY", conflicts_with = "read")]
+    until_stable: Vec<String>,
+
+    /// How long the --until-stable metrics have to stay within the band
+    #[arg(long, value_name = "DURATION", default_value = "5m", value_parser = parse_duration, requires = "until_stable")]
+    stable_for: Duration,
+
+    /// How far the --until-stable metrics can spread, as a percentage of their mean
+    #[arg(long, value_name = "PCT", default_value_t = 5.0, requires = "until_stable")]
+    stable_tolerance: f64,
+
+    /// Warn when a metric crosses a limit, as "<metric> <op> <value>" with op one of >, >=, < or <=, like "beat.memstats.rss > 500MB".
+    /// Breaches are shaded on the charts, and make beatperf exit with status 3 at the end of the run. Can be repeated
+    #[arg(long, value_name = "EXPR")]
+    threshold: Vec<Threshold>,
+
+    /// Fetch the beat's /state again this often, besides when the watch starts, to pick up changes to its queue or output
+    #[arg(long, value_name = "PERIOD", value_parser = parse_duration, conflicts_with = "read")]
+    state_interval: Option<Duration>,
+
+    /// Every period, write a snapshot of every group's output to a new checkpoint_<time> directory
+    #[arg(long, value_name = "PERIOD", value_parser = parse_duration, conflicts_with = "read")]
+    checkpoint: Option<Duration>,
 
-    ///Read metrics from an file, instead of from a a beat http endpoint.
+    /// Download a heap and a CPU profile from the beat's /debug/pprof/ endpoints every period, starting when the watch does,
+    /// into a pprof directory under the output directory. Needs http.pprof.enabled in the beat's config
+    #[arg(long, value_name = "PERIOD", value_parser = parse_duration, conflicts_with = "read")]
+    pprof_every: Option<Duration>,
+
+    /// Serve a control API (/status, /render, /add-metric, /mark, /stop) on this localhost port
+    #[cfg(feature = "serve")]
+    #[arg(long, value_name = "PORT", conflicts_with = "read")]
+    control_port: Option<u16>,
+
+    /// Draw a vertical line with this label on the charts of the beatperf instance serving its control API on --control-port, and exit
+    #[cfg(feature = "serve")]
+    #[arg(long, value_name = "LABEL", requires = "control_port")]
+    mark: Option<String>,
+
+    /// Watch every beat run by the Elastic Agent whose monitoring server (agent.monitoring.http) is at this hostname:port, like localhost:6791
+    #[arg(long, value_name = "ENDPOINT", conflicts_with_all = ["endpoint", "unix_socket", "read"])]
+    agent: Option<String>,
+
+    /// Watch every running Docker container whose image is a beat, through the host port it publishes the monitoring endpoint (5066) on.
+    /// Each container's files are named after it
+    #[arg(long, conflicts_with_all = ["endpoint", "unix_socket", "read", "agent"])]
+    docker: bool,
+
+    /// With --docker, watch the containers with this label instead, as <key> or <key>=<value>
+    #[arg(long, value_name = "LABEL", requires = "docker")]
+    docker_label: Option<String>,
+
+    /// Watch every running Kubernetes pod matching --selector, through the API server's proxy to the pod's monitoring endpoint (5066).
+    /// Each pod's files are named after it
+    #[arg(long, requires = "selector", conflicts_with_all = ["endpoint", "unix_socket", "read", "agent", "docker"])]
+    k8s: bool,
+
+    /// The label selector --k8s finds pods with, like app=filebeat
+    #[arg(long, value_name = "SELECTOR", requires = "k8s")]
+    selector: Option<String>,
+
+    /// Only look for --k8s pods in this namespace, instead of in every namespace
+    #[arg(long, value_name = "NAMESPACE", requires = "k8s")]
+    namespace: Option<String>,
+
+    /// The cluster API --k8s lists pods from and fetches their stats through. The default is where kubectl proxy listens
+    #[arg(long, value_name = "URL", default_value = "http://localhost:8001", requires = "k8s")]
+    k8s_api: String,
+
+    /// Fetch Go's expvars from /debug/vars instead of /stats, for beats whose stats endpoint is restricted.
+    /// They're reshaped to look like stats, so every group works, but only the metrics the beat publishes as expvars are there to chart
+    #[arg(long, conflicts_with_all = ["agent", "read"])]
+    expvar: bool,
+
+    /// Fetch stats over the unix socket a beat's monitoring endpoint listens on (http.host: unix:///...), instead of over TCP
+    #[arg(long, value_name = "PATH", conflicts_with = "endpoint")]
+    unix_socket: Option<String>,
+
+    /// Authenticate to the stats endpoint with HTTP basic auth as this user
+    #[arg(long, requires = "password_source")]
+    username: Option<String>,
+
+    /// The password for --username. Prefer the environment variable or --password-file, which stay out of ps and shell history
+    #[arg(long, env = "BEATPERF_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Read the password for --username from the first line of this file
+    #[arg(long, value_name = "FILE", conflicts_with = "password", requires = "username")]
+    password_file: Option<String>,
+
+    /// Authenticate to the stats endpoint with an Elastic API key, sent as `Authorization: ApiKey <key>`. --username takes precedence
+    #[arg(long, value_name = "KEY", env = "BEATPERF_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// Send this header with every request, as "<name>: <value>", like "Authorization: Bearer <token>". Can be repeated
+    #[arg(long, value_name = "HEADER")]
+    header: Vec<Header>,
+
+    /// Trust this PEM-encoded CA certificate, along with the system's, when fetching from https:// endpoints
+    #[cfg(feature = "tls")]
+    #[arg(long, value_name = "FILE")]
+    ca_cert: Option<String>,
+
+    /// Don't verify the certificates of https:// endpoints
+    #[cfg(feature = "tls")]
     #[arg(long)]
-    read: Option<String>
+    insecure: bool,
+
+    /// Bulk-index every sample into the Elasticsearch cluster at this URL, like https://localhost:9200, so the run can be charted in Kibana
+    #[arg(long, value_name = "URL")]
+    es_url: Option<String>,
+
+    /// The index or data stream --es-url writes samples to
+    #[arg(long, value_name = "INDEX", default_value = "beatperf", requires = "es_url")]
+    es_index: String,
+
+    /// Authenticate to --es-url with an Elastic API key, sent as `Authorization: ApiKey <key>`
+    #[arg(long, value_name = "KEY", requires = "es_url")]
+    es_api_key: Option<String>,
 
 }
 
+#[derive(Subcommand)]
+enum Command {
+    /// Compare two --ndjson captures, like before and after a beat upgrade
+    ///
+    /// Every enabled group's metrics are charted with both runs overlaid, and the change in each metric's mean is written to
+    /// diff_summary.csv. Group options go before the subcommand: beatperf --memory --pipeline diff before.ndjson after.ndjson
+    Diff {
+        /// The capture to compare against
+        before: String,
+        /// The capture to compare
+        after: String,
+    },
+    /// Check that a beat's monitoring endpoint can be watched
+    ///
+    /// Fetches the beat's /, /stats, /state and /inputs/ documents, reports the beat's type, version and uptime, and lists
+    /// which metric groups would find metrics to chart. Connection options like --unix-socket and --header go before the subcommand
+    Check {
+        /// The endpoint to check, in any form a run accepts [default: localhost:5066]
+        endpoint: Option<String>,
+    },
+    /// List the key of every metric a beat reports, for --metrics
+    ///
+    /// Fetches one sample and prints every numeric value in it, with its dot-notation key. Connection options like
+    /// --unix-socket and --expvar go before the subcommand
+    List {
+        /// The endpoint to list metrics from, in any form a run accepts [default: localhost:5066]
+        endpoint: Option<String>,
+        /// Only list keys that start with this, like libbeat.pipeline
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+}
+
+/// The help heading the metric group flags are listed under
+const GROUPS_HEADING: &str = "Metric groups";
+
+/// Where logs are written while the terminal dashboard is in use, or when running as a daemon
+const LOG_FILE: &str = "beatperf.log";
+
 fn default_endpoint() -> String {
     "localhost:5066".to_string()
 }
 
 /// start up tasks for every configured watcher
-fn generate_readers(args: &Cli, tx: &mut Sender<Map<String, Value>>, realtime: bool) -> JoinSet<()> {
-    let mut set = JoinSet::new();
-    if args.memory {
-        run_watch::<MemoryMetrics>(&mut set, tx, None, realtime);
+fn generate_readers(args: &Cli, set: &mut JoinSet<Finished>, tx: &mut Sender<Sample>, control: &Sender<Control>, realtime: bool, subdir: Option<String>) {
+    for spec in Registry::builtin().iter().filter(|spec| args.groups.contains(&spec.name)) {
+        spec.start(set, tx, control, chart_opts(args, spec.name), watch_opts(args, spec.name, realtime, &subdir));
     }
-    if args.processdb {
-        run_watch::<ProcessDB>(&mut set, tx, None, realtime);
+
+    if  args.metrics.is_some() {
+        run_watch::<CustomMetrics>(set, tx, control, args.metrics.clone(), chart_opts(args, "custom"), watch_opts(args, "custom", realtime, &subdir));
     }
+}
 
-    if args.pipeline {
-        run_watch::<Pipeline>(&mut set, tx, None, realtime);
+/// Build the chart options for a single group
+fn chart_opts(args: &Cli, group: &str) -> ChartOpts {
+    ChartOpts {
+        palette: args.palette,
+        theme: args.theme.clone(),
+        series_colors: SeriesColors::default(),
+        counters: if args.rate.iter().any(|name| name == group) { CounterMode::Rate } else { args.counters },
+        deltas: args.deltas,
+        byte_units: args.byte_units,
+        interval: group_every(args, group).unwrap_or(args.interval),
+        hlines: args.hline.iter().filter(|line| line.group == group).cloned().collect(),
+        markers: Vec::new(),
+        facet: args.facet.iter().any(|name| name == group),
+        stack: args.stack.iter().any(|name| name == group),
+        scale: args.scale.iter().find(|spec| spec.group.as_deref() == Some(group))
+        .or_else(|| args.scale.iter().rfind(|spec| spec.group.is_none()))
+        .map_or(Scale::Auto, |spec| spec.scale),
+        clamp: args.clamp.iter().find(|clamp| clamp.group == group).map(|clamp| clamp.percentile),
+        filter: if group == "custom" {
+            KeyFilter { include: args.metrics_regex.clone(), exclude: args.metrics_exclude_regex.clone() }
+        } else {
+            KeyFilter::default()
+        },
+        max_points: Some(args.max_points as usize),
+        retain: args.retain.map(|n| n as usize),
+        band: args.band.iter().any(|name| name == group).then_some(args.band_window as usize),
+        anomalies: args.anomalies.then_some(Detector { window: args.anomaly_window as usize, threshold: args.anomaly_threshold }),
     }
+}
 
-    if args.output {
-        run_watch::<Output>(&mut set, tx, None, realtime);
+/// Build the options for a single group's watcher task
+fn watch_opts(args: &Cli, group: &str, realtime: bool, subdir: &Option<String>) -> WatchOpts {
+    let mut formats = args.format.iter().find(|spec| spec.group.as_deref() == Some(group))
+    .or_else(|| args.format.iter().rfind(|spec| spec.group.is_none()))
+    .map_or_else(|| vec![OutputFormat::Svg], |spec| spec.formats.clone());
+    if let Some(layout) = args.csv.filter(|layout| !formats.contains(&OutputFormat::Csv(*layout))) {
+        formats.push(OutputFormat::Csv(layout));
     }
 
-    if args.kernel_tracing {
-        run_watch::<KernelTracing>(&mut set, tx, None, realtime);
+    WatchOpts { realtime, animate_every: args.animate.map(|n| n as usize), formats, subdir: subdir.clone(), keep_chart: args.report.is_some(), output_dir: args.output_dir.clone(),
+        thresholds: args.threshold.clone(), annotations: args.annotations.clone().unwrap_or_default(), file_name: args.file_name.iter().rfind(|file| file.group == group).map(|file| file.name.clone()),
+        file_prefix: args.file_prefix.clone(), every: group_every(args, group), poll_interval: args.interval }
+}
+
+/// How often a group charts a sample, if it's been asked to chart fewer than are fetched
+fn group_every(args: &Cli, group: &str) -> Option<Duration> {
+    args.group_interval.iter().rfind(|every| every.group == group).map(|every| every.interval).filter(|every| *every > args.interval)
+}
+
+/// The command line parser, with a flag for every registered metric group
+fn command(registry: &Registry) -> clap::Command {
+    let mut fields = ArgGroup::new("fields").args(["metrics", "metrics_file", "metrics_regex", "beat_config", "ndjson"]).multiple(true).required(true);
+    // --mark only talks to a running instance, so it doesn't need anything to chart
+    #[cfg(feature = "serve")]
+    {
+        fields = fields.arg("mark");
     }
+    let mut command = Cli::command();
+    for spec in registry.iter() {
+        command = command.arg(Arg::new(spec.name).long(spec.flag).help(spec.help).action(ArgAction::SetTrue).help_heading(GROUPS_HEADING));
+        fields = fields.arg(spec.name);
+    }
+    // check and list don't chart anything, and diff asks for its groups itself
+    command.group(fields)
+    .group(ArgGroup::new("password_source").args(["password", "password_file"]))
+    .group(ArgGroup::new("ndjson_rotation").args(["ndjson_max_size", "ndjson_rotate"]).multiple(true))
+    .subcommand_negates_reqs(true)
+}
 
-    if  args.metrics.is_some() {
-        run_watch::<CustomMetrics>(&mut set, tx, args.metrics.clone(), realtime);
+/// Parse arguments with [`command`], noting which of the registry's groups they enable
+fn parse_from(registry: &Registry, args: Vec<String>) -> Cli {
+    let matches = command(registry).get_matches_from(args);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    cli.groups = registry.iter().filter(|spec| matches.get_flag(spec.name)).map(|spec| spec.name).collect();
+    cli
+}
+
+/// Parse the command line, along with the config file if there is one
+fn parse_args() -> anyhow::Result<Cli> {
+    let registry = Registry::builtin();
+    let cli_args: Vec<String> = std::env::args().collect();
+    // the command line alone may not be valid yet, as the config file can fill in required options
+    let partial = command(&registry).ignore_errors(true).get_matches_from(&cli_args);
+    let Some(path) = partial.get_one::<String>("config") else {
+        return Ok(parse_from(&registry, cli_args));
+    };
+
+    let command = command(&registry);
+    let known: Vec<&str> = command.get_arguments().filter_map(|arg| arg.get_long()).filter(|long| *long != "config").collect();
+    let config = config::load(path, &known)?;
+
+    let mut args = vec![cli_args[0].clone()];
+    if partial.value_source("endpoint") != Some(ValueSource::CommandLine) {
+        args.extend(config.endpoints);
+    }
+    args.extend(config.flags);
+    args.extend(cli_args.into_iter().skip(1));
+    Ok(parse_from(&registry, args))
+}
+
+/// A recorder with every enabled group, for the dashboard and for comparing captures
+fn recorder(args: &Cli) -> Recorder {
+    let registry = Registry::builtin();
+    enabled_groups(args).into_iter().fold(Recorder::new(), |recorder, group| {
+        let recorder = recorder.with_opts(chart_opts(args, group));
+        match registry.get(group) {
+            Some(spec) => spec.record(recorder),
+            None if group == "custom" => recorder.with_metrics(args.metrics.clone().unwrap_or_default()),
+            None => recorder,
+        }
+    })
+}
+
+/// true if the terminal dashboard is in use
+#[cfg(feature = "tui")]
+fn tui_enabled(args: &Cli) -> bool {
+    args.tui
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui_enabled(_: &Cli) -> bool {
+    false
+}
+
+/// The password for --username, from --password or its environment variable, or the first line of --password-file
+fn password(args: &Cli) -> anyhow::Result<String> {
+    match (&args.password, &args.password_file) {
+        (Some(password), _) => Ok(password.clone()),
+        (None, Some(path)) => {
+            let raw = std::fs::read_to_string(path).with_context(|| format!("error reading {}", path))?;
+            Ok(raw.lines().next().unwrap_or_default().to_string())
+        }
+        (None, None) => Err(anyhow!("--username needs a password, from --password, BEATPERF_PASSWORD or --password-file")),
+    }
+}
+
+/// The client used to fetch stats from every endpoint
+fn client(args: &Cli) -> anyhow::Result<Client> {
+    #[cfg(feature = "tls")]
+    let mut client = Client::with_tls(args.ca_cert.as_deref(), args.insecure)?;
+    #[cfg(not(feature = "tls"))]
+    let mut client = Client::default();
+
+    // the credentials may come from the environment, so an API key left there doesn't stop --username from being used
+    if let Some(username) = &args.username {
+        client = client.with_header(Header::basic_auth(username, &password(args)?)?);
+    } else if let Some(key) = &args.api_key {
+        client = client.with_header(Header::authorization(&format!("ApiKey {}", key))?);
+    }
+    for header in &args.header {
+        client = client.with_header(header.clone());
+    }
+    if let Some(path) = &args.unix_socket {
+        // accept the path the way it's written in the beat's config, too
+        client = client.with_unix_socket(path.strip_prefix("unix://").unwrap_or(path));
+    }
+    if let Some(timeout) = args.http_timeout {
+        client = client.with_timeout(timeout);
+    }
+
+    Ok(client)
+}
+
+/// An indexer for --es-url, if it's set
+fn indexer(args: &Cli) -> anyhow::Result<Option<Indexer>> {
+    let Some(url) = &args.es_url else {
+        return Ok(None);
+    };
+    #[cfg(feature = "tls")]
+    let mut client = Client::with_tls(args.ca_cert.as_deref(), args.insecure)?;
+    #[cfg(not(feature = "tls"))]
+    let mut client = Client::default();
+
+    if let Some(key) = &args.es_api_key {
+        client = client.with_header(Header::authorization(&format!("ApiKey {}", key))?);
+    }
+    Ok(Some(Indexer::new(client, url, &args.es_index)))
+}
+
+/// The names of all the enabled metric groups
+fn enabled_groups(args: &Cli) -> Vec<&'static str> {
+    let mut groups: Vec<&'static str> = Registry::builtin().iter().map(|spec| spec.name).filter(|name| args.groups.contains(name)).collect();
+    if args.metrics.is_some() {
+        groups.push("custom");
     }
+    groups
+}
 
-    set
+/// A stats endpoint to watch
+struct Endpoint {
+    /// The full URL of the stats endpoint
+    url: String,
+    /// A name for the endpoint that's safe to use in file names
+    label: String,
 }
 
-/// Sit and read events
-async fn watch(stat_path: String, args: Cli) -> anyhow::Result<()> {
+/// Sit and read events, returning the number of threshold breaches
+async fn watch(args: Cli, endpoints: Vec<Endpoint>) -> anyhow::Result<u64> {
     let token = CancellationToken::new();
     let cloned_token = token.clone();
+    if let Some(duration) = args.duration {
+        let token = token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token.cancelled() => {}
+                _ = tokio::time::sleep(duration) => {
+                    info!("stopping after {:?}", duration);
+                    token.cancel();
+                }
+            }
+        });
+    }
     tokio::spawn(async move {
-        signal::ctrl_c().await.expect("failed to listen for event");
+        signals::shutdown().await;
         token.cancel();
     });
 
-    let mut nd_file: Option<File> = match &args.ndjson {
-        Some(fname) => {
-            let file = OpenOptions::new().append(true).create(true).open(fname)?;
-            Some(file)
-        },
-        None => None
-    };
+    // ======= init metrics channels
+    // commands for every endpoint's watchers go through a single channel, which is forwarded to each endpoint
+    let (control_tx, _) = broadcast::channel(16);
+    signals::render_on_hangup(control_tx.clone(), cloned_token.clone());
+    signals::mark_on_user2(control_tx.clone(), cloned_token.clone());
+    // an agent's components, containers and pods are always named, so their files say which one they came from
+    let multiple = endpoints.len() > 1 || args.agent.is_some() || args.docker || args.k8s;
+    let mut readers_handle = JoinSet::new();
+    let mut pollers = JoinSet::new();
+    let mut indexers = JoinSet::new();
+    let mut endpoint_stats = Vec::new();
+    #[cfg(feature = "tui")]
+    let mut sources = Vec::new();
 
+    let client = client(&args)?;
+    for (idx, endpoint) in endpoints.iter().enumerate() {
+        let stat_path = endpoint.url.clone();
+        let label = multiple.then(|| endpoint.label.clone());
+        if let Some(label) = &label {
+            let dir = output_path(&args, label);
+            fs::create_dir_all(&dir).with_context(|| format!("error creating output directory {}", dir))?;
+        }
 
-    // ======= init metrics channels
-    let (mut tx,  _) = broadcast::channel(100);
-    let _readers_handle = generate_readers(&args, &mut tx, true);
+        let (mut tx,  _) = broadcast::channel(100);
+        let (ep_control, _) = broadcast::channel(16);
+        generate_readers(&args, &mut readers_handle, &mut tx, &ep_control, !tui_enabled(&args), label.clone());
+        forward_control(&control_tx, ep_control.clone(), cloned_token.clone());
+        if let Some(indexer) = indexer(&args)? {
+            indexers.spawn(indexer.run(tx.subscribe()));
+        }
 
-    let mut interval = time::interval(Duration::from_secs(args.interval));
-    info!("starting watch of beat stats...");
+        #[cfg(feature = "tui")]
+        if args.tui {
+            sources.push(tui::Source { label: label.clone(), recorder: recorder(&args), samples: tx.subscribe() });
+        }
 
-    loop {
-        let mut sp = Spinner::new(Spinners::Dots9, "Watching...".into());
-        
-        tokio::select! {
-            _ = cloned_token.cancelled() => {
-                sp.stop_with_message("shutting down!".to_string());
-                    
-                return Ok(());
-            }
-            _ = interval.tick() => {
-                let res = get_stat(&stat_path, &mut nd_file).await;
-                if tx.receiver_count() > 0 {
-                    match  res {
-                        Ok(res) => {
-                           match tx.send(res){
-                            Ok(c) => {
-                                debug!("sent to {} monitors", c);
-                            }, 
-                            Err(e) => {
-                                error!("error sending event: {}", e);
-                            }
-                           }
-                        },
-                        Err(e) => {
-                            error!("got error fetching stats: {}", e)
-                        }
-                    }
-                }
+        let ndjson = match &args.ndjson {
+            Some(fname) => {
+                let fname = ndjson_path(&output_path(&args, fname), label.as_deref());
+                Some(capture::create(&fname, Rotation {
+                    max_size: args.ndjson_max_size,
+                    every: args.ndjson_rotate,
+                    keep: args.ndjson_keep.map(|keep| keep as usize),
+                    max_age: args.ndjson_max_age,
+                })?)
+            },
+            None => None
+        };
 
+        let adaptive = (!args.adaptive.is_empty()).then(|| {
+            let min = args.min_interval.unwrap_or(args.interval / 4);
+            let max = args.max_interval.unwrap_or(args.interval * 4).max(min);
+            info!("adapting the polling interval of {} between {:?} and {:?}", endpoint.url, min, max);
+            Adaptive::new(args.adaptive.clone(), args.interval, min, max)
+        });
+
+        let until_stable = (!args.until_stable.is_empty()).then(|| {
+            info!("stopping {} once {} stay within {}% for {:?}", endpoint.url, args.until_stable.join(", "), args.stable_tolerance, args.stable_for);
+            Stability::new(args.until_stable.clone(), args.stable_tolerance, args.stable_for.as_secs_f64() * 1000.0)
+        });
+
+        let per_input = args.groups.contains(&"per_input");
+        let inputs_url = per_input.then(|| fetch::inputs_url(&stat_path)).flatten();
+        if per_input && inputs_url.is_none() {
+            warn!("{} isn't a beat's /stats endpoint, so it has no per-input metrics to fetch", stat_path);
+        }
+
+        if let Some(period) = args.pprof_every {
+            match pprof::base_url(&stat_path) {
+                Some(base) => {
+                    let dir = output_path(&args, &label.as_ref().map_or_else(|| "pprof".to_string(), |label| format!("{}/pprof", label)));
+                    fs::create_dir_all(&dir).with_context(|| format!("error creating profile directory {}", dir))?;
+                    pprof::profile_every(period, client.clone(), base.to_string(), dir, cloned_token.clone());
+                }
+                None => warn!("{} isn't a beat's /stats endpoint, so it has no profiles to download", stat_path),
             }
         }
+
+        let stats = Arc::new(RunStats::default());
+        endpoint_stats.push((stat_path.clone(), stats.clone()));
+        let poller = Poller {
+            state_url: fetch::state_url(&stat_path),
+            state_interval: args.state_interval,
+            inputs_url,
+            url: stat_path,
+            interval: args.interval,
+            offset: schedule::stagger(idx, endpoints.len(), args.interval),
+            jitter: args.jitter,
+            retry: Retry { attempts: args.retries, backoff: args.retry_backoff },
+            adaptive,
+            until_stable,
+            max_samples: args.samples,
+            alerts: Alerts::new(args.threshold.clone()),
+            restarts: Restarts::default(),
+            ndjson,
+            stats,
+            client: client.clone(),
+        };
+        // the poller owns the sender, and closing the channel when it stops tells the watchers to render their final plots
+        pollers.spawn(poller.run(tx, cloned_token.clone()));
+    }
+
+    #[cfg(feature = "tui")]
+    let dashboard = args.tui.then(|| tokio::spawn(tui::run(sources, chart_opts(&args, ""), cloned_token.clone())));
+
+    #[cfg(feature = "serve")]
+    if let Some(port) = args.control_port {
+        let state = ControlState {
+            endpoints: endpoint_stats.clone(),
+            groups: enabled_groups(&args),
+            accepts_metrics: args.metrics.is_some(),
+            started: Instant::now(),
+            control: control_tx.clone(),
+            shutdown: cloned_token.clone(),
+        };
+        control::serve(port, state).await?;
     }
 
+    if let Some(path) = &args.metrics_file {
+        metrics_file::watch_keys(path.clone(), args.metrics.clone().unwrap_or_default(), control_tx.clone(), cloned_token.clone());
+    }
+
+    if let Some(period) = args.checkpoint {
+        checkpoint_every(period, args.output_dir.clone(), control_tx.clone(), cloned_token.clone());
+    }
+
+    let mut sp = (!tui_enabled(&args) && !args.daemon).then(|| Spinner::new(Spinners::Dots9, "Watching...".into()));
+    // pollers only stop on their own if their metrics settle, they've fetched --samples samples, or if they panic
+    tokio::select! {
+        _ = cloned_token.cancelled() => {}
+        _ = join_pollers(&mut pollers) => {}
+    }
+    cloned_token.cancel();
+    join_pollers(&mut pollers).await;
+    join_indexers(&mut indexers).await;
+    if let Some(sp) = &mut sp {
+        sp.stop_with_message("shutting down!".to_string());
+    }
+
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        match dashboard.await {
+            Ok(Err(e)) => error!("dashboard failed: {}", e),
+            Err(e) => error!("dashboard failed: {}", e),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    for (url, stats) in &endpoint_stats {
+        stats.log_summary(url);
+    }
+    let states: Vec<(String, Map<String, Value>)> = endpoint_stats.iter()
+    .filter_map(|(url, stats)| Some((url.clone(), stats.state.lock().unwrap().clone()?)))
+    .collect();
+    finish(readers_handle, &args, &states).await?;
+    Ok(endpoint_stats.iter().map(|(_, stats)| stats.breaches.load(Ordering::Relaxed)).sum())
 }
 
+/// Wait for every poller to stop
+async fn join_pollers(pollers: &mut JoinSet<()>) {
+    while let Some(res) = pollers.join_next().await {
+        if let Err(e) = res {
+            error!("fetch loop failed: {}", e);
+        }
+    }
+}
+
+/// Wait for every indexer to send the last of its samples
+async fn join_indexers(indexers: &mut JoinSet<()>) {
+    while let Some(res) = indexers.join_next().await {
+        if let Err(e) = res {
+            error!("indexing failed: {}", e);
+        }
+    }
+}
+
+/// The base URL of a hostname:port endpoint, or of a URL, which keeps its scheme and path
+fn base_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.contains("://") {
+        endpoint.to_string()
+    } else {
+        format!("http://{}", endpoint)
+    }
+}
+
+/// The stats URL of an endpoint, or its expvar URL with `--expvar`. URLs that already end with the path are used as they are,
+/// so a beat behind a reverse proxy can be given as the full URL of its stats
+fn stats_url(args: &Cli, endpoint: &str) -> String {
+    let path = if args.expvar { expvar::PATH } else { "/stats" };
+    let base = base_url(endpoint);
+    if base.ends_with(path) {
+        base
+    } else {
+        format!("{}{}", base, path)
+    }
+}
+
+/// The endpoint a subcommand was given, or the one the run would use
+fn command_endpoint<'a>(args: &'a Cli, endpoint: &'a Option<String>) -> &'a str {
+    // a unix socket has already replaced the endpoint
+    endpoint.as_ref().filter(|_| args.unix_socket.is_none()).unwrap_or(&args.endpoint[0])
+}
+
+/// A name for an endpoint that's safe to use in file names, like `localhost_5066`
+fn endpoint_label(endpoint: &str) -> String {
+    let endpoint = endpoint.split_once("://").map_or(endpoint, |(_, rest)| rest);
+    endpoint.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' }).collect()
+}
+
+/// Where a file is written, relative to the output directory
+fn output_path(args: &Cli, path: &str) -> String {
+    Path::new(&args.output_dir).join(path).to_string_lossy().into_owned()
+}
+
+/// The ndjson file for a single endpoint, like `out_localhost_5066.ndjson` when watching more than one
+fn ndjson_path(path: &str, label: Option<&str>) -> String {
+    let Some(label) = label else {
+        return path.to_string();
+    };
+    let path = Path::new(path);
+    let name = match capture::split_name(path.file_name().and_then(|name| name.to_str()).unwrap_or("beatperf")) {
+        (stem, Some(ext)) => format!("{}_{}.{}", stem, label, ext),
+        (stem, None) => format!("{}_{}", stem, label),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// Wait for every watcher to finish, and write out any exports. `states` is the last `/state` of each endpoint that served one
+async fn finish(mut readers_handle: JoinSet<Finished>, args: &Cli, states: &[(String, Map<String, Value>)]) -> anyhow::Result<()> {
+    let mut finished = Vec::new();
+    while let Some(res) = readers_handle.join_next().await {
+        info!("watcher done....");
+        match res {
+            Ok(done) => finished.push(done),
+            Err(e) => error!("watcher failed: {}", e),
+        }
+    }
+    finished.sort_by(|a, b| a.data.name.cmp(&b.data.name));
+
+    if args.anomalies {
+        let anomalies: Vec<&Anomaly> = finished.iter().flat_map(|done| &done.anomalies).collect();
+        info!("{} anomalies over the run", anomalies.len());
+        for anomaly in anomalies {
+            let when = anomaly.time.and_then(|ms| DateTime::from_timestamp_millis(ms as i64))
+            .map_or_else(|| format!("datapoint {}", anomaly.outlier.datapoint), |time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string());
+            warn!("{}: {} was {:.2} at {}, {:.1} MADs from the median", anomaly.group, anomaly.key, anomaly.outlier.value, when, anomaly.outlier.score);
+        }
+    }
 
-async fn get_stat<T: IntoUrl>(stat_path: T, fname: &mut Option<File>) -> anyhow::Result<serde_json::Map<String, serde_json::Value>>{
-    let test_get = reqwest::get(stat_path)
-    .await.context("error fetching URL")?.error_for_status()?.text().await?;
+    #[cfg(feature = "xlsx")]
+    if let Some(path) = &args.xlsx {
+        let path = &output_path(args, path);
+        info!("writing workbook to {}", path);
+        let groups: Vec<GroupData> = finished.iter().map(|done| done.data.clone()).collect();
+        beatperf::export::write_xlsx(path, &groups)?;
+    }
 
-    if let Some(file) = fname {
-        writeln!(file, "{}", test_get)?;
+    if let Some(path) = &args.report {
+        let path = &output_path(args, path);
+        info!("writing report to {}", path);
+        let groups: Vec<(&GroupData, Option<&str>)> = finished.iter().map(|done| (&done.data, done.chart.as_deref())).collect();
+        export::write_report(path, &groups, states)?;
     }
 
-    let result: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&test_get)?;
+    if let Some(path) = &args.json_out {
+        let path = &output_path(args, path);
+        info!("writing series to {}", path);
+        let groups: Vec<GroupData> = finished.iter().map(|done| done.data.clone()).collect();
+        export::write_json(path, &groups)?;
+    }
 
-    Ok(result)
+    Ok(())
 }
 
-/// ingest all metrics from a file
-async fn read_file<T: AsRef<str>>(path: T, args: Cli) -> anyhow::Result<()> {
-    let raw = read_to_string(path.as_ref()).context("error reading file to string")?;
+
+/// ingest all metrics from a file, returning the number of threshold breaches
+async fn read_file<T: AsRef<str>>(path: T, args: Cli) -> anyhow::Result<u64> {
+    let mut reader = capture::Reader::open(path.as_ref(), args.read_follow, args.read_rotated)?;
+    let source = if path.as_ref() == capture::STDIN { "stdin" } else { path.as_ref() };
     let (mut tx,  _) = broadcast::channel(100);
-    let mut readers_handle = generate_readers(&args, &mut tx, false);
-    for point in raw.split('\n') {
+    let (control_tx, _) = broadcast::channel(16);
+    let mut readers_handle = JoinSet::new();
+    // a paced or followed replay re-renders the charts as it goes, like watching a beat
+    generate_readers(&args, &mut readers_handle, &mut tx, &control_tx, args.replay_speed.paced() || args.read_follow, None);
+    let mut indexers = JoinSet::new();
+    if let Some(indexer) = indexer(&args)? {
+        indexers.spawn(indexer.run(tx.subscribe()));
+    }
+    let hangup = CancellationToken::new();
+    signals::render_on_hangup(control_tx.clone(), hangup.clone());
+    signals::mark_on_user2(control_tx.clone(), hangup.clone());
+    // one listener for the whole replay, so a signal that comes in between lines isn't missed
+    let stop = CancellationToken::new();
+    let stopped = stop.clone();
+    tokio::spawn(async move {
+        signals::shutdown().await;
+        stop.cancel();
+    });
+    let mut alerts = Alerts::new(args.threshold.clone());
+    let mut restarts = Restarts::default();
+    let mut state = None;
+    let mut breaches = 0;
+    let mut prev_time: Option<Option<f64>> = None;
+    if args.read_follow {
+        info!("following {}, press Ctrl-C to stop", path.as_ref());
+    }
+    loop {
+        let point = tokio::select! {
+            line = reader.next_line() => match line? {
+                Some(line) => line,
+                None => break,
+            },
+            _ = stopped.cancelled() => {
+                info!("stopping the replay");
+                break;
+            }
+        };
         if point.is_empty() {
             continue;
         }
 
-        let result: serde_json::Map<String, serde_json::Value> = serde_json::from_str(point).context("error parsing JSON")?;
-       tx.send(result)?;
+        let result = capture::parse_line(&point)
+        .inspect_err(|_| debug_dump::payload("parse", &point)).context("error parsing JSON")?;
+        let time = get_root_elem(&result, TIMESTAMP_PATH).and_then(Value::as_f64);
+        if let Some(prev) = prev_time.replace(time) {
+            tokio::select! {
+                _ = tokio::time::sleep(args.replay_speed.delay(prev, time, args.interval)) => {}
+                _ = stopped.cancelled() => {
+                    info!("stopping the replay");
+                    break;
+                }
+            }
+        }
+        breaches += alerts.check(source, &result);
+        if restarts.check(&result) {
+            warn!("{} restarted, its counters start over", source);
+        }
+        if let Some(Value::Object(doc)) = get_root_elem(&result, STATE_PATH) {
+            state = Some(doc.clone());
+        }
+       tx.send(Sample::doc(result))?;
     };
     drop(tx);
+    hangup.cancel();
+    join_indexers(&mut indexers).await;
+
+    let states: Vec<(String, Map<String, Value>)> = state.map(|state| (source.to_string(), state)).into_iter().collect();
+    finish(readers_handle, &args, &states).await?;
+    Ok(breaches)
+}
 
-    while readers_handle.join_next().await.is_some() {
-        info!("watcher done....")
+/// Run every enabled group over a capture file, without charting it
+async fn record_file(args: &Cli, path: &str) -> anyhow::Result<Summary> {
+    let raw = capture::read(path, args.read_rotated).await?;
+    let mut recorder = recorder(args);
+    for point in raw.lines().filter(|line| !line.is_empty()) {
+        let doc = capture::parse_line(point)
+        .inspect_err(|_| debug_dump::payload("parse", point)).with_context(|| format!("error parsing JSON in {}", path))?;
+        recorder.record(&doc);
     }
-    
 
-    Ok(())
+    Ok(recorder.summary())
+}
+
+/// Chart two captures against each other, and summarize how each metric changed
+async fn diff(args: &Cli, before: &str, after: &str) -> anyhow::Result<()> {
+    if enabled_groups(args).is_empty() {
+        return Err(anyhow!("nothing to compare, give the groups to compare before the subcommand, like beatperf --memory diff before.ndjson after.ndjson"));
+    }
+    let (before_run, after_run) = (record_file(args, before).await?, record_file(args, after).await?);
+    let mut diffs = Vec::new();
+    // both recorders have every enabled group, in the same order
+    let groups = enabled_groups(args).into_iter().zip(&before_run.groups).zip(&after_run.groups);
+    for ((group, before_group), after_group) in groups {
+        let diff = GroupDiff::new(before_group, after_group, chart_opts(args, group));
+        let path = output_path(args, &format!("{}_diff.svg", watch_opts(args, group, false, &None).file_stem(&diff.name)));
+        info!("writing {}", path);
+        diff.plot(&path)?;
+        diffs.push(diff);
+    }
+
+    println!("{:<60} {:>16} {:>16} {:>10}", "metric", "before", "after", "change");
+    for diff in &diffs {
+        for delta in diff.deltas() {
+            let num = |val: Option<f64>| val.map_or_else(|| "-".to_string(), |v| format!("{:.2}", v));
+            let change = delta.change_pct().map_or_else(|| "-".to_string(), |pct| format!("{:+.1}%", pct));
+            println!("{:<60} {:>16} {:>16} {:>10}", format!("{}/{}", diff.name, delta.key), num(delta.before), num(delta.after), change);
+        }
+    }
+
+    let path = output_path(args, &format!("{}diff_summary.csv", args.file_prefix));
+    info!("writing {}", path);
+    write_summary(&path, &diffs)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Cli::parse();
+    let mut args = parse_args()?;
+    #[cfg(feature = "serve")]
+    if let (Some(label), Some(port)) = (&args.mark, args.control_port) {
+        control::mark(port, label).await?;
+        println!("marked the charts with '{}'", label);
+        return Ok(());
+    }
+    fs::create_dir_all(&args.output_dir).with_context(|| format!("error creating output directory {}", args.output_dir))?;
+    if args.daemon && !daemon::is_child() {
+        let log_file = output_path(&args, LOG_FILE);
+        let pid = daemon::detach(&log_file).await?;
+        println!("beatperf is running in the background as pid {}, logging to {}", pid, log_file);
+        return Ok(());
+    }
 
     let mut level = LevelFilter::INFO;
     if args.verbose {
         level = LevelFilter::DEBUG;
     }
 
-    tracing_subscriber::fmt()
-    .with_env_filter(EnvFilter::builder().with_default_directive(level.into()).from_env_lossy()) 
-    .init();
+    let subscriber = tracing_subscriber::fmt()
+    .with_env_filter(EnvFilter::builder().with_default_directive(level.into()).from_env_lossy());
+    if tui_enabled(&args) || args.daemon {
+        // logging to the terminal would draw over the dashboard, and a daemon has no terminal to log to
+        let log = OpenOptions::new().append(true).create(true).open(output_path(&args, LOG_FILE)).context("error opening log file")?;
+        subscriber.with_ansi(false).with_writer(Mutex::new(log)).init();
+    } else {
+        subscriber.init();
+    }
+
+    let pid_file = args.daemon.then(|| daemon::PidFile::create(&output_path(&args, &args.pid_file))).transpose()?;
+    if args.daemon {
+        info!("running in the background as pid {}", std::process::id());
+    }
 
+    if let Some(dir) = &args.debug_dump {
+        debug_dump::init(dir)?;
+    }
 
-    if let Some(path) = args.read.clone() {
-        read_file(path, args).await?;
+    if let Some(path) = &args.metrics_file {
+        let keys = metrics_file::read_keys(path)?;
+        args.metrics.get_or_insert_with(Vec::new).extend(keys);
+    }
+    if !args.metrics_regex.is_empty() {
+        args.metrics.get_or_insert_with(Vec::new);
+    }
+
+    if let Some(path) = &args.beat_config {
+        let cfg = BeatConfig::load(path)?;
+        let mut enabled = Vec::new();
+        for group in cfg.groups() {
+            if !args.groups.contains(&group) {
+                args.groups.push(group);
+                enabled.push(group);
+            }
+        }
+        info!("beat config: output={:?} queue events={:?} inputs={:?} filebeat modules={:?} modules={:?} protocols={:?} monitors={:?}, enabling {}",
+            cfg.output, cfg.queue_events, cfg.inputs, cfg.filebeat_modules, cfg.modules, cfg.protocols, cfg.monitors, if enabled.is_empty() { "no more groups".to_string() } else { enabled.join(", ") });
+        args.hline.extend(cfg.hlines());
+    }
+
+    let groups = enabled_groups(&args);
+    for line in args.hline.iter().filter(|line| !groups.contains(&line.group.as_str())) {
+        warn!("reference line for {} will not be drawn, the group is not enabled", line.group);
+    }
+    for name in args.facet.iter().filter(|name| !groups.contains(&name.as_str())) {
+        warn!("--facet {} has no effect, the group is not enabled", name);
+    }
+    for name in args.scale.iter().filter_map(|spec| spec.group.as_ref()).filter(|name| !groups.contains(&name.as_str())) {
+        warn!("--scale {} has no effect, the group is not enabled", name);
+    }
+    for name in args.stack.iter().filter(|name| !groups.contains(&name.as_str())) {
+        warn!("--stack {} has no effect, the group is not enabled", name);
+    }
+    for name in args.band.iter().filter(|name| !groups.contains(&name.as_str())) {
+        warn!("--band {} has no effect, the group is not enabled", name);
+    }
+    for name in args.rate.iter().filter(|name| !groups.contains(&name.as_str())) {
+        warn!("--rate {} has no effect, the group is not enabled", name);
+    }
+    for spec in &args.format {
+        if let Some(group) = spec.group.as_ref().filter(|group| !groups.contains(&group.as_str())) {
+            warn!("--format {} has no effect, the group is not enabled", group);
+        }
+    }
+    for clamp in args.clamp.iter().filter(|clamp| !groups.contains(&clamp.group.as_str())) {
+        warn!("--clamp {} has no effect, the group is not enabled", clamp.group);
+    }
+    for file in args.file_name.iter().filter(|file| !groups.contains(&file.group.as_str())) {
+        warn!("--file-name {} has no effect, the group is not enabled", file.group);
+    }
+    for every in &args.group_interval {
+        if !groups.contains(&every.group.as_str()) {
+            warn!("--group-interval {} has no effect, the group is not enabled", every.group);
+        } else if every.interval <= args.interval {
+            warn!("--group-interval {} has no effect, groups can't chart samples more often than they're fetched every {:?}", every.group, args.interval);
+        }
+    }
+
+    if let Some(path) = &args.unix_socket {
+        info!("fetching stats over unix socket {}", path);
+        // the beat doesn't look at the host, it's only used to name the endpoint
+        args.endpoint = vec!["localhost".to_string()];
+    }
+
+    let breaches = if let Some(Command::Diff { before, after }) = &args.command {
+        diff(&args, before, after).await?;
+        0
+    } else if let Some(Command::Check { endpoint }) = &args.command {
+        check::run(&client(&args)?, &stats_url(&args, command_endpoint(&args, endpoint))).await?;
+        0
+    } else if let Some(Command::List { endpoint, prefix }) = &args.command {
+        list::run(&client(&args)?, &stats_url(&args, command_endpoint(&args, endpoint)), prefix.as_deref()).await?;
+        0
+    } else if let Some(path) = args.read.clone() {
+        read_file(path, args).await?
     } else {
-        let stats_endpoint = format!("http://{}/stats", args.endpoint);
-        info!("using endpoint {}", stats_endpoint);
-    
-        // do initial get to make sure the endpoint is okay.
-        let _test_get = reqwest::get(&stats_endpoint)
-        .await.context("error fetching URL. Is is correct, and is the beat running?")?.error_for_status()?.text().await?;
-    
-        
-        watch(stats_endpoint, args).await?;
+        let client = client(&args)?;
+        let endpoints: Vec<Endpoint> = match &args.agent {
+            Some(agent) => {
+                let components = agent::discover(&client, &base_url(agent)).await?;
+                if components.is_empty() {
+                    return Err(anyhow!("the agent at {} isn't running any beats with stats to watch", agent));
+                }
+                components.into_iter().map(|component| Endpoint { label: endpoint_label(&component.id), url: component.url }).collect()
+            }
+            None if args.docker => {
+                let containers = docker::discover(args.docker_label.as_deref()).await?;
+                if containers.is_empty() {
+                    return Err(anyhow!("no running containers with a published monitoring port look like beats"));
+                }
+                containers.into_iter().map(|container| Endpoint { url: stats_url(&args, &container.endpoint), label: endpoint_label(&container.name) }).collect()
+            }
+            None if args.k8s => {
+                let selector = args.selector.as_deref().unwrap_or_default();
+                let pods = k8s::discover(&client, &args.k8s_api, args.namespace.as_deref(), selector).await?;
+                if pods.is_empty() {
+                    return Err(anyhow!("no running pods match {}", selector));
+                }
+                pods.into_iter().map(|pod| Endpoint { url: stats_url(&args, &pod.endpoint), label: endpoint_label(&pod.name) }).collect()
+            }
+            None => args.endpoint.iter().map(|endpoint| Endpoint { url: stats_url(&args, endpoint), label: endpoint_label(endpoint) }).collect(),
+        };
+        for endpoint in &endpoints {
+            info!("using endpoint {}", endpoint.url);
+
+            // do initial get to make sure the endpoint is okay.
+            client.get(&endpoint.url).await
+            .with_context(|| format!("error fetching {}. Is is correct, and is the beat running?", endpoint.url))?;
+        }
+
+        watch(args, endpoints).await?
+    };
+
+    if breaches > 0 {
+        // everything has been written by now, so the exit code is the only thing left to report
+        error!("{} threshold breaches over the run", breaches);
+        // exiting skips destructors, so the pid file has to go first
+        drop(pid_file);
+        std::process::exit(BREACH_EXIT_CODE);
     }
 
     Ok(())
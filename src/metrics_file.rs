@@ -0,0 +1,87 @@
+/*!
+ * Support for reading the custom metrics list from a file, and watching that file for newly added keys mid-run.
+ */
+
+use std::{fs, path::{Path, PathBuf}, time::{Duration, SystemTime}};
+
+use anyhow::Context;
+use tokio::{sync::broadcast::Sender, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use beatperf::watchers::Control;
+
+/// How often to check the metrics file for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Read a list of dot-notation metric keys, one per line. Blank lines and lines starting with `#` are ignored.
+pub fn read_keys(path: &str) -> anyhow::Result<Vec<String>> {
+    let raw = fs::read_to_string(path).with_context(|| format!("error reading metrics file {}", path))?;
+    Ok(parse_keys(&raw))
+}
+
+fn parse_keys(raw: &str) -> Vec<String> {
+    raw.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Poll the metrics file for changes, and send any keys we haven't seen before to the watchers.
+/// Keys removed from the file are not removed from the running watchers.
+pub fn watch_keys(path: String, mut known: Vec<String>, control: Sender<Control>, token: CancellationToken) {
+    tokio::spawn(async move {
+        let path = PathBuf::from(path);
+        let mut last_modified = modified(&path);
+        let mut interval = time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => {
+                    return;
+                }
+                _ = interval.tick() => {
+                    let current = modified(&path);
+                    if current == last_modified {
+                        continue;
+                    }
+                    last_modified = current;
+                    debug!("metrics file {} changed, reloading", path.display());
+
+                    let keys = match fs::read_to_string(&path) {
+                        Ok(raw) => parse_keys(&raw),
+                        Err(e) => {
+                            error!("error reading metrics file {}: {}", path.display(), e);
+                            continue;
+                        }
+                    };
+                    for key in keys {
+                        if known.contains(&key) {
+                            continue;
+                        }
+                        info!("adding metric {} from metrics file", key);
+                        if let Err(e) = control.send(Control::AddMetric(key.clone())) {
+                            error!("error sending new metric to watchers: {}", e);
+                        }
+                        known.push(key);
+                    }
+                }
+            }
+        }
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_keys;
+
+    #[test]
+    fn test_parse_keys() {
+        let raw = "# interesting keys\nbeat.runtime.goroutines\n\n  libbeat.output.events.acked  \n";
+        assert_eq!(parse_keys(raw), vec!["beat.runtime.goroutines".to_string(), "libbeat.output.events.acked".to_string()]);
+    }
+}
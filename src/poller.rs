@@ -0,0 +1,331 @@
+/*!
+ * Polls a single beat endpoint on its own task. Each endpoint has its own schedule, fetch timeout and failure accounting,
+ * so a slow or hung beat can only ever cost its own samples.
+ */
+
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+use tokio::{sync::broadcast::Sender, time::{self, Instant, Interval, MissedTickBehavior}};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{debug_dump, fetch::{self, Client}};
+
+use crate::{adaptive::Adaptive, capture::{self, CaptureWriter}, expvar, schedule, stability::Stability, restarts::Restarts, thresholds::Alerts, watchers::Sample};
+
+/// The shortest time a single fetch is given before it's abandoned, regardless of the interval
+const MIN_FETCH_BUDGET: Duration = Duration::from_secs(1);
+
+/// Counters shared between an endpoint's fetch loop and anything reporting on the run, like the control API
+#[derive(Debug, Default)]
+pub struct RunStats {
+    /// Successful fetches from the beat
+    pub samples: AtomicU64,
+    /// Failed fetches from the beat
+    pub fetch_errors: AtomicU64,
+    /// Fetches abandoned because the beat took too long to respond, also counted as failures
+    pub timeouts: AtomicU64,
+    /// Samples that breached a threshold, counted once per threshold
+    pub breaches: AtomicU64,
+    /// Fetches that failed at first, but succeeded on a retry
+    pub retried: AtomicU64,
+    /// Runs of consecutive failed fetches, like while the beat restarts
+    pub outages: AtomicU64,
+    /// The most fetches that failed in a row
+    pub longest_outage: AtomicU64,
+    /// The beat's last `/state` document, if it serves one
+    pub state: Mutex<Option<Map<String, Value>>>,
+}
+
+impl RunStats {
+    /// Log how the endpoint's fetches went over the run, if any failed
+    pub fn log_summary(&self, url: &str) {
+        let failed = self.fetch_errors.load(Ordering::Relaxed);
+        let retried = self.retried.load(Ordering::Relaxed);
+        if retried > 0 {
+            info!("{} of the fetches from {} succeeded after retrying", retried, url);
+        }
+        if failed > 0 {
+            let outages = self.outages.load(Ordering::Relaxed);
+            warn!("{} of {} fetches from {} failed, over {} outage{}. The longest was {} samples in a row", failed, failed + self.samples.load(Ordering::Relaxed),
+                url, outages, if outages == 1 { "" } else { "s" }, self.longest_outage.load(Ordering::Relaxed));
+        }
+    }
+}
+
+/// How a failed fetch is tried again before the sample is given up on. Retries only happen within the time a single fetch
+/// is given, so they can't hold up the next sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Retry {
+    /// How many times a failed fetch is tried again
+    pub attempts: u32,
+    /// The wait before the first retry, which doubles for each one after it
+    pub backoff: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry { attempts: 0, backoff: Duration::from_millis(100) }
+    }
+}
+
+impl Retry {
+    /// The wait before retry number `attempt`, counting from zero
+    fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << attempt.min(16))
+    }
+}
+
+/// Everything needed to poll a single endpoint
+pub struct Poller {
+    /// The full URL of the stats endpoint
+    pub url: String,
+    /// The beat's `/state` URL. It's fetched when polling starts, and attached to the next sample under [`fetch::STATE_PATH`]
+    pub state_url: Option<String>,
+    /// How often to fetch `/state` again, if at all
+    pub state_interval: Option<Duration>,
+    /// Filebeat's `/inputs/` URL, fetched along with every sample and attached under [`fetch::INPUTS_PATH`], if set
+    pub inputs_url: Option<String>,
+    /// The time between fetches, until adaptive polling changes it
+    pub interval: Duration,
+    /// How long to wait before the first fetch, so endpoints polled at the same interval take turns
+    pub offset: Duration,
+    /// Delay each fetch by a random amount up to this long
+    pub jitter: Option<Duration>,
+    /// How failed fetches are retried
+    pub retry: Retry,
+    /// If set, poll faster or slower depending on how quickly the metrics it watches are changing
+    pub adaptive: Option<Adaptive>,
+    /// If set, stop polling once the metrics it watches have settled
+    pub until_stable: Option<Stability>,
+    /// If set, stop polling once this many samples have been fetched
+    pub max_samples: Option<u64>,
+    /// The thresholds every sample is checked against
+    pub alerts: Alerts,
+    /// Spots the beat restarting between samples
+    pub restarts: Restarts,
+    /// Where to write every sample, if set
+    pub ndjson: Option<CaptureWriter>,
+    /// Counters for the run, shared with anything reporting on it
+    pub stats: Arc<RunStats>,
+    pub client: Client,
+}
+
+impl Poller {
+    /// Poll the stats endpoint at `url` every `interval`, without any of the optional extras
+    pub fn new(url: String, interval: Duration, client: Client) -> Poller {
+        Poller {
+            url,
+            state_url: None,
+            state_interval: None,
+            inputs_url: None,
+            interval,
+            offset: Duration::ZERO,
+            jitter: None,
+            retry: Retry::default(),
+            adaptive: None,
+            until_stable: None,
+            max_samples: None,
+            alerts: Alerts::default(),
+            restarts: Restarts::default(),
+            ndjson: None,
+            stats: Arc::default(),
+            client,
+        }
+    }
+
+    /// Poll the endpoint until the token is cancelled, sending every sample to the watchers, along with a gap for every one that's missed.
+    /// The sender is dropped when this returns, which tells the watchers the run is over.
+    pub async fn run(mut self, tx: Sender<Sample>, token: CancellationToken) {
+        let mut interval = new_interval(self.interval, self.offset);
+        // the number of samples missed since the beat stopped responding
+        let mut missed: Option<u64> = None;
+        let mut next_state = self.state_url.is_some().then(Instant::now);
+        // a state document waits for the next sample that's fetched successfully
+        let mut pending_state = None;
+        info!("starting watch of {}...", self.url);
+
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            if let Some(max) = self.jitter {
+                tokio::select! {
+                    _ = time::sleep(schedule::jitter(max)) => {}
+                    _ = token.cancelled() => break,
+                }
+            }
+
+            // a fetch can't hold up the next one, so it gets at most one interval, shared with the /state fetch before it
+            let budget = interval.period().max(MIN_FETCH_BUDGET);
+            let deadline = Instant::now() + budget;
+            if let (Some(url), Some(at)) = (&self.state_url, next_state) {
+                if at <= Instant::now() {
+                    next_state = self.state_interval.map(|every| Instant::now() + every);
+                    pending_state = get_state(&self.client, url, deadline, &self.stats).await.or(pending_state);
+                }
+            }
+            let res = match time::timeout_at(deadline, get_stat(&self.client, &self.url, self.inputs_url.as_deref(), pending_state.as_ref(), &mut self.ndjson, self.retry, &self.stats)).await {
+                Ok(res) => res,
+                Err(_) => {
+                    self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+                    Err(anyhow!("no response within {:?}", budget))
+                }
+            };
+            match &res {
+                Ok(_) => {
+                    pending_state = None;
+                    self.stats.samples.fetch_add(1, Ordering::Relaxed)
+                },
+                Err(e) => {
+                    if e.downcast_ref::<fetch::TimedOut>().is_some() {
+                        self.stats.timeouts.fetch_add(1, Ordering::Relaxed);
+                    }
+                    self.stats.fetch_errors.fetch_add(1, Ordering::Relaxed)
+                },
+            };
+
+            if let (Some(adaptive), Ok(doc)) = (&mut self.adaptive, &res) {
+                let next = adaptive.next(doc);
+                if next != interval.period() {
+                    debug!("polling {} every {:?}", self.url, next);
+                    interval = new_interval(next, next);
+                }
+            }
+
+            if let Ok(doc) = &res {
+                self.stats.breaches.fetch_add(self.alerts.check(&self.url, doc), Ordering::Relaxed);
+                if self.restarts.check(doc) {
+                    warn!("{} restarted, its counters start over", self.url);
+                }
+            }
+
+            if let (Some(stability), Ok(doc)) = (&mut self.until_stable, &res) {
+                if stability.update(doc) {
+                    info!("{} stable, stopping watch of {}", stability.keys().join(", "), self.url);
+                    break;
+                }
+            }
+
+            match &res {
+                Ok(_) => if let Some(count) = missed.take() {
+                    info!("reconnected to {} after {} missed samples", self.url, count);
+                },
+                Err(e) => {
+                    let count = missed.get_or_insert(0);
+                    if *count == 0 {
+                        warn!("lost connection to {}, will keep retrying: {:#}", self.url, e);
+                        self.stats.outages.fetch_add(1, Ordering::Relaxed);
+                    }
+                    *count += 1;
+                    self.stats.longest_outage.fetch_max(*count, Ordering::Relaxed);
+                }
+            }
+
+            // the last sample is still sent, so every endpoint ends up with exactly max_samples datapoints
+            let fetched = self.stats.samples.load(Ordering::Relaxed);
+            let done = res.is_ok() && self.max_samples.is_some_and(|max| fetched >= max);
+
+            if tx.receiver_count() > 0 {
+                match res {
+                    Ok(doc) => match tx.send(Sample::doc(doc)) {
+                        Ok(c) => debug!("sent to {} monitors", c),
+                        Err(e) => error!("error sending event: {}", e),
+                    },
+                    Err(e) => {
+                        debug!("got error fetching stats: {}", e);
+                        // let the watchers leave a gap, so the series carry on from the same place once the beat is back
+                        let _ = tx.send(Sample::Missed);
+                    }
+                }
+            }
+
+            if done {
+                info!("fetched {} samples, stopping watch of {}", fetched, self.url);
+                break;
+            }
+        }
+    }
+}
+
+/// Fetch the beat's `/state`, keeping it for the rest of the run. Not every beat serves it, so a failure is only logged at debug level.
+async fn get_state(client: &Client, url: &str, deadline: Instant, stats: &RunStats) -> Option<Map<String, Value>> {
+    let res: anyhow::Result<Map<String, Value>> = match time::timeout_at(deadline, client.get(url)).await {
+        Ok(res) => res.and_then(|(body, _)| serde_json::from_str(&body).context("error parsing state")),
+        Err(_) => Err(anyhow!("no response before the fetch's deadline")),
+    };
+    match res {
+        Ok(state) => {
+            *stats.state.lock().unwrap() = Some(state.clone());
+            Some(state)
+        },
+        Err(e) => {
+            debug!("error fetching {}: {:#}", url, e);
+            None
+        }
+    }
+}
+
+/// Create an interval that first fires after `delay`
+fn new_interval(period: Duration, delay: Duration) -> Interval {
+    let mut interval = time::interval_at(time::Instant::now() + delay, period);
+    // at short intervals a slow fetch can overrun the next tick, so push the schedule back instead of firing a burst to catch up
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    interval
+}
+
+/// Fetch a single stats document, tag it with the endpoint, request timings and any new state of the beat, and write it to the ndjson capture if there is one.
+/// If `inputs_url` is set, filebeat's per-input metrics are fetched and attached too. A sample is still kept when they can't be fetched.
+/// A failed request is retried as `retry` says, and a fetch that only succeeds on a retry is counted in `stats`.
+pub async fn get_stat(client: &Client, stat_path: &str, inputs_url: Option<&str>, state: Option<&Map<String, Value>>, fname: &mut Option<CaptureWriter>, retry: Retry, stats: &RunStats) -> anyhow::Result<Map<String, Value>> {
+    let mut attempt = 0;
+    let (test_get, timings) = loop {
+        match client.get(stat_path).await {
+            Ok(res) => break res,
+            Err(e) if attempt < retry.attempts => {
+                debug!("error fetching {}, retrying in {:?}: {:#}", stat_path, retry.delay(attempt), e);
+                time::sleep(retry.delay(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.context("error fetching URL")),
+        }
+    };
+    if attempt > 0 {
+        stats.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut result: Map<String, Value> = serde_json::from_str(&test_get)
+    .inspect_err(|_| debug_dump::payload("parse", &test_get))?;
+    // beatperf's own measurements are gathered apart from the response, so a capture can keep the response as the beat
+    // returned it without a copy being made, see capture::write_envelope
+    let mut tags = Map::new();
+    fetch::add_meta(&mut tags, stat_path, timings);
+    if let Some(state) = state {
+        fetch::add_state(&mut tags, state.clone());
+    }
+    if let Some(url) = inputs_url {
+        match get_inputs(client, url).await {
+            Ok(inputs) => fetch::add_inputs(&mut tags, &inputs),
+            Err(e) => debug!("error fetching {}: {:#}", url, e),
+        }
+    }
+    let meta = tags.remove(fetch::META_KEY).unwrap_or_default();
+    if let Some(file) = fname {
+        capture::write_envelope(file, &result, &meta)?;
+    }
+
+    if expvar::is_expvar(stat_path) {
+        result = expvar::to_stats(result);
+    }
+    result.insert(fetch::META_KEY.to_string(), meta);
+    Ok(result)
+}
+
+/// Fetch filebeat's per-input metrics, a list with an entry for every input that reports them
+async fn get_inputs(client: &Client, url: &str) -> anyhow::Result<Vec<Value>> {
+    let (body, _) = client.get(url).await.context("error fetching URL")?;
+    serde_json::from_str(&body).inspect_err(|_| debug_dump::payload("parse", &body)).context("error parsing inputs")
+}
@@ -0,0 +1,70 @@
+/*!
+ * Downloads heap and CPU profiles from a beat's pprof endpoints (`http.pprof.enabled`) while it's watched, so a spike on a chart
+ * can be matched up with a profile taken around the same time. Profiles are written as they're served, gzipped protobuf that `go tool pprof` reads.
+ */
+
+use std::{fs, time::Duration};
+
+use anyhow::Context;
+use chrono::Utc;
+use tokio::time;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use beatperf::{expvar, fetch::Client};
+
+/// The longest a CPU profile runs for. Shorter periods get shorter profiles, so they don't overlap
+const CPU_PROFILE: Duration = Duration::from_secs(10);
+
+/// The base URL of the beat behind a stats URL, which its pprof endpoints are under
+pub fn base_url(stats_url: &str) -> Option<&str> {
+    stats_url.strip_suffix("/stats").or_else(|| stats_url.strip_suffix(expvar::PATH))
+}
+
+/// Download a heap and a CPU profile from the beat at `base` every period, starting now, until the token is cancelled.
+/// Each is written to `dir` as `heap_<time>.pb.gz` and `cpu_<time>.pb.gz`.
+pub fn profile_every(period: Duration, client: Client, base: String, dir: String, token: CancellationToken) {
+    let cpu_secs = CPU_PROFILE.min(period).as_secs().max(1);
+    tokio::spawn(async move {
+        let mut interval = time::interval(period);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+            let profiles = [
+                (format!("{}/debug/pprof/heap", base), format!("{}/heap_{}.pb.gz", dir, stamp)),
+                (format!("{}/debug/pprof/profile?seconds={}", base, cpu_secs), format!("{}/cpu_{}.pb.gz", dir, stamp)),
+            ];
+            for (url, path) in profiles {
+                let res = tokio::select! {
+                    _ = token.cancelled() => return,
+                    res = save(&client, &url, &path) => res,
+                };
+                match res {
+                    Ok(()) => info!("wrote profile {}", path),
+                    Err(e) => warn!("error downloading {}, is http.pprof.enabled set? {:#}", url, e),
+                }
+            }
+        }
+    });
+}
+
+/// Download a profile and write it to `path`
+async fn save(client: &Client, url: &str, path: &str) -> anyhow::Result<()> {
+    let (body, _) = client.get_bytes(url).await?;
+    fs::write(path, body).with_context(|| format!("error writing {}", path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::base_url;
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(base_url("http://localhost:5066/stats"), Some("http://localhost:5066"));
+        assert_eq!(base_url("http://localhost:5066/debug/vars"), Some("http://localhost:5066"));
+        assert_eq!(base_url("http://localhost:6791/processes/filebeat-default"), None);
+    }
+}
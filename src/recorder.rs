@@ -0,0 +1,173 @@
+/*!
+ * A programmatic way to collect beat metrics, for embedding beatperf in integration and soak tests.
+ * A [`Recorder`] runs samples through the same groups the CLI charts, but instead of writing charts it returns
+ * summary statistics that a test can assert on.
+ *
+ * ```no_run
+ * # async fn soak() -> anyhow::Result<()> {
+ * use std::time::Duration;
+ * use beatperf::{groups::memory::MemoryMetrics, recorder::Recorder};
+ *
+ * let mut recorder = Recorder::new().with_group::<MemoryMetrics>().with_metrics(vec!["beat.runtime.goroutines".to_string()]);
+ * recorder.poll("http://localhost:5066/stats", Duration::from_secs(1), 60).await?;
+ *
+ * let summary = recorder.summary();
+ * let rss = summary.stats("beat.memstats.rss").expect("no RSS samples");
+ * assert!(rss.max < 500_000_000.0);
+ * # Ok(())
+ * # }
+ * ```
+ */
+
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::debug;
+
+use crate::{export::SeriesSummary, fetch, groups::{custom::CustomMetrics, ChartOpts, GroupData, Watcher}};
+
+/// The parts of a [`Watcher`] a recorder needs, in a form that can be boxed
+trait Recording: Send {
+    fn update(&mut self, doc: &Map<String, Value>);
+    fn skip(&mut self);
+    fn export(&self) -> GroupData;
+}
+
+impl<T: Watcher + Send> Recording for T {
+    fn update(&mut self, doc: &Map<String, Value>) {
+        Watcher::update(self, doc)
+    }
+
+    fn skip(&mut self) {
+        Watcher::skip(self)
+    }
+
+    fn export(&self) -> GroupData {
+        Watcher::export(self)
+    }
+}
+
+/// Collects samples into a set of metric groups
+#[derive(Default)]
+pub struct Recorder {
+    groups: Vec<Box<dyn Recording>>,
+    opts: ChartOpts,
+}
+
+impl Recorder {
+    /// Create a recorder with no groups
+    pub fn new() -> Recorder {
+        Recorder::default()
+    }
+
+    /// Use these options for every group added after this. Options that only affect how charts look are ignored.
+    pub fn with_opts(mut self, opts: ChartOpts) -> Recorder {
+        self.opts = opts;
+        self
+    }
+
+    /// Record one of the built-in groups, like [`crate::groups::memory::MemoryMetrics`]
+    pub fn with_group<T: Watcher + Send + 'static>(mut self) -> Recorder {
+        self.groups.push(Box::new(T::new(None, self.opts.clone())));
+        self
+    }
+
+    /// Record a list of metrics, in dot-notation
+    pub fn with_metrics(mut self, keys: Vec<String>) -> Recorder {
+        self.groups.push(Box::new(CustomMetrics::new(Some(keys), self.opts.clone())));
+        self
+    }
+
+    /// Record a single stats document
+    pub fn record(&mut self, doc: &Map<String, Value>) {
+        for group in &mut self.groups {
+            group.update(doc);
+        }
+    }
+
+    /// Record a datapoint that couldn't be collected, leaving a gap in every series
+    pub fn skip(&mut self) {
+        for group in &mut self.groups {
+            group.skip();
+        }
+    }
+
+    /// Fetch a single stats document from a beat endpoint, like `http://localhost:5066/stats`, and record it
+    pub async fn sample(&mut self, url: &str) -> anyhow::Result<()> {
+        let (body, timings) = fetch::get(url).await?;
+        let mut doc: Map<String, Value> = serde_json::from_str(&body)?;
+        fetch::add_meta(&mut doc, url, timings);
+        self.record(&doc);
+        Ok(())
+    }
+
+    /// Take `count` samples from a beat endpoint, one every `interval`.
+    /// Failed fetches are recorded as gaps, and the number of failures is returned.
+    pub async fn poll(&mut self, url: &str, interval: Duration, count: usize) -> anyhow::Result<usize> {
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut failures = 0;
+        for _ in 0..count {
+            ticker.tick().await;
+            if let Err(e) = self.sample(url).await {
+                debug!("error fetching stats from {}: {}", url, e);
+                self.skip();
+                failures += 1;
+            }
+        }
+
+        Ok(failures)
+    }
+
+    /// Snapshot everything recorded so far
+    pub fn summary(&self) -> Summary {
+        Summary { groups: self.groups.iter().map(|group| group.export()).collect() }
+    }
+}
+
+/// The series recorded by every group of a [`Recorder`]
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub groups: Vec<GroupData>,
+}
+
+impl Summary {
+    /// Summary statistics for a single metric, from the first group that recorded it
+    pub fn stats(&self, key: &str) -> Option<SeriesSummary> {
+        self.groups.iter().find_map(|group| group.series.get(key)).and_then(SeriesSummary::from_series)
+    }
+
+    /// The raw series of a single metric, from the first group that recorded it
+    pub fn series(&self, key: &str) -> Option<&[(usize, f64)]> {
+        self.groups.iter().find_map(|group| group.series.get(key)).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::groups::memory::MemoryMetrics;
+
+    use super::Recorder;
+
+    #[test]
+    fn test_recorder() {
+        let doc = |rss: u64, goroutines: u64| json!({
+            "beat": {"memstats": {"rss": rss, "gc_next": 1, "memory_alloc": 1, "memory_sys": 1, "memory_total": 1}, "runtime": {"goroutines": goroutines}},
+        }).as_object().unwrap().clone();
+        let mut recorder = Recorder::new().with_group::<MemoryMetrics>().with_metrics(vec!["beat.runtime.goroutines".to_string()]);
+        recorder.record(&doc(1000, 10));
+        recorder.skip();
+        recorder.record(&doc(3000, 20));
+
+        let summary = recorder.summary();
+        assert_eq!(summary.groups.len(), 2);
+        let goroutines = summary.stats("beat.runtime.goroutines").unwrap();
+        assert_eq!((goroutines.count, goroutines.min, goroutines.max, goroutines.last), (2, 10.0, 20.0, 20.0));
+        assert_eq!(summary.series("beat.runtime.goroutines"), Some(&[(0, 10.0), (2, 20.0)][..]));
+        assert!(summary.stats("beat.memstats.rss").is_some());
+        assert_eq!(summary.stats("nope"), None);
+    }
+}
@@ -0,0 +1,135 @@
+/*!
+ * The metric groups a run can enable, and how to start each of them. The CLI builds a flag for every registered group, and
+ * starts a watcher for every one that's enabled, so a new group only has to be added to [`BUILTIN`].
+ * Library users can register groups of their own on top of the built-in ones:
+ *
+ * ```
+ * use beatperf::{groups::memory::MemoryMetrics, registry::{Registry, WatcherSpec}};
+ *
+ * let registry = Registry::builtin().register(WatcherSpec::new::<MemoryMetrics>("rss_only", "rss-only", "Report only the RSS"));
+ * assert!(registry.get("rss_only").is_some());
+ * ```
+ */
+
+use tokio::{sync::broadcast::Sender, task::JoinSet};
+
+use crate::{
+    groups::{auditbeat::Auditbeat, cpu::CpuMetrics, es_output::EsOutput, failures::Failures, heartbeat::Heartbeat, host::Host, http_timing::HttpTiming, inputs::Inputs, kernel_tracing::KernelTracing,
+        memory::MemoryMetrics, metadata::Metadata, output::Output, packetbeat::Packetbeat, per_input::PerInput, pipeline::Pipeline, processdb::ProcessDB, queue::Queue, ChartOpts, Watcher},
+    recorder::Recorder,
+    watchers::{run_watch, Control, Finished, Sample, WatchOpts},
+};
+
+/// Every metric group beatperf ships with, in the order they're started and reported in
+pub const BUILTIN: &[WatcherSpec] = &[
+    WatcherSpec::new::<CpuMetrics>("cpu", "cpu", "report CPU metrics"),
+    WatcherSpec::new::<MemoryMetrics>("memory", "memory", "report memory metrics"),
+    WatcherSpec::new::<ProcessDB>("processdb", "processdb", "report add_session_metadata's processDB metrics"),
+    WatcherSpec::new::<Pipeline>("pipeline", "pipeline", "report libbeat pipeline metrics"),
+    WatcherSpec::new::<Output>("output", "output", "Report output event metrics"),
+    WatcherSpec::new::<EsOutput>("es_output", "es-output",
+        "Report the Elasticsearch output's acked, failed and retried events, and the bytes and errors on its connection"),
+    WatcherSpec::new::<Failures>("failures", "failures",
+        "Report the events the output failed, dropped or rejected, and the pipeline's retries and failures, as rates"),
+    WatcherSpec::new::<Inputs>("inputs", "inputs", "Report filebeat's harvester and event metrics"),
+    WatcherSpec::new::<PerInput>("per_input", "per-input",
+        "Fetch filebeat's per-input metrics from its /inputs/ endpoint with every sample, and report the events and errors of each input"),
+    WatcherSpec::new::<Queue>("queue", "queue", "Report the pipeline queue in detail, with event counts and bytes in separate panels"),
+    WatcherSpec::new::<Host>("host", "host", "Report host load, and the CPU and memory of the beat's cgroup when it runs in one"),
+    WatcherSpec::new::<Metadata>("metadata", "metadata", "Report the add_kubernetes_metadata and add_docker_metadata processors' cache and watcher metrics"),
+    WatcherSpec::new::<KernelTracing>("kernel_tracing", "kernel-tracing", "report add_session_metadata's kernel_tracing metrics"),
+    WatcherSpec::new::<Auditbeat>("auditbeat", "auditbeat",
+        "Report auditbeat's auditd module: the audit messages received from the kernel, the ones lost on the way, and the kernel's backlog"),
+    WatcherSpec::new::<Packetbeat>("packetbeat", "packetbeat", "Report packetbeat's sniffed and dropped packets, its flows, and the transactions of each protocol"),
+    WatcherSpec::new::<Heartbeat>("heartbeat", "heartbeat", "Report heartbeat's scheduler, and the jobs of each type of monitor and how long they take"),
+    WatcherSpec::new::<HttpTiming>("http_timing", "http-timing", "Report how long each phase of fetching stats from the beat takes"),
+];
+
+/// A metric group that can be enabled for a run
+#[derive(Clone, Copy)]
+pub struct WatcherSpec {
+    /// The group's name, as used in file names and in options like --rate and --hline
+    pub name: &'static str,
+    /// The long command line flag that enables the group, without the leading dashes
+    pub flag: &'static str,
+    /// The flag's help text
+    pub help: &'static str,
+    start: fn(&mut JoinSet<Finished>, &Sender<Sample>, &Sender<Control>, ChartOpts, WatchOpts),
+    record: fn(Recorder) -> Recorder,
+}
+
+impl WatcherSpec {
+    /// Describe a group charted by the watcher `T`
+    pub const fn new<T: Watcher + Send + 'static>(name: &'static str, flag: &'static str, help: &'static str) -> Self {
+        WatcherSpec { name, flag, help, start: start::<T>, record: record::<T> }
+    }
+
+    /// Start a task watching the group, as [`run_watch`] does
+    pub fn start(&self, set: &mut JoinSet<Finished>, broadcaster: &Sender<Sample>, control: &Sender<Control>, opts: ChartOpts, watch_opts: WatchOpts) {
+        (self.start)(set, broadcaster, control, opts, watch_opts)
+    }
+
+    /// Add the group to a recorder, as [`Recorder::with_group`] does
+    pub fn record(&self, recorder: Recorder) -> Recorder {
+        (self.record)(recorder)
+    }
+}
+
+fn start<T: Watcher + Send + 'static>(set: &mut JoinSet<Finished>, broadcaster: &Sender<Sample>, control: &Sender<Control>, opts: ChartOpts, watch_opts: WatchOpts) {
+    run_watch::<T>(set, broadcaster, control, None, opts, watch_opts)
+}
+
+fn record<T: Watcher + Send + 'static>(recorder: Recorder) -> Recorder {
+    recorder.with_group::<T>()
+}
+
+/// A set of metric groups, looked up by name
+#[derive(Clone)]
+pub struct Registry {
+    specs: Vec<WatcherSpec>,
+}
+
+impl Registry {
+    /// A registry with every built-in group
+    pub fn builtin() -> Registry {
+        Registry { specs: BUILTIN.to_vec() }
+    }
+
+    /// Add a group, replacing any registered under the same name
+    pub fn register(mut self, spec: WatcherSpec) -> Registry {
+        match self.specs.iter_mut().find(|existing| existing.name == spec.name) {
+            Some(existing) => *existing = spec,
+            None => self.specs.push(spec),
+        }
+        self
+    }
+
+    /// The group registered under a name
+    pub fn get(&self, name: &str) -> Option<&WatcherSpec> {
+        self.specs.iter().find(|spec| spec.name == name)
+    }
+
+    /// Every registered group, in the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = &WatcherSpec> {
+        self.specs.iter()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::builtin()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BUILTIN;
+
+    #[test]
+    fn test_builtin_unique() {
+        for (idx, spec) in BUILTIN.iter().enumerate() {
+            assert!(BUILTIN[idx + 1..].iter().all(|other| other.name != spec.name && other.flag != spec.flag), "{} is registered twice", spec.name);
+            assert_eq!(spec.flag.replace('-', "_"), spec.name);
+        }
+    }
+}
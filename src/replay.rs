@@ -0,0 +1,82 @@
+/*!
+ * Pacing for replaying a capture with `--read`, so a capture can be watched as it was recorded, or faster.
+ */
+
+use std::{str::FromStr, time::Duration};
+
+use anyhow::{anyhow, Context};
+
+/// How fast the samples of a capture are replayed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReplaySpeed {
+    /// Every sample as soon as the last one is processed
+    #[default]
+    Max,
+    /// At a multiple of the speed they were recorded at, where 1 is realtime
+    Factor(f64),
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "max" => Ok(ReplaySpeed::Max),
+            "realtime" => Ok(ReplaySpeed::Factor(1.0)),
+            other => {
+                let factor: f64 = other.strip_suffix('x').unwrap_or(other).parse()
+                .with_context(|| format!("invalid replay speed '{}', expected realtime, max, or a multiple like 10x", other))?;
+                if !(factor > 0.0 && factor.is_finite()) {
+                    return Err(anyhow!("replay speed must be greater than zero"));
+                }
+                Ok(ReplaySpeed::Factor(factor))
+            }
+        }
+    }
+}
+
+impl ReplaySpeed {
+    /// true if samples are replayed at their recorded pace, or a multiple of it
+    pub fn paced(&self) -> bool {
+        *self != ReplaySpeed::Max
+    }
+
+    /// How long to wait before sending a sample, given when it and the previous sample were collected, in milliseconds since the epoch.
+    /// Samples without timestamps are taken to be `interval` apart.
+    pub fn delay(&self, prev: Option<f64>, current: Option<f64>, interval: Duration) -> Duration {
+        let ReplaySpeed::Factor(factor) = self else {
+            return Duration::ZERO;
+        };
+        let gap = match (prev, current) {
+            // a capture spanning a restart can go back in time, don't wait on it
+            (Some(prev), Some(current)) => Duration::from_secs_f64((current - prev).max(0.0) / 1000.0),
+            _ => interval,
+        };
+
+        gap.div_f64(*factor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ReplaySpeed;
+
+    #[test]
+    fn test_replay_speed() -> anyhow::Result<()> {
+        assert_eq!("max".parse::<ReplaySpeed>()?, ReplaySpeed::Max);
+        assert_eq!("realtime".parse::<ReplaySpeed>()?, ReplaySpeed::Factor(1.0));
+        assert_eq!("10x".parse::<ReplaySpeed>()?, ReplaySpeed::Factor(10.0));
+        assert_eq!("0.5".parse::<ReplaySpeed>()?, ReplaySpeed::Factor(0.5));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+
+        let interval = Duration::from_secs(5);
+        assert_eq!(ReplaySpeed::Max.delay(Some(0.0), Some(10_000.0), interval), Duration::ZERO);
+        assert_eq!(ReplaySpeed::Factor(10.0).delay(Some(0.0), Some(10_000.0), interval), Duration::from_secs(1));
+        assert_eq!(ReplaySpeed::Factor(1.0).delay(None, Some(10_000.0), interval), interval);
+        assert_eq!(ReplaySpeed::Factor(1.0).delay(Some(10_000.0), Some(0.0), interval), Duration::ZERO);
+        Ok(())
+    }
+}
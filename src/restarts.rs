@@ -0,0 +1,59 @@
+/*!
+ * Spots beat restarts between samples. A restarted beat starts every cumulative counter over from zero, so a restart in the
+ * middle of a run shows up as a cliff in the raw counters, and a bogus datapoint in their rates, unless it's pointed out.
+ */
+
+use serde_json::{Map, Value};
+
+use crate::groups::generic::get_root_elem;
+
+/// A new ID every time the beat process starts
+const EPHEMERAL_ID_PATH: &str = "beat.info.ephemeral_id";
+const UPTIME_PATH: &str = "beat.info.uptime.ms";
+
+/// Remembers the ephemeral ID and uptime of the last sample from a beat
+#[derive(Debug, Default)]
+pub struct Restarts {
+    ephemeral_id: Option<String>,
+    uptime: Option<f64>,
+}
+
+impl Restarts {
+    /// Look at a new sample, and return true if the beat restarted since the last one.
+    /// The ephemeral ID is the best evidence, but uptime going backwards also counts, for beats that don't report an ID.
+    pub fn check(&mut self, doc: &Map<String, Value>) -> bool {
+        let ephemeral_id = get_root_elem(doc, EPHEMERAL_ID_PATH).and_then(Value::as_str).map(str::to_string);
+        let uptime = get_root_elem(doc, UPTIME_PATH).and_then(Value::as_f64);
+
+        let new_id = matches!((&self.ephemeral_id, &ephemeral_id), (Some(prev), Some(id)) if prev != id);
+        let uptime_reset = matches!((self.uptime, uptime), (Some(prev), Some(now)) if now < prev);
+
+        // a sample missing either field keeps the last one seen, so a partial document can't hide a restart
+        if ephemeral_id.is_some() {
+            self.ephemeral_id = ephemeral_id;
+        }
+        if uptime.is_some() {
+            self.uptime = uptime;
+        }
+        new_id || uptime_reset
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::Restarts;
+
+    #[test]
+    fn test_restarts() {
+        let doc = |id: &str, uptime: u64| json!({"beat": {"info": {"ephemeral_id": id, "uptime": {"ms": uptime}}}}).as_object().unwrap().clone();
+        let mut restarts = Restarts::default();
+        assert!(!restarts.check(&doc("a", 1000)));
+        assert!(!restarts.check(&doc("a", 2000)));
+        assert!(restarts.check(&doc("b", 3000)));
+        assert!(restarts.check(&doc("b", 100)));
+        assert!(!restarts.check(&json!({}).as_object().unwrap().clone()));
+        assert!(!restarts.check(&doc("b", 200)));
+    }
+}
@@ -0,0 +1,41 @@
+/*!
+ * Helpers for spreading fetches out over time, so beatperf doesn't create synchronized load spikes on the hosts it's measuring.
+ */
+
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, time::Duration};
+
+/// A random delay between zero and `max`.
+/// This doesn't need to be a good source of randomness, so it uses the random keys std seeds its hash maps with.
+pub fn jitter(max: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    let fraction = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+    max.mul_f64(fraction)
+}
+
+/// The start offset of the `idx`th of `count` endpoints polled every `period`, so their fetches take turns instead of all firing at once
+pub fn stagger(idx: usize, count: usize, period: Duration) -> Duration {
+    period.mul_f64(idx as f64 / count.max(1) as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{jitter, stagger};
+
+    #[test]
+    fn test_jitter() {
+        let max = Duration::from_millis(100);
+        assert!((0..100).map(|_| jitter(max)).all(|delay| delay < max));
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_stagger() {
+        let period = Duration::from_secs(6);
+        assert_eq!(stagger(0, 3, period), Duration::ZERO);
+        assert_eq!(stagger(2, 3, period), Duration::from_secs(4));
+        assert_eq!(stagger(0, 0, period), Duration::ZERO);
+    }
+}
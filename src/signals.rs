@@ -0,0 +1,65 @@
+/*!
+ * The signals beatperf acts on. Ctrl-C and SIGTERM, which CI runners and systemd stop processes with, both end the run the same way,
+ * so the final charts and exports are always written. SIGHUP re-renders every chart without stopping, and SIGUSR2 marks the latest
+ * sample of every chart with a numbered line.
+ */
+
+use tokio::{signal, sync::broadcast::Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use beatperf::watchers::Control;
+
+/// Wait for Ctrl-C, or for SIGTERM where there is one
+pub async fn shutdown() {
+    #[cfg(unix)]
+    {
+        let mut term = signal::unix::signal(signal::unix::SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            res = signal::ctrl_c() => res.expect("failed to listen for Ctrl-C"),
+            _ = term.recv() => info!("got SIGTERM"),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await.expect("failed to listen for Ctrl-C");
+}
+
+/// Tell every watcher to render its chart each time the process gets SIGHUP, until the token is cancelled
+pub fn render_on_hangup(control: Sender<Control>, token: CancellationToken) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup()).expect("failed to listen for SIGHUP");
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = hangup.recv() => {
+                    info!("got SIGHUP, rendering every chart");
+                    let _ = control.send(Control::Render);
+                }
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = (control, token);
+}
+
+/// Mark the latest sample of every chart with a numbered line each time the process gets SIGUSR2, until the token is cancelled
+pub fn mark_on_user2(control: Sender<Control>, token: CancellationToken) {
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut user2 = signal::unix::signal(signal::unix::SignalKind::user_defined2()).expect("failed to listen for SIGUSR2");
+        let mut count = 0;
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = user2.recv() => {
+                    count += 1;
+                    info!("got SIGUSR2, marking every chart with 'mark {}'", count);
+                    let _ = control.send(Control::Annotate(format!("mark {}", count)));
+                }
+            }
+        }
+    });
+    #[cfg(not(unix))]
+    let _ = (control, token);
+}
@@ -0,0 +1,95 @@
+/*!
+ * A stop condition for "measure steady state" runs, which ends the capture once the selected metrics have stayed within a
+ * tolerance band for long enough. How long a beat takes to warm up varies, so this is more reliable than a fixed run length.
+ */
+
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::{Map, Value};
+
+use crate::{fetch::TIMESTAMP_PATH, groups::generic::get_root_elem};
+
+/// Watches the selected metrics, and decides when all of them have settled
+pub struct Stability {
+    keys: Vec<String>,
+    /// The allowed spread of a metric's values, as a fraction of their mean
+    tolerance: f64,
+    /// How long every metric has to stay within the band, in milliseconds
+    window_ms: f64,
+    /// The recent `(timestamp_ms, value)` samples of each metric
+    history: HashMap<String, VecDeque<(f64, f64)>>,
+}
+
+impl Stability {
+    /// Create a new stop condition. `tolerance` is a percentage of the mean, and `window_ms` is in milliseconds
+    pub fn new(keys: Vec<String>, tolerance: f64, window_ms: f64) -> Stability {
+        Stability { keys, tolerance: tolerance / 100.0, window_ms, history: HashMap::new() }
+    }
+
+    /// Look at a new sample, and return true if every metric has been stable for the whole window
+    pub fn update(&mut self, doc: &Map<String, Value>) -> bool {
+        let Some(now) = get_root_elem(doc, TIMESTAMP_PATH).and_then(Value::as_f64) else {
+            return false;
+        };
+
+        let mut stable = true;
+        for key in &self.keys {
+            let history = self.history.entry(key.clone()).or_default();
+            match get_root_elem(doc, key).and_then(Value::as_f64) {
+                Some(val) => history.push_back((now, val)),
+                None => {
+                    stable = false;
+                    continue;
+                }
+            }
+            // keep one sample from before the window, so we know the window is fully covered
+            let cutoff = now - self.window_ms;
+            while history.get(1).is_some_and(|(ts, _)| *ts <= cutoff) {
+                history.pop_front();
+            }
+
+            stable &= history.front().is_some_and(|(ts, _)| *ts <= cutoff) && within_band(history, self.tolerance);
+        }
+
+        stable
+    }
+
+    /// The metrics being watched
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+}
+
+/// Check that the spread of the values is within `tolerance` of their mean
+fn within_band(history: &VecDeque<(f64, f64)>, tolerance: f64) -> bool {
+    let values = history.iter().map(|(_, v)| *v);
+    let min = values.clone().fold(f64::INFINITY, f64::min);
+    let max = values.clone().fold(f64::NEG_INFINITY, f64::max);
+    let mean = values.sum::<f64>() / history.len() as f64;
+
+    max - min <= tolerance * mean.abs()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::Stability;
+
+    #[test]
+    fn test_stability() {
+        let doc = |ts: u64, rss: f64| json!({"beat": {"memstats": {"rss": rss}}, "beatperf": {"timestamp_ms": ts}}).as_object().unwrap().clone();
+        let mut stable = Stability::new(vec!["beat.memstats.rss".to_string()], 5.0, 3000.0);
+
+        // still warming up
+        assert!(!stable.update(&doc(0, 100.0)));
+        assert!(!stable.update(&doc(1000, 200.0)));
+        // plateaued, but not for the whole window yet
+        assert!(!stable.update(&doc(2000, 300.0)));
+        assert!(!stable.update(&doc(3000, 302.0)));
+        assert!(!stable.update(&doc(4000, 305.0)));
+        assert!(stable.update(&doc(5000, 301.0)));
+        // a jump starts the window over
+        assert!(!stable.update(&doc(6000, 400.0)));
+    }
+}
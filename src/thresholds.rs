@@ -0,0 +1,175 @@
+/*!
+ * Thresholds on single metrics, like `beat.memstats.rss > 500MB`, checked against every sample.
+ * A breach is logged and shaded on the charts, and makes the run exit with [`BREACH_EXIT_CODE`], so soak tests can fail on it.
+ */
+
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use crate::groups::generic::get_root_elem;
+
+/// The exit code of a run where any threshold was breached
+pub const BREACH_EXIT_CODE: i32 = 3;
+
+/// How a metric is compared to its threshold
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Comparison::Above => ">",
+            Comparison::AtLeast => ">=",
+            Comparison::Below => "<",
+            Comparison::AtMost => "<=",
+        })
+    }
+}
+
+/// A limit on a single metric, parsed from `<metric> <op> <value>[unit]`, with ops `>`, `>=`, `<` and `<=`.
+/// Values can have a byte unit, like `500MB` or `2GiB`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Threshold {
+    /// The dot-notation key of the metric
+    pub key: String,
+    op: Comparison,
+    value: f64,
+    /// The threshold as it was written, used to label it in logs and on the charts
+    pub label: String,
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(['<', '>']).ok_or_else(|| anyhow!("threshold must be formatted as <metric> <op> <value>, with op one of >, >=, < or <="))?;
+        let (key, rest) = s.split_at(split);
+        let (op, value) = match rest.split_at(1) {
+            (">", value) => value.strip_prefix('=').map_or((Comparison::Above, value), |value| (Comparison::AtLeast, value)),
+            (_, value) => value.strip_prefix('=').map_or((Comparison::Below, value), |value| (Comparison::AtMost, value)),
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(anyhow!("threshold '{}' doesn't name a metric", s));
+        }
+
+        let value = parse_value(value.trim()).with_context(|| format!("invalid threshold '{}'", s))?;
+        Ok(Threshold { key: key.to_string(), op, value, label: format!("{} {} {}", key, op, value_label(rest)) })
+    }
+}
+
+impl Threshold {
+    /// Check whether a sample breaches the threshold. Samples without the metric never do.
+    pub fn breached(&self, doc: &Map<String, Value>) -> bool {
+        let Some(val) = get_root_elem(doc, &self.key).and_then(Value::as_f64) else {
+            return false;
+        };
+        match self.op {
+            Comparison::Above => val > self.value,
+            Comparison::AtLeast => val >= self.value,
+            Comparison::Below => val < self.value,
+            Comparison::AtMost => val <= self.value,
+        }
+    }
+}
+
+/// Checks a single endpoint's samples against every threshold, logging when a threshold starts and stops being breached
+#[derive(Debug, Default)]
+pub struct Alerts {
+    thresholds: Vec<Threshold>,
+    /// Whether each threshold was breached by the previous sample
+    active: Vec<bool>,
+}
+
+impl Alerts {
+    /// Track the given thresholds, none of which have been breached yet
+    pub fn new(thresholds: Vec<Threshold>) -> Alerts {
+        let active = vec![false; thresholds.len()];
+        Alerts { thresholds, active }
+    }
+
+    /// Check a sample from `source`, and return the number of thresholds it breaches
+    pub fn check(&mut self, source: &str, doc: &Map<String, Value>) -> u64 {
+        let mut count = 0;
+        for (threshold, active) in self.thresholds.iter().zip(self.active.iter_mut()) {
+            let breached = threshold.breached(doc);
+            if breached && !*active {
+                warn!("threshold {} breached by {}", threshold.label, source);
+            } else if !breached && *active {
+                info!("{} is back within threshold {}", source, threshold.label);
+            }
+            *active = breached;
+            count += breached as u64;
+        }
+
+        count
+    }
+}
+
+/// Parse a threshold's value, along with its unit
+fn parse_value(raw: &str) -> anyhow::Result<f64> {
+    let split = raw.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(raw.len());
+    let (value, unit) = raw.split_at(split);
+    let value: f64 = value.trim().parse().with_context(|| format!("'{}' is not a number", value.trim()))?;
+    let scale = match unit {
+        "" | "B" => 1.0,
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "TiB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        other => return Err(anyhow!("unknown unit '{}', expected B, KB, MB, GB, TB, KiB, MiB, GiB or TiB", other)),
+    };
+
+    Ok(value * scale)
+}
+
+/// The value of a threshold as it was written, without the operator
+fn value_label(rest: &str) -> &str {
+    rest.trim_start_matches(['<', '>', '=']).trim()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{Alerts, Threshold};
+
+    #[test]
+    fn test_threshold() -> anyhow::Result<()> {
+        let doc = |rss: f64| json!({"beat": {"memstats": {"rss": rss}}}).as_object().unwrap().clone();
+
+        let rss: Threshold = "beat.memstats.rss > 500MB".parse()?;
+        assert_eq!(rss.label, "beat.memstats.rss > 500MB");
+        assert!(!rss.breached(&doc(500e6)));
+        assert!(rss.breached(&doc(500e6 + 1.0)));
+
+        let at_most: Threshold = "beat.memstats.rss<=1KiB".parse()?;
+        assert_eq!(at_most.label, "beat.memstats.rss <= 1KiB");
+        assert!(at_most.breached(&doc(1024.0)));
+        assert!(!at_most.breached(&doc(1025.0)));
+        // a missing metric isn't a breach
+        assert!(!at_most.breached(&json!({}).as_object().unwrap().clone()));
+
+        assert!("beat.memstats.rss 500".parse::<Threshold>().is_err());
+        assert!("> 500".parse::<Threshold>().is_err());
+        assert!("beat.memstats.rss > 500 apples".parse::<Threshold>().is_err());
+
+        let mut alerts = Alerts::new(vec![rss, at_most]);
+        assert_eq!(alerts.check("test", &doc(600e6)), 1);
+        assert_eq!(alerts.check("test", &doc(100.0)), 1);
+        assert_eq!(alerts.check("test", &doc(2000.0)), 0);
+        Ok(())
+    }
+}
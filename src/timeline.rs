@@ -0,0 +1,113 @@
+/*!
+ * Labelled moments of a run, like "load generator started", read from a file so they can be put on the charts of a run
+ * that's already been recorded. Each one is drawn on the first sample taken at or after its time.
+ */
+
+use std::{fs, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, Context};
+use chrono::DateTime;
+use serde_json::{Map, Value};
+
+use crate::{fetch::TIMESTAMP_PATH, groups::generic::get_root_elem};
+
+/// A labelled moment of the run
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    /// Milliseconds since the epoch, like the timestamp of a sample
+    pub time_ms: f64,
+    pub label: String,
+}
+
+/// Every annotation in a file, in time order. Each line is a time and a label, like `2024-05-01T12:00:00Z load started`,
+/// where the time is RFC 3339 or seconds since the epoch. Blank lines and lines starting with `#` are skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Annotations(Arc<[Annotation]>);
+
+impl Annotations {
+    /// Read annotations from a file
+    pub fn load(path: &str) -> anyhow::Result<Annotations> {
+        let raw = fs::read_to_string(path).with_context(|| format!("error reading annotations file {}", path))?;
+        parse(&raw).with_context(|| format!("error parsing annotations file {}", path))
+    }
+}
+
+impl FromStr for Annotations {
+    type Err = anyhow::Error;
+
+    /// The path to an annotations file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Annotations::load(s)
+    }
+}
+
+fn parse(raw: &str) -> anyhow::Result<Annotations> {
+    let mut annotations = Vec::new();
+    for (idx, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (time, label) = line.split_once(char::is_whitespace).ok_or_else(|| anyhow!("line {} must be formatted as <time> <label>", idx + 1))?;
+        let time_ms = match DateTime::parse_from_rfc3339(time) {
+            Ok(time) => time.timestamp_millis() as f64,
+            Err(_) => time.parse::<f64>().map(|secs| secs * 1000.0)
+            .map_err(|_| anyhow!("invalid time '{}' on line {}, expected RFC 3339 or seconds since the epoch", time, idx + 1))?,
+        };
+        annotations.push(Annotation { time_ms, label: label.trim().to_string() });
+    }
+    annotations.sort_by(|a, b| a.time_ms.total_cmp(&b.time_ms));
+
+    Ok(Annotations(annotations.into()))
+}
+
+/// Works through a run's annotations as its samples come in
+#[derive(Debug, Default)]
+pub struct Timeline {
+    annotations: Annotations,
+    /// The first annotation that hasn't been reached yet
+    next: usize,
+}
+
+impl Timeline {
+    pub fn new(annotations: Annotations) -> Timeline {
+        Timeline { annotations, next: 0 }
+    }
+
+    /// The annotations that are due at a new sample: every one that hasn't been returned yet, up to the sample's timestamp.
+    /// Samples without a timestamp never bring any.
+    pub fn due(&mut self, doc: &Map<String, Value>) -> &[Annotation] {
+        let Some(now) = get_root_elem(doc, TIMESTAMP_PATH).and_then(Value::as_f64) else {
+            return &[];
+        };
+        let start = self.next;
+        self.next += self.annotations.0[start..].iter().take_while(|annotation| annotation.time_ms <= now).count();
+        &self.annotations.0[start..self.next]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{parse, Timeline};
+
+    #[test]
+    fn test_timeline() -> anyhow::Result<()> {
+        let annotations = parse("# soak test\n1700000060 load stopped\n\n2023-11-14T22:13:20Z  load started\n1700000030.5 config reloaded\n")?;
+        let labels: Vec<&str> = annotations.0.iter().map(|annotation| annotation.label.as_str()).collect();
+        assert_eq!(labels, ["load started", "config reloaded", "load stopped"]);
+        assert_eq!(annotations.0[1].time_ms, 1_700_000_030_500.0);
+        assert!(parse("1700000000").is_err());
+        assert!(parse("yesterday load started").is_err());
+
+        let mut timeline = Timeline::new(annotations);
+        let sample = |ms: u64| json!({"beatperf": {"timestamp_ms": ms}}).as_object().unwrap().clone();
+        assert!(timeline.due(&sample(1_699_999_999_000)).is_empty());
+        assert_eq!(timeline.due(&sample(1_700_000_040_000)).len(), 2);
+        assert!(timeline.due(&json!({}).as_object().unwrap().clone()).is_empty());
+        assert!(timeline.due(&sample(1_700_000_050_000)).is_empty());
+        assert_eq!(timeline.due(&sample(1_700_000_070_000))[0].label, "load stopped");
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+/*!
+ * A live terminal dashboard, for quick triage sessions over SSH without copying chart files around.
+ * Every enabled group gets its own tab, charted from the same samples the watchers see.
+ */
+
+use std::{collections::HashMap, time::Duration};
+
+use beatperf::{groups::{ChartOpts, GroupData}, recorder::Recorder, watchers::Sample};
+use ratatui::{crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers}, layout::{Constraint, Layout, Rect}, style::{Color, Style, Stylize}, symbols::Marker, text::Line, widgets::{Axis, Block, Chart, Dataset, GraphType, Paragraph, Tabs}, DefaultTerminal, Frame};
+use tokio::{sync::{broadcast::{error::RecvError, Receiver}, mpsc}, time};
+use tokio_util::sync::CancellationToken;
+
+/// How often the dashboard is redrawn and checked for key presses
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// The colors series are drawn in, in order
+const SERIES_COLORS: [Color; 6] = [Color::Cyan, Color::Yellow, Color::Green, Color::Magenta, Color::Red, Color::Blue];
+const HELP: &str = " ←/→ or 1-9: switch group   q: quit";
+
+/// A single endpoint's samples, and the groups charting them
+pub struct Source {
+    /// When watching more than one endpoint, the name shown in front of the endpoint's groups
+    pub label: Option<String>,
+    pub recorder: Recorder,
+    pub samples: Receiver<Sample>,
+}
+
+/// Show the dashboard until the token is cancelled or every source stops, cancelling the token if the user quits
+pub async fn run(sources: Vec<Source>, opts: ChartOpts, token: CancellationToken) -> anyhow::Result<()> {
+    let (feed_tx, feed) = mpsc::unbounded_channel();
+    let mut recorders = Vec::new();
+    for (idx, source) in sources.into_iter().enumerate() {
+        tokio::spawn(forward(idx, source.samples, feed_tx.clone()));
+        recorders.push((source.label, source.recorder));
+    }
+    drop(feed_tx);
+
+    let mut terminal = ratatui::init();
+    let res = Dashboard { recorders, opts, selected: 0, groups: Vec::new(), stale: true }.run(&mut terminal, feed, token).await;
+    ratatui::restore();
+    res
+}
+
+/// Pass a source's samples and missed datapoints on to the dashboard, in the order they came in, until the source stops
+async fn forward(idx: usize, mut samples: Receiver<Sample>, feed: mpsc::UnboundedSender<(usize, Sample)>) {
+    loop {
+        let sample = match samples.recv().await {
+            Ok(sample) => sample,
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        if feed.send((idx, sample)).is_err() {
+            break;
+        }
+    }
+}
+
+struct Dashboard {
+    /// The groups of every source, along with the source's label
+    recorders: Vec<(Option<String>, Recorder)>,
+    /// Used to chart counters as rates, like the watchers do
+    opts: ChartOpts,
+    /// The index of the group being shown
+    selected: usize,
+    /// The groups of every source as they were last snapshotted, which the tabs and charts are drawn from
+    groups: Vec<GroupData>,
+    /// true if a sample has come in since the groups were snapshotted
+    stale: bool,
+}
+
+impl Dashboard {
+    async fn run(&mut self, terminal: &mut DefaultTerminal, mut feed: mpsc::UnboundedReceiver<(usize, Sample)>, token: CancellationToken) -> anyhow::Result<()> {
+        let mut redraw = time::interval(REDRAW_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                event = feed.recv() => {
+                    match event {
+                        Some((idx, Sample::Doc(doc))) => self.recorders[idx].1.record(&doc),
+                        Some((idx, Sample::Missed)) => self.recorders[idx].1.skip(),
+                        None => break,
+                    }
+                    self.stale = true;
+                },
+                _ = redraw.tick() => {
+                    // the terminal is in raw mode, so ctrl-c arrives as a key press instead of a signal
+                    while event::poll(Duration::ZERO)? {
+                        if let Event::Key(key) = event::read()? {
+                            if key.kind != KeyEventKind::Press {
+                                continue;
+                            }
+                            match key.code {
+                                KeyCode::Char('q') | KeyCode::Esc => token.cancel(),
+                                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => token.cancel(),
+                                KeyCode::Right | KeyCode::Tab | KeyCode::Char('l') => self.select(self.selected + 1),
+                                KeyCode::Left | KeyCode::BackTab | KeyCode::Char('h') => self.select(self.selected + self.count() - 1),
+                                KeyCode::Char(c @ '1'..='9') => self.select(c as usize - '1' as usize),
+                                _ => {}
+                            }
+                        }
+                    }
+                    // only snapshot the groups when there's something new, as it copies every series
+                    if self.stale {
+                        self.groups = self.snapshot();
+                        self.stale = false;
+                        self.select(self.selected);
+                    }
+                    terminal.draw(|frame| self.draw(frame))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of tabs, from the groups as they were last snapshotted
+    fn count(&self) -> usize {
+        self.groups.len().max(1)
+    }
+
+    fn select(&mut self, idx: usize) {
+        self.selected = idx % self.count();
+    }
+
+    /// Snapshot the groups of every source, naming them after their source
+    fn snapshot(&self) -> Vec<GroupData> {
+        self.recorders.iter().flat_map(|(label, recorder)| {
+            recorder.summary().groups.into_iter().map(move |mut group| {
+                if let Some(label) = label {
+                    group.name = format!("{}/{}", label, group.name);
+                }
+                group
+            })
+        }).collect()
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tabs_area, chart_area, help_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+
+        let titles = self.groups.iter().enumerate().map(|(idx, group)| format!("{} {}", idx + 1, group.name));
+        let tabs = Tabs::new(titles).select(self.selected).block(Block::bordered().title(" beatperf "))
+        .highlight_style(Style::new().fg(Color::Yellow).bold());
+        frame.render_widget(tabs, tabs_area);
+
+        if let Some(group) = self.groups.get(self.selected) {
+            self.draw_group(frame, chart_area, group);
+        }
+        frame.render_widget(Paragraph::new(HELP).dim(), help_area);
+    }
+
+    fn draw_group(&self, frame: &mut Frame, area: Rect, group: &GroupData) {
+        let series: HashMap<String, Vec<(usize, f64)>> = group.series.clone().into_iter().collect();
+        let mut points: Vec<(String, Vec<(f64, f64)>)> = self.opts.prepare(series, &[]).into_iter()
+        .map(|(key, series)| (key, series.into_iter().map(|(x, y)| (x as f64, y)).collect()))
+        .filter(|(_, series): &(String, Vec<(f64, f64)>)| !series.is_empty())
+        .collect();
+        points.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let block = Block::bordered().title(format!(" {} ({} datapoints) ", group.name, group.datapoints));
+        if points.is_empty() {
+            frame.render_widget(Paragraph::new("waiting for samples...").block(block), area);
+            return;
+        }
+
+        let values = points.iter().flat_map(|(_, series)| series.iter().map(|(_, y)| *y));
+        let min = values.clone().fold(f64::INFINITY, f64::min);
+        let max = values.fold(f64::NEG_INFINITY, f64::max);
+        // give flat series some room, so they aren't drawn along the edge of the chart
+        let (min, max) = if max <= min { (min - 1.0, max + 1.0) } else { (min, max) };
+        let x_max = group.datapoints.saturating_sub(1).max(1) as f64;
+
+        let datasets = points.iter().enumerate().map(|(idx, (key, series))| {
+            Dataset::default().name(key.as_str()).marker(Marker::Braille).graph_type(GraphType::Line)
+            .style(Style::new().fg(SERIES_COLORS[idx % SERIES_COLORS.len()])).data(series)
+        }).collect();
+
+        let chart = Chart::new(datasets).block(block)
+        .x_axis(Axis::default().title("Datapoints").bounds([0.0, x_max]).labels(axis_labels(0.0, x_max)))
+        .y_axis(Axis::default().bounds([min, max]).labels(axis_labels(min, max)))
+        .legend_position(Some(ratatui::widgets::LegendPosition::TopLeft))
+        .hidden_legend_constraints((Constraint::Percentage(60), Constraint::Percentage(60)));
+        frame.render_widget(chart, area);
+    }
+}
+
+/// Labels for the bottom, middle and top of an axis
+fn axis_labels(min: f64, max: f64) -> Vec<Line<'static>> {
+    [min, (min + max) / 2.0, max].into_iter().map(|val| Line::from(short_number(val))).collect()
+}
+
+/// Format a number in a few characters, so axis labels don't eat the chart
+fn short_number(val: f64) -> String {
+    let abs = val.abs();
+    if abs >= 1e9 {
+        format!("{:.1}G", val / 1e9)
+    } else if abs >= 1e6 {
+        format!("{:.1}M", val / 1e6)
+    } else if abs >= 1e3 {
+        format!("{:.1}k", val / 1e3)
+    } else if abs >= 10.0 || val == val.trunc() {
+        format!("{:.0}", val)
+    } else {
+        format!("{:.2}", val)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::short_number;
+
+    #[test]
+    fn test_short_number() {
+        assert_eq!(short_number(0.0), "0");
+        assert_eq!(short_number(0.25), "0.25");
+        assert_eq!(short_number(512.4), "512");
+        assert_eq!(short_number(125_000_000.0), "125.0M");
+        assert_eq!(short_number(-2500.0), "-2.5k");
+    }
+}
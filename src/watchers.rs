@@ -1,38 +1,380 @@
+/*!
+ * The task behind each metric group during a run. A watcher feeds the samples broadcast by a [`crate::poller::Poller`] to its group,
+ * re-renders the chart as it goes if asked to, and writes every output format at the end of the run.
+ */
+
+use std::{any::Any, fs, panic::{self, AssertUnwindSafe}, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use chrono::Utc;
+
 use serde_json::{Map, Value};
-use tokio::{sync::broadcast::Sender, task::JoinSet};
-use tracing::{debug, error, info};
+use tokio::{sync::broadcast::{error::RecvError, Sender}, task::JoinSet, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
+
+use crate::{export::{self, OutputFormat}, fetch::TIMESTAMP_PATH, groups::{annotations::RESTART_LABEL, anomaly::Anomaly, generic::get_root_elem, ChartOpts, GroupData, Watcher}};
+
+use crate::{animation::Frames, restarts::Restarts, thresholds::Threshold, timeline::{Annotations, Timeline}};
+
+/// What's broadcast for each datapoint of a run. Gaps travel with the documents, so they land between the right datapoints.
+#[derive(Clone, Debug)]
+pub enum Sample {
+    /// A stats document, shared by every watcher of an endpoint instead of being cloned for each of them
+    Doc(Arc<Map<String, Value>>),
+    /// A sample couldn't be collected, usually because the beat is restarting
+    Missed,
+}
+
+impl Sample {
+    /// Share a stats document with every watcher
+    pub fn doc(doc: Map<String, Value>) -> Sample {
+        Sample::Doc(Arc::new(doc))
+    }
+}
+
+/// Out-of-band commands sent to every running watcher
+#[derive(Clone, Debug)]
+pub enum Control {
+    /// Render the current plot immediately
+    Render,
+    /// Add a metric to any watchers that accept additional metrics
+    AddMetric(String),
+    /// Write a snapshot of every group's output to the given directory
+    Checkpoint(String),
+    /// Mark the latest datapoint with a labelled line
+    Annotate(String),
+}
+
+/// Options that control how a watcher task behaves over the course of a run
+#[derive(Clone, Debug, Default)]
+pub struct WatchOpts {
+    /// Periodically re-render the plot as new data comes in
+    pub realtime: bool,
+    /// If set, capture an animation frame every N samples and write an animated SVG at the end of the run
+    pub animate_every: Option<usize>,
+    /// The formats the group's output is written in
+    pub formats: Vec<OutputFormat>,
+    /// When watching more than one endpoint, the subdirectory this endpoint's files are written to
+    pub subdir: Option<String>,
+    /// Keep the final chart as an SVG document, for the combined report
+    pub keep_chart: bool,
+    /// The directory every file is written to
+    pub output_dir: String,
+    /// Thresholds whose breaches are shaded on the charts
+    pub thresholds: Vec<Threshold>,
+    /// Labelled moments drawn on the charts, at the first sample taken at or after each one
+    pub annotations: Annotations,
+    /// The name the group's files are written under, instead of [`Watcher::fname`]
+    pub file_name: Option<String>,
+    /// Prepended to the name of every file the group writes
+    pub file_prefix: String,
+    /// Chart a sample only this often, for groups that don't need every sample the poller fetches
+    pub every: Option<Duration>,
+    /// How often the poller fetches samples, used to thin them out when they have no timestamps
+    pub poll_interval: Duration,
+}
+
+/// Thins the samples a group sees out to [`WatchOpts::every`]. Samples are kept by their timestamps when they have them,
+/// allowing for a little jitter, and otherwise every so many are kept, going by the poll interval.
+#[derive(Debug, Default)]
+struct Decimator {
+    every: Option<Duration>,
+    poll_interval: Duration,
+    /// The timestamp of the last sample kept, in milliseconds since the epoch
+    last_kept: Option<f64>,
+    /// How many samples have gone by since the last one kept, or `None` until the first one is
+    since_kept: Option<u64>,
+}
+
+impl Decimator {
+    fn new(opts: &WatchOpts) -> Decimator {
+        Decimator { every: opts.every, poll_interval: opts.poll_interval, last_kept: None, since_kept: None }
+    }
+
+    /// true if a sample taken at `time_ms` should be charted. Samples that couldn't be collected have no time
+    fn keep(&mut self, time_ms: Option<f64>) -> bool {
+        let Some(every) = self.every else {
+            return true;
+        };
+        let poll_ms = self.poll_interval.as_secs_f64() * 1000.0;
+        let keep = match (self.since_kept, time_ms, self.last_kept) {
+            (None, _, _) => true,
+            // half a poll interval of slack, so a sample that comes in a little early isn't put off for a whole interval
+            (_, Some(now), Some(last)) => now - last + poll_ms / 2.0 >= every.as_secs_f64() * 1000.0,
+            (Some(since), _, _) => since + 1 >= (every.as_secs_f64() * 1000.0 / poll_ms.max(1.0)).round().max(1.0) as u64,
+        };
+        if keep {
+            self.last_kept = time_ms.or(self.last_kept);
+            self.since_kept = Some(0);
+        } else {
+            self.since_kept = self.since_kept.map(|since| since + 1);
+        }
+        keep
+    }
+}
+
+/// What a watcher task leaves behind at the end of the run
+pub struct Finished {
+    /// Every series the group collected
+    pub data: GroupData,
+    /// The final chart as an SVG document, if [`WatchOpts::keep_chart`] was set and it could be rendered
+    pub chart: Option<String>,
+    /// The outliers found in the group's series, if anomaly detection is on
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl WatchOpts {
+    /// The directory the group's files are written to, under `base`
+    fn dir_in(&self, base: &str) -> String {
+        match &self.subdir {
+            Some(sub) => format!("{}/{}", base, sub),
+            None => base.to_string(),
+        }
+    }
+
+    /// The directory the group's files are written to
+    fn dir(&self) -> String {
+        self.dir_in(&self.output_dir)
+    }
 
-use crate::groups::Watcher;
+    /// The name of the group's files, before the suffix of each kind of file, for a group named `fname`
+    pub fn file_stem(&self, fname: &str) -> String {
+        format!("{}{}", self.file_prefix, self.file_name.as_deref().unwrap_or(fname))
+    }
+}
 
-/// Start a watcher for a single group of metrics
-pub fn run_watch<T: Watcher + Send + 'static>( set: &mut JoinSet<()>, broadcaster: &Sender<Map<String, Value>>, added_metrics: Option<Vec<String>>, realtime: bool) {
+/// Start a watcher for a single group of metrics. When the broadcast channel closes, the task renders its final plot
+/// and returns a snapshot of the group's data.
+pub fn run_watch<T: Watcher + Send + 'static>( set: &mut JoinSet<Finished>, broadcaster: &Sender<Sample>, control: &Sender<Control>, added_metrics: Option<Vec<String>>, opts: ChartOpts, watch_opts: WatchOpts) {
     let mut rx2 = broadcaster.subscribe();
+    let mut control_rx = control.subscribe();
     set.spawn(async move {
-        let mut watch = T::new(added_metrics);
+        // the detector scores the series as they're charted, so it needs the options the group was created with
+        let chart_opts = opts.clone();
+        let mut watch = T::new(added_metrics, opts);
         let mut count = 0;
+        let animation = format!("{}/{}_animated.svg", watch_opts.dir(), watch_opts.file_stem(watch.fname()));
+        let mut frames = Frames::new(format!("{}.frames", animation));
+        let mut restarts = Restarts::default();
+        let mut timeline = Timeline::new(watch_opts.annotations.clone());
+        let mut decimator = Decimator::new(&watch_opts);
         loop {
             tokio::select! {
-                Ok(dat) = rx2.recv() => {
-                    watch.update(&dat);
-                    count+=1;
+                res = rx2.recv() => {
+                    match res {
+                        Ok(Sample::Missed) => {
+                            if decimator.keep(None) {
+                                watch.skip();
+                            }
+                            continue;
+                        }
+                        Ok(Sample::Doc(dat)) if !decimator.keep(get_root_elem(&dat, TIMESTAMP_PATH).and_then(Value::as_f64)) => {
+                            continue;
+                        }
+                        Ok(Sample::Doc(dat)) => {
+                            if let Err(e) = catch(|| { watch.update(&dat); Ok(()) }) {
+                                error!("{} failed to process a sample, charting what it has so far: {}", watch.fname(), e);
+                                break
+                            }
+                            for threshold in watch_opts.thresholds.iter().filter(|threshold| threshold.breached(&dat)) {
+                                watch.mark(&threshold.label);
+                            }
+                            if restarts.check(&dat) {
+                                watch.mark(RESTART_LABEL);
+                            }
+                            for annotation in timeline.due(&dat) {
+                                watch.annotate(&annotation.label);
+                            }
+                            count+=1;
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!("watcher fell behind, skipped {} events", skipped);
+                            continue;
+                        }
+                        Err(RecvError::Closed) => {
+                            break
+                        }
+                    }
                 }
-                else => {
-                    break
+                Ok(cmd) = control_rx.recv() => {
+                    handle_control(&mut watch, cmd, &watch_opts);
+                    continue;
                 }
             }
 
-            if realtime && count % 5 == 0{
+            if watch_opts.realtime && count % 5 == 0{
                 debug!("updating plot...");
-                if let Err(e) = watch.plot() {
+                if let Err(e) = render(&watch, &watch_opts.formats, &watch_opts.dir(), &watch_opts.file_stem(watch.fname())) {
                     error!("error updating plot: {}", e)
                 }
             }
 
+            if let Some(every) = watch_opts.animate_every {
+                if count % every == 0 {
+                    match catch(|| watch.plot_to_string()) {
+                        Ok(frame) => if let Err(e) = frames.push(&frame) {
+                            error!("error saving animation frame: {}", e)
+                        },
+                        Err(e) => error!("error rendering animation frame: {}", e)
+                    }
+                }
+            }
+
         }
 
         info!("rendering final plot");
-        if let Err(e) = watch.plot() {
+        if let Err(e) = render(&watch, &watch_opts.formats, &watch_opts.dir(), &watch_opts.file_stem(watch.fname())) {
             error!("error rendering plot: {}", e)
         }
+
+        if !frames.is_empty() {
+            info!("writing {} animation frames to {}", frames.len(), animation);
+            if let Err(e) = frames.write(&animation) {
+                error!("error writing animation: {}", e)
+            }
+        }
+
+        let mut data = watch.export();
+        if let Some(sub) = &watch_opts.subdir {
+            data.name = format!("{}/{}", sub, data.name);
+        }
+        let anomalies = chart_opts.anomalies.map(|detector| detector.find(&data, &chart_opts)).unwrap_or_default();
+        let chart = if watch_opts.keep_chart {
+            catch(|| watch.plot_to_string()).inspect_err(|e| error!("error rendering chart for the report: {}", e)).ok()
+        } else {
+            None
+        };
+        Finished { data, chart, anomalies }
+    });
+}
+
+/// Run `f`, turning a panic into an error, so a single bad sample or chart can't lose everything the group has collected
+fn catch<R>(f: impl FnOnce() -> anyhow::Result<R>) -> anyhow::Result<R> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| Err(anyhow!("panicked: {}", panic_message(&payload))))
+}
+
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    payload.downcast_ref::<&str>().copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("unknown cause")
+}
+
+/// Write the group's output to a directory, in each of the given formats, with file names starting with `stem`
+fn render<T: Watcher>(watch: &T, formats: &[OutputFormat], dir: &str, stem: &str) -> anyhow::Result<()> {
+    catch(|| render_formats(watch, formats, dir, stem))
+}
+
+fn render_formats<T: Watcher>(watch: &T, formats: &[OutputFormat], dir: &str, stem: &str) -> anyhow::Result<()> {
+    for format in formats {
+        match format {
+            OutputFormat::Svg => watch.plot(&format!("{}/{}_plot.svg", dir, stem))?,
+            OutputFormat::Png => watch.plot_png(&format!("{}/{}_plot.png", dir, stem))?,
+            OutputFormat::Html => export::write_html(&format!("{}/{}_plot.html", dir, stem), &watch.export(), &watch.plot_to_string()?)?,
+            OutputFormat::Csv(layout) => export::write_csv(&format!("{}/{}", dir, layout.file_name(stem)), &watch.export(), *layout)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Act on a single control command
+fn handle_control<T: Watcher>(watch: &mut T, cmd: Control, opts: &WatchOpts) {
+    match cmd {
+        Control::Render => {
+            debug!("rendering plot on request");
+            if let Err(e) = render(watch, &opts.formats, &opts.dir(), &opts.file_stem(watch.fname())) {
+                error!("error rendering plot: {}", e)
+            }
+        }
+        Control::Checkpoint(base) => {
+            let dir = opts.dir_in(&base);
+            if let Err(e) = fs::create_dir_all(&dir).map_err(anyhow::Error::from).and_then(|_| render(watch, &opts.formats, &dir, &opts.file_stem(watch.fname()))) {
+                error!("error writing checkpoint to {}: {}", dir, e)
+            }
+        }
+        Control::AddMetric(key) => {
+            if watch.add_metric(&key) {
+                info!("added metric {} to {}", key, watch.fname());
+            }
+        }
+        Control::Annotate(label) => {
+            debug!("marking {} with '{}'", watch.fname(), label);
+            watch.annotate(&label);
+        }
+    }
+}
+
+/// Every `period`, create a new timestamped directory and tell every watcher to write a snapshot of its output there,
+/// so a long run leaves reviewable artifacts behind even if it's interrupted
+pub fn checkpoint_every(period: Duration, output_dir: String, control: Sender<Control>, token: CancellationToken) {
+    tokio::spawn(async move {
+        let mut interval = time::interval_at(time::Instant::now() + period, period);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            let dir = format!("{}/checkpoint_{}", output_dir, Utc::now().format("%Y%m%dT%H%M%SZ"));
+            if let Err(e) = fs::create_dir_all(&dir) {
+                error!("error creating checkpoint directory {}: {}", dir, e);
+                continue;
+            }
+            info!("writing checkpoint to {}", dir);
+            let _ = control.send(Control::Checkpoint(dir));
+        }
+    });
+}
+
+/// Forward the commands meant for every endpoint to a single endpoint's watchers, until the token is cancelled
+pub fn forward_control(from: &Sender<Control>, to: Sender<Control>, token: CancellationToken) {
+    let mut rx = from.subscribe();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                res = rx.recv() => match res {
+                    Ok(cmd) => {
+                        let _ = to.send(cmd);
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!("control forwarding fell behind, skipped {} commands", skipped),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        }
     });
-} // 75-140
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{catch, Decimator};
+
+    #[test]
+    fn test_catch() {
+        let err = catch::<()>(|| panic!("bad sample")).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: bad sample");
+        let err = catch::<()>(|| panic!("bad sample {}", 2)).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: bad sample 2");
+        assert_eq!(catch(|| Ok(1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_decimator() {
+        let decimator = || Decimator { every: Some(Duration::from_secs(3)), poll_interval: Duration::from_secs(1), ..Default::default() };
+
+        // by timestamp, with a little jitter
+        let mut by_time = decimator();
+        let kept: Vec<bool> = [0.0, 1000.0, 2100.0, 2900.0, 4000.0, 6000.0].into_iter().map(|ms| by_time.keep(Some(ms))).collect();
+        assert_eq!(kept, [true, false, false, true, false, true]);
+
+        // by count, including samples that couldn't be collected
+        let mut by_count = decimator();
+        let kept: Vec<bool> = (0..7).map(|_| by_count.keep(None)).collect();
+        assert_eq!(kept, [true, false, false, true, false, false, true]);
+
+        let mut every = Decimator::default();
+        assert!(every.keep(None) && every.keep(Some(1.0)) && every.keep(Some(1.0)));
+    }
+}